@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE `pending_items`(
+	`id` BIGINT NOT NULL,
+	`source` TEXT NOT NULL,
+	PRIMARY KEY(`id`, `source`)
+);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE `run_markers`(
+	`source` TEXT NOT NULL PRIMARY KEY,
+	`last_run_at` BIGINT NOT NULL
+);
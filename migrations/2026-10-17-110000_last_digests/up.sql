@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE `last_digests`(
+	`digest` TEXT NOT NULL,
+	`position` INTEGER NOT NULL,
+	`id` BIGINT NOT NULL,
+	`news_title` TEXT NOT NULL,
+	`news_url` TEXT NOT NULL,
+	`created_at` BIGINT NOT NULL,
+	`snippet` TEXT,
+	`label` TEXT,
+	`category` TEXT,
+	PRIMARY KEY(`digest`, `position`)
+);
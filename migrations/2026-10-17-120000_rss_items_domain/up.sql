@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE `rss_items` ADD COLUMN `domain` TEXT;
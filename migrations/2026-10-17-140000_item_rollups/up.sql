@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE `item_rollups`(
+	`source` TEXT NOT NULL,
+	`month` TEXT NOT NULL,
+	`count` BIGINT NOT NULL,
+	PRIMARY KEY(`source`, `month`)
+);
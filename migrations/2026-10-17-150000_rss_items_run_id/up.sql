@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE `rss_items` ADD COLUMN `run_id` TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE `sent_urls`(
+	`url` TEXT NOT NULL PRIMARY KEY,
+	`source` TEXT NOT NULL,
+	`sent_at` BIGINT NOT NULL
+);
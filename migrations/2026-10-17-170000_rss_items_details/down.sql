@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE `rss_items` DROP COLUMN `score`;
+ALTER TABLE `rss_items` DROP COLUMN `url`;
+ALTER TABLE `rss_items` DROP COLUMN `title`;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE `rss_items` ADD COLUMN `title` TEXT;
+ALTER TABLE `rss_items` ADD COLUMN `url` TEXT;
+ALTER TABLE `rss_items` ADD COLUMN `score` BIGINT;
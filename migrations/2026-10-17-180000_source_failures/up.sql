@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE `source_failures`(
+	`source` TEXT NOT NULL PRIMARY KEY,
+	`failures` BIGINT NOT NULL,
+	`last_error` TEXT NOT NULL,
+	`failed_at` BIGINT NOT NULL
+);
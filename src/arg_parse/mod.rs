@@ -1,26 +1,80 @@
 use std::io::Error;
 
+use hackernews_rust::RunOptions;
+
 #[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct CmdArgs {
     pub config: String,
     pub reverse: bool,
     pub vacuum: bool,
+    pub max_age: Option<i64>,
     pub feeds_only: Option<bool>,
+    pub remove_source: Option<String>,
+    pub prune_sources: bool,
+    pub no_store: bool,
+    pub preview_email: bool,
+    pub dump_raw: Option<String>,
+    pub test_sender: bool,
+    pub count_only: bool,
+    pub list_filters: bool,
+    pub print_config: bool,
+    pub resend_last: bool,
+    pub test_filter: Option<String>,
+    pub source: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub preview_format: Option<String>,
+    pub output: Option<String>,
+    pub verbose: bool,
+    pub flush_queue: bool,
+    pub weekly: bool,
+    pub reprocess: bool,
+    pub sort_stable: bool,
+    pub dry_run: bool,
+    pub histogram: bool,
 }
 
 impl CmdArgs {
+    #[allow(clippy::too_many_lines)]
     pub fn parse(args: Vec<String>) -> Result<Self, Error> {
-        let mut config = String::from("./config.json");
+        // -c|--config wins over HND_CONFIG, which wins over the default
+        let mut config = std::env::var("HND_CONFIG")
+            .unwrap_or_else(|_| String::from("./config.json"));
         let mut reverse = false;
         let mut vacuum = false;
+        let mut max_age: Option<i64> = None;
         let mut feeds_only = false;
+        let mut remove_source: Option<String> = None;
+        let mut prune_sources = false;
+        let mut no_store = false;
+        let mut preview_email = false;
+        let mut dump_raw: Option<String> = None;
+        let mut test_sender = false;
+        let mut count_only = false;
+        let mut list_filters = false;
+        let mut print_config = false;
+        let mut resend_last = false;
+        let mut test_filter: Option<String> = None;
+        let mut source: Option<String> = None;
+        let mut from: Option<String> = None;
+        let mut to: Option<String> = None;
+        let mut preview_format: Option<String> = None;
+        let mut output: Option<String> = None;
+        let mut verbose = false;
+        let mut flush_queue = false;
+        let mut weekly = false;
+        let mut reprocess = false;
+        let mut sort_stable = false;
+        let mut dry_run = false;
+        let mut histogram = false;
         {
             let mut ap = argparse::ArgumentParser::new();
             ap.set_description("Hackernews CLI");
             ap.refer(&mut config).add_option(
                 &["-c", "--config"],
                 argparse::Store,
-                "Config file path; default is config.json",
+                "Config file path, or - for stdin; default is $HND_CONFIG, then ./config.json",
             );
             ap.refer(&mut reverse).add_option(
                 &["-r", "--reverse"],
@@ -32,13 +86,134 @@ impl CmdArgs {
                 argparse::StoreTrue,
                 "Vacuum the database of older items",
             );
+            ap.refer(&mut max_age).add_option(
+                &["--max-age"],
+                argparse::StoreOption,
+                "Days to keep the items for with --vacuum, instead of purge_after_days",
+            );
+            ap.refer(&mut dry_run).add_option(
+                &["--dry-run"],
+                argparse::StoreTrue,
+                "With --vacuum, only print how many items would be removed",
+            );
+            ap.refer(&mut histogram).add_option(
+                &["--histogram"],
+                argparse::StoreTrue,
+                "With --vacuum, print the item counts per source and age; implies --dry-run",
+            );
             ap.refer(&mut feeds_only).add_option(
                 &["-f", "--feeds-only"],
                 argparse::StoreTrue,
                 "Fetch only RSS feeds",
             );
+            ap.refer(&mut remove_source).add_option(
+                &["--remove-source"],
+                argparse::StoreOption,
+                "Remove the RSS source with the given name from the config file",
+            );
+            ap.refer(&mut prune_sources).add_option(
+                &["--prune-sources"],
+                argparse::StoreTrue,
+                "Remove the RSS sources failing prune_after_failures runs in a row from the config file",
+            );
+            ap.refer(&mut no_store).add_option(
+                &["--no-store"],
+                argparse::StoreTrue,
+                "Send the digest without storing the items, so they come back next run",
+            );
+            ap.refer(&mut preview_email).add_option(
+                &["--preview-email"],
+                argparse::StoreTrue,
+                "Print the raw emails instead of sending them; implies --no-store",
+            );
+            ap.refer(&mut dump_raw).add_option(
+                &["--dump-raw"],
+                argparse::StoreOption,
+                "Write every fetched item to the given NDJSON file, for debugging",
+            );
+            ap.refer(&mut test_sender).add_option(
+                &["--test-sender"],
+                argparse::StoreTrue,
+                "Send a test message with the configured sender, without fetching",
+            );
+            ap.refer(&mut count_only).add_option(
+                &["--count-only"],
+                argparse::StoreTrue,
+                "Only print the number of new items per source; implies --no-store",
+            );
+            ap.refer(&mut list_filters).add_option(
+                &["--list-filters"],
+                argparse::StoreTrue,
+                "Print the compiled filter regexes and the invalid ones, then exit",
+            );
+            ap.refer(&mut print_config).add_option(
+                &["--print-config"],
+                argparse::StoreTrue,
+                "Print the effective config as JSON, with the secrets redacted, then exit",
+            );
+            ap.refer(&mut resend_last).add_option(
+                &["--resend-last"],
+                argparse::StoreTrue,
+                "Resend the last digest of each source, without fetching",
+            );
+            ap.refer(&mut test_filter).add_option(
+                &["--test-filter"],
+                argparse::StoreOption,
+                "Print whether the filters keep an item with the given title, then exit",
+            );
+            ap.refer(&mut source).add_option(
+                &["--source"],
+                argparse::StoreOption,
+                "With --test-filter, the source of the item; default is hackernews",
+            );
+            ap.refer(&mut from).add_option(
+                &["--from"],
+                argparse::StoreOption,
+                "Send the email from the given address instead of the configured one",
+            );
+            ap.refer(&mut to).add_option(
+                &["--to"],
+                argparse::StoreOption,
+                "Send the email to the given address instead of the configured one",
+            );
+            ap.refer(&mut preview_format).add_option(
+                &["--preview-format"],
+                argparse::StoreOption,
+                "Format of the console digest: text (default) or html",
+            );
+            ap.refer(&mut output).add_option(
+                &["--output"],
+                argparse::StoreOption,
+                "Format of the run results: text (default) or json",
+            );
+            ap.refer(&mut verbose).add_option(
+                &["--verbose"],
+                argparse::StoreTrue,
+                "Print the details of the run, like the dropped duplicates",
+            );
+            ap.refer(&mut flush_queue).add_option(
+                &["--flush-queue"],
+                argparse::StoreTrue,
+                "Send the digests kept in the failed queue again, without fetching",
+            );
+            ap.refer(&mut weekly).add_option(
+                &["--weekly"],
+                argparse::StoreTrue,
+                "Send the top scored items stored in the last 7 days, without fetching",
+            );
+            ap.refer(&mut reprocess).add_option(
+                &["--reprocess"],
+                argparse::StoreTrue,
+                "Digest the items kept in raw_cache_dir with the current filters, without fetching or storing",
+            );
+            ap.refer(&mut sort_stable).add_option(
+                &["--sort-stable"],
+                argparse::StoreTrue,
+                "Sort the digests by source and ID, for the same output of the same items",
+            );
 
-            match ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr()) {
+            match ap.parse(args, &mut std::io::stdout(), &mut std::io::stderr())
+            {
                 Ok(()) => {}
                 Err(_) => {
                     return Err(Error::from(std::io::ErrorKind::InvalidInput));
@@ -50,7 +225,62 @@ impl CmdArgs {
             config,
             reverse,
             vacuum,
+            max_age,
             feeds_only: Some(feeds_only),
+            remove_source,
+            prune_sources,
+            no_store,
+            preview_email,
+            dump_raw,
+            test_sender,
+            count_only,
+            list_filters,
+            print_config,
+            resend_last,
+            test_filter,
+            source,
+            from,
+            to,
+            preview_format,
+            output,
+            verbose,
+            flush_queue,
+            weekly,
+            reprocess,
+            sort_stable,
+            dry_run,
+            histogram,
         })
     }
+
+    /// Check if the run results are to be printed as JSON
+    pub fn json_output(&self) -> Result<bool, String> {
+        match self.output.as_deref() {
+            None | Some("text") => Ok(false),
+            Some("json") => Ok(true),
+            Some(output) => {
+                Err(format!("--output must be text or json, got {output}"))
+            }
+        }
+    }
+
+    /// Get the options of the fetch run
+    pub fn run_options(&self) -> RunOptions {
+        RunOptions {
+            reverse: self.reverse,
+            // nothing is delivered in the preview and count-only modes,
+            // so nothing is stored; the reprocessed items are stored already
+            no_store: self.no_store
+                || self.preview_email
+                || self.count_only
+                || self.reprocess,
+            count_only: self.count_only,
+            dump_raw: self.dump_raw.clone(),
+            run_id: RunOptions::new_run_id(),
+            verbose: self.verbose,
+            reprocess: self.reprocess,
+            sort_stable: self.sort_stable,
+            ..Default::default()
+        }
+    }
 }
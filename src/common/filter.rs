@@ -1,33 +1,152 @@
-use crate::{Deserialize, Regex, RegexBuilder};
+use crate::{Deserialize, Regex, RegexBuilder, Serialize};
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct ItemFilter {
-    // pub title: String,
+    /// Category to label the items kept by the filter with, e.g. `Rust`
+    pub title: Option<String>,
     pub value: String,
+    /// `include` (default) keeps the matching items, `exclude` drops them
+    pub mode: Option<String>,
+    /// Names of the sources the filter applies to, e.g. `hackernews` or an
+    /// RSS source's name; absent or empty applies it to all of them
+    pub sources: Option<Vec<String>>,
+    /// What the filter is matched against: `title`, `body` (the Hacker News
+    /// item's text or the feed item's description) or `both`; the config's
+    /// `match_field` if absent
+    pub match_field: Option<String>,
+}
+
+/// A single compiled filter pattern
+pub struct CompiledFilter {
+    pub regex: Regex,
+    /// Drop the matching items instead of keeping them
+    pub exclude: bool,
+    /// Title of the filter the pattern comes from
+    pub category: Option<String>,
+    /// Sources the pattern applies to; empty for all of them
+    pub sources: Vec<String>,
+    /// What the pattern is matched against, if set for the filter
+    pub match_field: Option<String>,
+}
+
+impl CompiledFilter {
+    /// Check if the pattern applies to the items of the source; the names
+    /// are compared case-insensitively
+    #[must_use]
+    pub fn applies_to(&self, source: &str) -> bool {
+        self.sources.is_empty()
+            || self.sources.iter().any(|s| s.eq_ignore_ascii_case(source))
+    }
+
+    /// Check if the pattern matches the item's title, body or either of
+    /// them, by the filter's `match_field` or else `default_field`; the
+    /// title is matched if neither is set. An item without a body never
+    /// matches by the body
+    #[must_use]
+    pub fn is_match(
+        &self,
+        title: &str,
+        body: Option<&str>,
+        default_field: Option<&str>,
+    ) -> bool {
+        let body_matches =
+            || body.is_some_and(|body| self.regex.is_match(body));
+        match self.match_field.as_deref().or(default_field) {
+            Some("body") => body_matches(),
+            Some("both") => self.regex.is_match(title) || body_matches(),
+            _ => self.regex.is_match(title),
+        }
+    }
+}
+
+/// A filter pattern that is not a valid regex
+pub struct FilterError {
+    pub pattern: String,
+    /// Title of the filter the pattern comes from
+    pub category: Option<String>,
+    pub error: regex::Error,
 }
 
 pub struct Filters {}
 
 impl Filters {
+    /// Compile the filters, logging and skipping the invalid patterns
     #[must_use]
-    pub fn compile(filters: &[ItemFilter]) -> Vec<Regex> {
-        let string_filters: Vec<String> = filters
-            .iter()
-            .flat_map(|f| {
-                f.value.split(',').map(std::string::ToString::to_string)
-            })
-            .collect();
+    pub fn compile(filters: &[ItemFilter]) -> Vec<CompiledFilter> {
+        let (compiled, errors) = Self::try_compile(filters);
+        for e in errors {
+            eprintln!("Error creating filter: {}", e.error);
+        }
+        compiled
+    }
 
-        let mut filters: Vec<Regex> = Vec::new();
-        for filter in string_filters {
-            match RegexBuilder::new(&filter.to_lowercase())
-                .case_insensitive(true)
-                .build()
-            {
-                Ok(re) => filters.push(re),
-                Err(e) => eprintln!("Error creating filter: {e}"),
+    /// Compile the filters; each comma-separated part of a filter's value is
+    /// a separate pattern. Return the compiled patterns and the invalid ones
+    #[must_use]
+    pub fn try_compile(
+        filters: &[ItemFilter],
+    ) -> (Vec<CompiledFilter>, Vec<FilterError>) {
+        let mut compiled: Vec<CompiledFilter> = Vec::new();
+        let mut errors: Vec<FilterError> = Vec::new();
+        for f in filters {
+            let exclude = Self::is_exclude(f);
+            for pattern in f.value.split(',') {
+                match RegexBuilder::new(&pattern.to_lowercase())
+                    .case_insensitive(true)
+                    .build()
+                {
+                    Ok(regex) => compiled.push(CompiledFilter {
+                        regex,
+                        exclude,
+                        category: f.title.clone(),
+                        sources: f.sources.clone().unwrap_or_default(),
+                        match_field: f.match_field.clone(),
+                    }),
+                    Err(error) => errors.push(FilterError {
+                        pattern: pattern.to_string(),
+                        category: f.title.clone(),
+                        error,
+                    }),
+                }
             }
         }
-        filters
+        (compiled, errors)
+    }
+
+    /// Check if the filter is an exclude one; unknown modes are rejected by
+    /// the config validation
+    fn is_exclude(filter: &ItemFilter) -> bool {
+        filter.mode.as_deref() == Some("exclude")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filters, ItemFilter};
+
+    #[test]
+    fn test_try_compile() {
+        let filters = vec![
+            ItemFilter {
+                title: Some("Rust".to_string()),
+                value: "rust,cargo(".to_string(),
+                ..Default::default()
+            },
+            ItemFilter {
+                value: "\\bgo\\b".to_string(),
+                mode: Some("exclude".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let (compiled, errors) = Filters::try_compile(&filters);
+        let patterns: Vec<(&str, bool)> = compiled
+            .iter()
+            .map(|f| (f.regex.as_str(), f.exclude))
+            .collect();
+        assert_eq!(patterns, vec![("rust", false), ("\\bgo\\b", true)]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].pattern, "cargo(");
+        assert_eq!(errors[0].category.as_deref(), Some("Rust"));
     }
 }
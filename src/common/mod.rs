@@ -1,37 +1,562 @@
-use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
-use crate::{feeds::prelude::RssFetcher, DigestItem, HNFetcher};
+use crate::{
+    config::AppConfig, feeds::prelude::RssFetcher, title_similarity,
+    CompiledFilter, DigestItem, HNFetcher, Serialize, Storage, Url,
+};
+use futures::{stream, StreamExt};
+use std::io::Write;
 
 mod filter;
 mod repository;
+mod text;
 
 pub enum FetcherType {
     HNFetcher(HNFetcher),
     RssFetcher(RssFetcher),
 }
 
+/// Why a new item was left out of the digest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    Deleted,
+    DisallowedType,
+    Blacklisted,
+    MissingUrl,
+    ShortTitle,
+    BeforeLastRun,
+    FilteredOut,
+    RecentlySent,
+    SeenDomain,
+    /// Another item of the run has the same URL and a higher score
+    Duplicate,
+    /// The item is reliably detected to be in none of the `languages`
+    Language,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            SkipReason::Deleted => "deleted",
+            SkipReason::DisallowedType => "disallowed type",
+            SkipReason::Blacklisted => "blacklisted",
+            SkipReason::MissingUrl => "no URL",
+            SkipReason::ShortTitle => "short title",
+            SkipReason::BeforeLastRun => "before last run",
+            SkipReason::FilteredOut => "filtered out",
+            SkipReason::RecentlySent => "recently sent",
+            SkipReason::SeenDomain => "seen domain",
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::Language => "other language",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// Statistics of a single source's fetch run
+pub struct RunStats {
+    pub source: String,
+    /// Number of new items digested
+    pub fetched: usize,
+    /// Number of new items not digested (blacklisted, filtered out, etc.)
+    pub skipped: usize,
+    /// Number of skipped items per reason
+    pub skipped_reasons: BTreeMap<SkipReason, usize>,
+    /// Why the source could not be fetched, if it failed
+    pub error: Option<String>,
+}
+
+impl RunStats {
+    #[must_use]
+    pub fn new(
+        source: &str,
+        fetched: usize,
+        skip_reasons: &[SkipReason],
+    ) -> Self {
+        let mut skipped_reasons = BTreeMap::new();
+        for reason in skip_reasons {
+            *skipped_reasons.entry(*reason).or_insert(0) += 1;
+        }
+
+        Self {
+            source: source.to_string(),
+            fetched,
+            skipped: skip_reasons.len(),
+            skipped_reasons,
+            error: None,
+        }
+    }
+
+    /// Stats of a source that failed to be fetched, so it doesn't keep the
+    /// stats of the other sources from being reported
+    #[must_use]
+    pub fn failed(source: &str, error: &str) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(source, 0, &[])
+        }
+    }
+
+    /// Build a one-line summary of the runs, e.g. `HackerNews: 12 new,
+    /// 40 skipped (blacklisted: 2, filtered out: 38); Habr: 3 new, 0 skipped;
+    /// total sent: 15`
+    #[must_use]
+    pub fn summary(stats: &[RunStats]) -> String {
+        let total: usize = stats.iter().map(|s| s.fetched).sum();
+        let mut parts: Vec<String> = stats
+            .iter()
+            .map(|s| {
+                if s.error.is_some() {
+                    return format!("{}: failed", s.source);
+                }
+                let mut part = format!(
+                    "{}: {} new, {} skipped",
+                    s.source, s.fetched, s.skipped
+                );
+                if !s.skipped_reasons.is_empty() {
+                    let reasons: Vec<String> = s
+                        .skipped_reasons
+                        .iter()
+                        .map(|(reason, count)| format!("{reason}: {count}"))
+                        .collect();
+                    part.push_str(
+                        format!(" ({})", reasons.join(", ")).as_str(),
+                    );
+                }
+                part
+            })
+            .collect();
+        parts.push(format!("total sent: {total}"));
+
+        parts.join("; ")
+    }
+
+    /// Build the `--count-only` report: a `source: count` line of new items
+    /// per source and the `total: count` line
+    #[must_use]
+    pub fn counts(stats: &[RunStats]) -> String {
+        let total: usize = stats.iter().map(|s| s.fetched).sum();
+        let mut lines: Vec<String> = stats
+            .iter()
+            .map(|s| match s.error {
+                Some(_) => format!("{}: failed", s.source),
+                None => format!("{}: {}", s.source, s.fetched),
+            })
+            .collect();
+        lines.push(format!("total: {total}"));
+
+        lines.join("\n")
+    }
+
+    /// Build the `--output json` report: a single JSON object with the
+    /// counts per source, the total sent, the run duration in seconds and
+    /// the errors of the run
+    #[must_use]
+    pub fn to_json(
+        stats: &[RunStats],
+        duration: std::time::Duration,
+        errors: &[String],
+    ) -> String {
+        let sources: Vec<serde_json::Value> = stats
+            .iter()
+            .map(|s| {
+                let skipped_reasons: serde_json::Map<_, _> = s
+                    .skipped_reasons
+                    .iter()
+                    .map(|(reason, count)| {
+                        (reason.to_string(), (*count).into())
+                    })
+                    .collect();
+                serde_json::json!({
+                    "source": s.source,
+                    "fetched": s.fetched,
+                    "skipped": s.skipped,
+                    "skipped_reasons": skipped_reasons,
+                    "error": s.error,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "sources": sources,
+            "total_sent": stats.iter().map(|s| s.fetched).sum::<usize>(),
+            "duration_secs": duration.as_secs_f64(),
+            "errors": errors,
+        })
+        .to_string()
+    }
+}
+
+/// Options of a fetch run, set from the command line
+#[derive(Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RunOptions {
+    /// Reverse the filters results - exclude instead of include
+    pub reverse: bool,
+    /// Send the digest without storing the fetched items
+    pub no_store: bool,
+    /// Only count the new items; nothing is sent or stored
+    pub count_only: bool,
+    /// Append every fetched item to this NDJSON file before sending
+    pub dump_raw: Option<String>,
+    /// Set on SIGINT/SIGTERM: stop pulling new items, but store and send
+    /// the ones pulled so far
+    pub stopping: Arc<AtomicBool>,
+    /// ID of the run, which the stored items are tagged with
+    pub run_id: String,
+    /// Normalized URLs digested by the sources of the run so far, shared by
+    /// the fetchers for the `run` dedup scope
+    pub run_urls: Arc<Mutex<HashSet<String>>>,
+    /// Print the details of the run, like the dropped duplicates, to stderr
+    pub verbose: bool,
+    /// Digest the Hacker News items kept in `raw_cache_dir` again instead of
+    /// pulling new ones; nothing is fetched or stored
+    pub reprocess: bool,
+    /// Number of the HTTP requests the fetchers of the run made so far, for
+    /// `max_requests_per_run`
+    pub requests: Arc<AtomicUsize>,
+    /// Sort the digests by the source and the item ID before sending, so the
+    /// same items always make the same digest
+    pub sort_stable: bool,
+}
+
+impl RunOptions {
+    /// Generate a run ID from the current time and the process ID, e.g.
+    /// `20261017T093000.123456Z-4242`
+    #[must_use]
+    pub fn new_run_id() -> String {
+        format!(
+            "{}-{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ"),
+            std::process::id()
+        )
+    }
+
+    /// Ask the fetchers to stop pulling new items
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if the run is being stopped
+    #[must_use]
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+
+    /// Count an HTTP request against the run's budget of `max` requests, if
+    /// any. Return false, counting nothing, if the budget is spent: the
+    /// request must not be made then
+    #[must_use]
+    pub fn take_request(&self, max: Option<usize>) -> bool {
+        let Some(max) = max else {
+            return true;
+        };
+        self.requests
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok()
+    }
+}
+
+/// A fetched item as written to the `--dump-raw` file, before any filtering
+#[derive(Serialize)]
+pub struct RawItem {
+    #[serde(flatten)]
+    pub item: DigestItem,
+    pub source: String,
+    /// The item made it to the digest
+    pub kept: bool,
+    pub skip_reason: Option<String>,
+    /// The first filter pattern matching the item by its `match_field`;
+    /// exclude ones go first
+    pub matched_filter: Option<String>,
+}
+
+/// Append the raw items to the NDJSON file, one JSON object per line
+pub fn dump_raw_items(
+    file_name: &str,
+    items: &[RawItem],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lines = String::new();
+    for item in items {
+        lines.push_str(serde_json::to_string(item)?.as_str());
+        lines.push('\n');
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_name)?;
+    file.write_all(lines.as_bytes())?;
+
+    Ok(())
+}
+
+// The fetchers are awaited on the caller's task rather than spawned, so
+// their futures don't need to be `Send`
+#[allow(async_fn_in_trait)]
 pub trait Fetch {
-    async fn run(&mut self, reverse: bool) -> Result<usize, Box<dyn std::error::Error>>;
-    fn get_filters(&self) -> &Vec<Regex>;
-    fn keep_item(&self, title: &str, reverse: bool) -> bool {
+    async fn run(
+        &mut self,
+        options: &RunOptions,
+    ) -> Result<Vec<RunStats>, Box<dyn std::error::Error>>;
+    fn get_filters(&self) -> &Vec<CompiledFilter>;
+    fn get_config(&self) -> &AppConfig;
+
+    /// Check if the item should be kept by its title alone; the filters
+    /// matching the body never match it
+    fn keep_item(&self, title: &str, source: &str, reverse: bool) -> bool {
+        self.keep_item_with_body(title, None, source, reverse)
+    }
+
+    /// Check if the item should be kept. An item matching any exclude filter
+    /// is dropped, even if an include filter matches it too (exclude wins).
+    /// Otherwise, the item is kept if it matches any include filter; `reverse`
+    /// flips that. With only exclude filters, all non-excluded items are
+    /// kept; without any filters, no item is (all of them with `reverse`).
+    /// Only the filters applying to the item's source are checked, each
+    /// against the title, the body or both by its `match_field`.
+    fn keep_item_with_body(
+        &self,
+        title: &str,
+        body: Option<&str>,
+        source: &str,
+        reverse: bool,
+    ) -> bool {
+        let default_field = self.get_config().match_field.as_deref();
+        let is_match =
+            |f: &CompiledFilter| f.is_match(title, body, default_field);
+        let filters: Vec<&CompiledFilter> = self
+            .get_filters()
+            .iter()
+            .filter(|f| f.applies_to(source))
+            .collect();
+        if filters.iter().any(|f| f.exclude && is_match(f)) {
+            return false;
+        }
+
+        let mut include_filters =
+            filters.iter().filter(|f| !f.exclude).peekable();
+        if include_filters.peek().is_none() && !filters.is_empty() {
+            return true;
+        }
+
         let keep: bool = reverse;
-        for filter in self.get_filters() {
-            if filter.is_match(title) {
+        for filter in include_filters {
+            if is_match(filter) {
                 return !reverse;
             }
         }
         keep
     }
+
+    /// Get the category of the first include filter of the source matching
+    /// the title alone
+    fn match_category(
+        &self,
+        title: &str,
+        source: &str,
+        reverse: bool,
+    ) -> Option<String> {
+        self.match_category_with_body(title, None, source, reverse)
+    }
+
+    /// Get the category (the filter title) of the first include filter of
+    /// the source matching the item, by the filter's `match_field`. In
+    /// reverse mode, the kept items match no include filter, so they have
+    /// no category.
+    fn match_category_with_body(
+        &self,
+        title: &str,
+        body: Option<&str>,
+        source: &str,
+        reverse: bool,
+    ) -> Option<String> {
+        if reverse {
+            return None;
+        }
+        let default_field = self.get_config().match_field.as_deref();
+        self.get_filters()
+            .iter()
+            .find(|f| {
+                !f.exclude
+                    && f.applies_to(source)
+                    && f.is_match(title, body, default_field)
+            })
+            .and_then(|f| f.category.clone())
+    }
+
+    /// Get the pattern of the first filter of the source matching the item,
+    /// by the filter's `match_field`; exclude filters are checked first as
+    /// they win over the include ones
+    fn matched_filter(
+        &self,
+        title: &str,
+        body: Option<&str>,
+        source: &str,
+    ) -> Option<String> {
+        let default_field = self.get_config().match_field.as_deref();
+        let filters = self.get_filters();
+        filters
+            .iter()
+            .filter(|f| f.exclude)
+            .chain(filters.iter().filter(|f| !f.exclude))
+            .filter(|f| f.applies_to(source))
+            .find(|f| f.is_match(title, body, default_field))
+            .map(|f| f.regex.as_str().to_string())
+    }
+
+    /// Describe the item for the `--dump-raw` file
+    fn raw_item(
+        &self,
+        item: &DigestItem,
+        source: &str,
+        skip_reason: Option<SkipReason>,
+    ) -> RawItem {
+        RawItem {
+            // The source label would clash with the source name
+            item: DigestItem {
+                source: None,
+                ..item.clone()
+            },
+            source: source.to_string(),
+            kept: skip_reason.is_none(),
+            skip_reason: skip_reason.map(|reason| reason.to_string()),
+            matched_filter: self.matched_filter(
+                &item.news_title,
+                item.body.as_deref(),
+                source,
+            ),
+        }
+    }
+
+    /// Get the label to mark the items of the source with in the digest;
+    /// `None` if the labels are turned off with `source_labels`
+    fn source_label(&self, source: &str) -> Option<String> {
+        if self.get_config().source_labels == Some(false) {
+            return None;
+        }
+        Some(source.to_string())
+    }
+
+    /// Check if the item is in none of the `languages`, by the title and the
+    /// body together. Only the reliable detections count: an item of a text
+    /// too short or too mixed to tell is kept
+    fn is_other_language(&self, title: &str, body: Option<&str>) -> bool {
+        let Some(languages) = self
+            .get_config()
+            .languages
+            .as_ref()
+            .filter(|languages| !languages.is_empty())
+        else {
+            return false;
+        };
+        let text = match body {
+            Some(body) => format!("{title} {body}"),
+            None => title.to_string(),
+        };
+        whatlang::detect(&text).is_some_and(|info| {
+            info.is_reliable()
+                && !languages
+                    .iter()
+                    .any(|lang| lang.eq_ignore_ascii_case(info.lang().code()))
+        })
+    }
+
+    /// Check if the title is shorter than `min_title_len` characters; the
+    /// check is disabled if the option is absent or zero
+    fn is_short_title(&self, title: &str) -> bool {
+        match self.get_config().min_title_len {
+            Some(min_len) if min_len > 0 => {
+                title.trim().chars().count() < min_len
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the title similarity threshold to collapse near-duplicate items
+    /// at; `None` if the title de-duplication is off
+    fn get_title_similarity(&self) -> Option<f64> {
+        self.get_config()
+            .dedup_title_similarity
+            .filter(|threshold| *threshold > 0.0)
+    }
+
+    /// Check if the item was created before the source's last run, so it is
+    /// an old one even if its ID is new; only with `only_since_last_run`.
+    /// Items of unknown creation time (0) are never old.
+    fn is_before_last_run(
+        &self,
+        created_at: i64,
+        last_run: Option<i64>,
+    ) -> bool {
+        self.get_config().only_since_last_run == Some(true)
+            && created_at > 0
+            && last_run.is_some_and(|last_run| created_at < last_run)
+    }
+
+    /// Check if a URL's domain is in the blacklist, or its public suffix
+    /// (e.g. `co.uk`) is in `blacklisted_tlds` or not in `whitelisted_tlds`.
+    /// The exact domains are checked first
+    fn is_blacklisted(&self, url: &str) -> bool {
+        if url.is_empty() {
+            return false;
+        }
+
+        let Some(domain) = Url::parse(url)
+            .ok()
+            .and_then(|parsed_url| parsed_url.domain().map(str::to_string))
+        else {
+            return false;
+        };
+        let config = self.get_config();
+        if config.blacklisted_domains.contains(&domain) {
+            return true;
+        }
+
+        let Some(suffix) = psl::suffix_str(&domain) else {
+            return false;
+        };
+        if let Some(tlds) = &config.blacklisted_tlds {
+            if tlds.iter().any(|tld| is_suffix_of_tld(suffix, tld)) {
+                return true;
+            }
+        }
+        if let Some(tlds) = &config.whitelisted_tlds {
+            return !tlds.iter().any(|tld| is_suffix_of_tld(suffix, tld));
+        }
+
+        false
+    }
+}
+
+/// Check if the public suffix is the TLD of the rule or under it: `uk` or
+/// `.uk` covers both `uk` and `co.uk`, while `co.uk` only covers `co.uk`
+fn is_suffix_of_tld(suffix: &str, tld: &str) -> bool {
+    let tld = tld.trim_start_matches('.').to_lowercase();
+    suffix == tld
+        || suffix
+            .strip_suffix(tld.as_str())
+            .is_some_and(|rest| rest.ends_with('.'))
 }
 
-/// De-duplicate the fetched items and return the unique items. URL is used as the key.
+/// Query parameters that only track where a visitor came from
+const TRACKING_PARAMS: [&str; 6] =
+    ["fbclid", "gclid", "yclid", "mc_cid", "mc_eid", "igshid"];
+
+/// De-duplicate the fetched items and return the unique items. The normalized
+/// URL is used as the key, the items keep their original URLs.
 pub fn deduplicate(items: &Vec<DigestItem>) -> Vec<DigestItem> {
     let mut unique_items: Vec<DigestItem> = Vec::new();
     let mut urls: Vec<String> = Vec::new();
 
     for item in items {
-        if !urls.contains(&item.news_url.clone()) {
-            urls.push(item.news_url.clone());
+        let key = normalize_url(&item.news_url);
+        if !urls.contains(&key) {
+            urls.push(key);
             unique_items.push(item.clone());
         }
     }
@@ -39,6 +564,213 @@ pub fn deduplicate(items: &Vec<DigestItem>) -> Vec<DigestItem> {
     unique_items
 }
 
+/// Get the items `deduplicate` drops, each with the item kept in its place
+pub fn find_duplicates(
+    items: &[DigestItem],
+) -> Vec<(&DigestItem, &DigestItem)> {
+    let mut kept: Vec<(String, &DigestItem)> = Vec::new();
+    let mut duplicates = Vec::new();
+
+    for item in items {
+        let key = normalize_url(&item.news_url);
+        match kept.iter().find(|(url, _)| *url == key) {
+            Some((_, kept_item)) => duplicates.push((*kept_item, item)),
+            None => kept.push((key, item)),
+        }
+    }
+
+    duplicates
+}
+
+/// Print the items `deduplicate` drops from the source's digest, with the
+/// titles kept and dropped, for the `--verbose` runs
+pub fn report_duplicates(source: &str, items: &[DigestItem]) {
+    for (kept, dropped) in find_duplicates(items) {
+        eprintln!(
+            "{source}: duplicate {}: kept {:?}, dropped {:?}",
+            dropped.news_url, kept.news_title, dropped.news_title
+        );
+    }
+}
+
+/// Drop the items which URLs were digested by a source of the run already,
+/// and claim the URLs of the rest, for the `run` dedup scope. The sources
+/// run concurrently, so the first one to digest a URL keeps it. The dropped
+/// items are printed with `verbose`
+pub fn deduplicate_run(
+    source: &str,
+    items: Vec<DigestItem>,
+    options: &RunOptions,
+) -> Vec<DigestItem> {
+    let mut run_urls = options
+        .run_urls
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    items
+        .into_iter()
+        .filter(|item| {
+            let is_new = run_urls.insert(normalize_url(&item.news_url));
+            if !is_new && options.verbose {
+                eprintln!(
+                    "{source}: duplicate {}: dropped {:?}, digested by another source",
+                    item.news_url, item.news_title
+                );
+            }
+            is_new
+        })
+        .collect()
+}
+
+/// Drop the items which titles are at least `threshold` similar (see
+/// `title_similarity`) to the title of an item seen before: either an earlier
+/// item of the list or one of `seen_titles`. The first seen item is kept, and
+/// the titles of the kept items are added to `seen_titles`.
+pub fn deduplicate_titles(
+    items: &[DigestItem],
+    seen_titles: &mut Vec<String>,
+    threshold: f64,
+) -> Vec<DigestItem> {
+    let mut unique_items: Vec<DigestItem> = Vec::new();
+
+    for item in items {
+        let is_duplicate = seen_titles.iter().any(|title| {
+            title_similarity(title, &item.news_title) >= threshold
+        });
+        if !is_duplicate {
+            seen_titles.push(item.news_title.clone());
+            unique_items.push(item.clone());
+        }
+    }
+
+    unique_items
+}
+
+/// Sort the digest items by their source label, then by ID, for a digest
+/// which doesn't depend on the order the items were fetched in
+pub fn sort_stable(items: &mut [DigestItem]) {
+    items.sort_by(|a, b| (&a.source, a.id).cmp(&(&b.source, b.id)));
+}
+
+/// Normalize a URL to be used as a de-duplication key: lowercase the host,
+/// drop the trailing slash and the tracking query parameters (`utm_*`,
+/// `fbclid`, etc.). URLs that cannot be parsed are returned as is.
+pub fn normalize_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        return url.to_string();
+    };
+
+    let query: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(name, _)| {
+            let name = name.to_lowercase();
+            !name.starts_with("utm_")
+                && !TRACKING_PARAMS.contains(&name.as_str())
+        })
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    let path = parsed.path().trim_end_matches('/').to_string();
+    parsed.set_path(&path);
+
+    parsed.to_string().trim_end_matches('/').to_string()
+}
+
+/// Get the lowercase domain of the URL, without the `www.` prefix; `None`
+/// for the URLs that cannot be parsed or have no domain
+pub fn url_domain(url: &str) -> Option<String> {
+    let domain = Url::parse(url).ok()?.domain()?.to_lowercase();
+    Some(domain.strip_prefix("www.").unwrap_or(&domain).to_string())
+}
+
+/// Check if the domain of the URL was seen before: stored with an item of
+/// any source, or met earlier in the run (`run_domains`). A domain not seen
+/// before is added to `run_domains`. URLs without a domain are never seen.
+///
+/// # Errors
+///
+/// Fails if the storage can't be queried
+pub fn is_seen_domain(
+    storage: &mut Storage,
+    url: &str,
+    run_domains: &mut HashSet<String>,
+) -> Result<bool, diesel::result::Error> {
+    let Some(domain) = url_domain(url) else {
+        return Ok(false);
+    };
+    if run_domains.contains(&domain) || storage.is_known_domain(&domain)? {
+        return Ok(true);
+    }
+    run_domains.insert(domain);
+
+    Ok(false)
+}
+
+/// Check if an item with the URL was digested, by any source, since the
+/// given time (see `AppConfig::get_sent_since`). Always false if `since` is
+/// absent.
+///
+/// # Errors
+///
+/// Fails if the storage can't be queried
+pub fn is_recently_sent(
+    storage: &mut Storage,
+    url: &str,
+    since: Option<i64>,
+) -> Result<bool, diesel::result::Error> {
+    match since {
+        Some(since) => storage.is_sent_since(url, since),
+        None => Ok(false),
+    }
+}
+
+/// Permalinks of Hacker News items, which are never checked
+const HN_ITEM_URL: &str = "https://news.ycombinator.com/item";
+
+/// Check the digest items' URLs with HEAD requests, at most `concurrency`
+/// at a time. The items which URLs answer with a 4xx/5xx status are dropped
+/// with `drop_dead`, or marked with `dead_link` otherwise. The servers not
+/// supporting HEAD (405, 501) and the unreachable ones don't make a link dead.
+pub async fn check_links(
+    client: &reqwest::Client,
+    digest: Vec<DigestItem>,
+    concurrency: usize,
+    drop_dead: bool,
+) -> Vec<DigestItem> {
+    let is_dead = |url: String| async move {
+        if url.starts_with(HN_ITEM_URL) {
+            return false;
+        }
+        match client.head(&url).send().await {
+            Ok(response) => {
+                let status = response.status();
+                (status.is_client_error() || status.is_server_error())
+                    && !matches!(status.as_u16(), 405 | 501)
+            }
+            Err(e) => {
+                eprintln!("Could not check the link {url}: {e}");
+                false
+            }
+        }
+    };
+    let dead: Vec<bool> = stream::iter(&digest)
+        .map(|item| is_dead(item.news_url.clone()))
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    digest
+        .into_iter()
+        .zip(dead)
+        .filter(|(_, dead)| !(drop_dead && *dead))
+        .map(|(item, dead_link)| DigestItem { dead_link, ..item })
+        .collect()
+}
+
 /// Check if a URL is missing or empty in the digest item
 pub fn is_missing_url(item_url: &String) -> bool {
     item_url.is_empty() || item_url == "-"
@@ -47,5 +779,6 @@ pub fn is_missing_url(item_url: &String) -> bool {
 pub mod prelude {
     pub use super::filter::*;
     pub use super::repository::*;
-    pub use super::Fetch;
+    pub use super::text::*;
+    pub use super::{Fetch, RunOptions, RunStats, SkipReason};
 }
@@ -1,16 +1,53 @@
-use crate::rss_items;
+// The storage methods only fail with the database errors
+#![allow(clippy::missing_errors_doc)]
+
+use crate::{
+    common::{normalize_url, url_domain},
+    deferred_digests, item_rollups, last_digests, pending_items, rss_items,
+    run_markers, sent_urls, source_failures, Deserialize, Serialize,
+};
+use diesel::migration::MigrationSource;
 use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::sqlite::Sqlite;
 use diesel_migrations::{
     embed_migrations, EmbeddedMigrations, MigrationHarness,
 };
+use std::collections::HashSet;
+
+/// The schema migrations embedded into the app
+const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
-#[derive(Clone)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 /// DB Model: A news item that has been fetched
 pub struct DigestItem {
     pub id: i64,
     pub news_title: String,
     pub news_url: String,
     pub created_at: i64,
+    /// A plain-text excerpt of the item's text, if any
+    pub snippet: Option<String>,
+    /// Name of the source to label the item with in the digest, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Title of the filter that kept the item, if any
+    pub category: Option<String>,
+    /// The item's URL answered with an error status to the link check
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dead_link: bool,
+    /// Plain-text snippets of the item's top comments, for context
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
+    /// Estimated minutes to read the item's text, if it has any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_time: Option<usize>,
+    /// Score of the item on its site, if the site has scores
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<i64>,
+    /// Plain text of the item's body, the Hacker News item's text or the
+    /// feed item's description, for the filters matching it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
 }
 
 #[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
@@ -19,6 +56,147 @@ struct FeedItem {
     pub id: i64,
     pub source: String,
     pub created_at: i64,
+    /// Domain of the item's URL, if it has one
+    pub domain: Option<String>,
+    /// ID of the run that stored the item; empty for the items stored
+    /// before the runs had IDs
+    pub run_id: String,
+    /// Title of the digested item; none for the skipped items and the ones
+    /// stored before the titles were
+    pub title: Option<String>,
+    /// URL of the digested item, like the title
+    pub url: Option<String>,
+    /// Score of the item on its site, if the site has scores
+    pub score: Option<i64>,
+}
+
+#[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = pending_items)]
+/// DB Model: A prefetched item ID that is yet to be pulled
+struct PendingItem {
+    pub id: i64,
+    pub source: String,
+}
+
+#[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = run_markers)]
+/// DB Model: When the source was last fetched
+struct RunMarker {
+    pub source: String,
+    pub last_run_at: i64,
+}
+
+#[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = sent_urls)]
+/// DB Model: The normalized URL of a digested item, and when it was digested
+struct SentUrl {
+    pub url: String,
+    pub source: String,
+    pub sent_at: i64,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = source_failures)]
+/// DB Model: How many runs in a row failed to fetch the source
+pub struct SourceFailure {
+    pub source: String,
+    pub failures: i64,
+    /// Error of the last failed run
+    pub last_error: String,
+    /// When the last failed run was
+    pub failed_at: i64,
+}
+
+#[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = last_digests)]
+/// DB Model: An item of the last digest built for a source
+struct LastDigestItem {
+    pub digest: String,
+    pub position: i32,
+    pub id: i64,
+    pub news_title: String,
+    pub news_url: String,
+    pub created_at: i64,
+    pub snippet: Option<String>,
+    pub label: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(Clone, Debug, Queryable, Selectable, PartialEq)]
+#[diesel(table_name = item_rollups)]
+/// DB Model: The number of the items of a source created in a month, kept
+/// after the items themselves are rolled up
+pub struct ItemRollup {
+    pub source: String,
+    /// The month as `YYYY-MM`, in UTC
+    pub month: String,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, QueryableByName, PartialEq, Serialize)]
+/// The number of the stored items of a source by their age, for picking
+/// the retention period
+pub struct AgeHistogram {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub source: String,
+    /// Items created in the last 7 days
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[serde(rename = "0-7d")]
+    pub days_0_7: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[serde(rename = "7-30d")]
+    pub days_7_30: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[serde(rename = "30-90d")]
+    pub days_30_90: i64,
+    /// Items created 90 days ago or earlier
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    #[serde(rename = "90d+")]
+    pub days_90_plus: i64,
+}
+
+impl AgeHistogram {
+    /// Format the histograms as a table with a header, the columns aligned
+    #[must_use]
+    pub fn table(rows: &[AgeHistogram]) -> String {
+        let width = rows
+            .iter()
+            .map(|row| row.source.chars().count())
+            .chain(std::iter::once("Source".len()))
+            .max()
+            .unwrap_or_default();
+        let mut lines = vec![format!(
+            "{:<width$} {:>7} {:>7} {:>7} {:>7}",
+            "Source", "0-7d", "7-30d", "30-90d", "90d+"
+        )];
+        lines.extend(rows.iter().map(|row| {
+            format!(
+                "{:<width$} {:>7} {:>7} {:>7} {:>7}",
+                row.source,
+                row.days_0_7,
+                row.days_7_30,
+                row.days_30_90,
+                row.days_90_plus
+            )
+        }));
+
+        lines.join("\n")
+    }
+}
+
+#[derive(Clone, Queryable, Selectable, PartialEq, Insertable)]
+#[diesel(table_name = deferred_digests)]
+/// DB Model: An item of a digest held back for the quiet hours
+struct DeferredDigestItem {
+    pub digest: String,
+    pub position: i32,
+    pub id: i64,
+    pub news_title: String,
+    pub news_url: String,
+    pub created_at: i64,
+    pub snippet: Option<String>,
+    pub label: Option<String>,
+    pub category: Option<String>,
 }
 
 pub struct Storage {
@@ -26,6 +204,12 @@ pub struct Storage {
 }
 
 impl Storage {
+    /// Wrap the connection, bringing the database schema up to date
+    ///
+    /// # Panics
+    ///
+    /// Panics if the migrations fail
+    #[must_use]
     pub fn new(conn: SqliteConnection) -> Self {
         let mut s = Storage { conn };
         s.run_migrations().expect("Error running migrations");
@@ -33,12 +217,56 @@ impl Storage {
         s
     }
 
+    /// Open the `SQLite` database, creating the file if needed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the database can't be opened
+    #[must_use]
     pub fn establish_connection(database_url: &str) -> SqliteConnection {
         SqliteConnection::establish(database_url).unwrap_or_else(|e| {
             panic!("Error connecting to {database_url} with {e}")
         })
     }
 
+    /// Open the `SQLite` database like `establish_connection`, making the
+    /// statements wait up to `busy_timeout` for the locks held by other
+    /// connections instead of failing right away
+    ///
+    /// # Panics
+    ///
+    /// Panics if the database can't be opened
+    #[must_use]
+    pub fn establish_connection_with_timeout(
+        database_url: &str,
+        busy_timeout: std::time::Duration,
+    ) -> SqliteConnection {
+        let mut conn = Self::establish_connection(database_url);
+        diesel::sql_query(format!(
+            "PRAGMA busy_timeout = {}",
+            busy_timeout.as_millis()
+        ))
+        .execute(&mut conn)
+        .unwrap_or_else(|e| {
+            panic!("Error setting the busy timeout of {database_url} with {e}")
+        });
+
+        conn
+    }
+
+    /// Switch the database to the write-ahead log, so the readers don't
+    /// block the writer and the other way around. The mode sticks to the
+    /// database file, which gets the `-wal` and `-shm` files next to it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the journal mode can't be set
+    pub fn enable_wal(conn: &mut SqliteConnection) {
+        diesel::sql_query("PRAGMA journal_mode = WAL")
+            .execute(conn)
+            .unwrap_or_else(|e| panic!("Error enabling WAL with {e}"));
+    }
+
     /// Vacuum the database - remove news items which `created_at` is older than `expire_after_days`
     pub fn vacuum(
         &mut self,
@@ -46,76 +274,1422 @@ impl Storage {
     ) -> Result<usize, diesel::result::Error> {
         use crate::schemas::prelude::rss_items::dsl::*;
 
+        // Zero retention means the items never expire
+        if expire_after_days == 0 {
+            return Ok(0);
+        }
+
         let expire_after =
             chrono::Utc::now().timestamp() - expire_after_days * 24 * 60 * 60;
-        let num_deleted =
+        let num_deleted = with_retries(|| {
             diesel::delete(rss_items.filter(created_at.lt(expire_after)))
-                .execute(&mut self.conn)?;
+                .execute(&mut self.conn)
+        })?;
+        with_retries(|| {
+            diesel::delete(
+                sent_urls::table.filter(sent_urls::sent_at.lt(expire_after)),
+            )
+            .execute(&mut self.conn)
+        })?;
         Ok(num_deleted)
     }
 
+    /// Replace the items older than `rollup_after_days` with their counts per
+    /// source and month, added to the counts rolled up before; return the
+    /// number of items rolled up
+    pub fn rollup(
+        &mut self,
+        rollup_after_days: i64,
+    ) -> Result<usize, DieselError> {
+        use crate::schemas::prelude::rss_items::dsl::{created_at, rss_items};
+
+        let rollup_before =
+            chrono::Utc::now().timestamp() - rollup_after_days * 24 * 60 * 60;
+        with_retries(|| {
+            self.conn.transaction(|conn| {
+                diesel::sql_query(
+                    "INSERT INTO item_rollups (source, month, count)
+                    SELECT source, strftime('%Y-%m', created_at, 'unixepoch'),
+                        COUNT(*)
+                    FROM rss_items WHERE created_at < ?
+                    GROUP BY 1, 2
+                    ON CONFLICT (source, month)
+                    DO UPDATE SET count = count + excluded.count",
+                )
+                .bind::<diesel::sql_types::BigInt, _>(rollup_before)
+                .execute(conn)?;
+                diesel::delete(rss_items.filter(created_at.lt(rollup_before)))
+                    .execute(conn)
+            })
+        })
+    }
+
+    /// Get the rolled up item counts, by source and month
+    pub fn get_rollups(&mut self) -> Result<Vec<ItemRollup>, DieselError> {
+        use crate::schemas::prelude::item_rollups::dsl::{
+            item_rollups, month, source,
+        };
+
+        with_retries(|| {
+            item_rollups
+                .order((source.asc(), month.asc()))
+                .select(ItemRollup::as_select())
+                .load(&mut self.conn)
+        })
+    }
+
+    /// Keep only the newest `max_records` items of each source, by
+    /// `created_at`, deleting the rest; return the number of items deleted
+    pub fn trim_sources(
+        &mut self,
+        max_records: usize,
+    ) -> Result<usize, DieselError> {
+        let max_records = i64::try_from(max_records).unwrap_or(i64::MAX);
+        with_retries(|| {
+            diesel::sql_query(
+                "DELETE FROM rss_items WHERE rowid IN (
+                    SELECT rowid FROM (
+                        SELECT rowid, ROW_NUMBER() OVER (
+                            PARTITION BY source
+                            ORDER BY created_at DESC, id DESC
+                        ) AS newest
+                        FROM rss_items
+                    ) WHERE newest > ?
+                )",
+            )
+            .bind::<diesel::sql_types::BigInt, _>(max_records)
+            .execute(&mut self.conn)
+        })
+    }
+
+    /// Count the items `rollup` would roll up, those older than
+    /// `rollup_after_days`, without touching them
+    pub fn count_older_than(&mut self, days: i64) -> Result<i64, DieselError> {
+        use crate::schemas::prelude::rss_items::dsl::{created_at, rss_items};
+
+        let before = chrono::Utc::now().timestamp() - days * 24 * 60 * 60;
+        with_retries(|| {
+            rss_items
+                .filter(created_at.lt(before))
+                .count()
+                .get_result(&mut self.conn)
+        })
+    }
+
+    /// Count the items `vacuum` and `trim_sources` would delete together,
+    /// without touching them. The items older than `rollup_after_days` are
+    /// left out, as `rollup` takes them first.
+    pub fn count_vacuum(
+        &mut self,
+        expire_after_days: i64,
+        max_records: Option<usize>,
+        rollup_after_days: Option<i64>,
+    ) -> Result<i64, DieselError> {
+        #[derive(QueryableByName)]
+        struct Count {
+            #[diesel(sql_type = diesel::sql_types::BigInt)]
+            count: i64,
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let days_ago = |days: i64| now - days * 24 * 60 * 60;
+        // Zero retention means the items never expire
+        let expire_after = match expire_after_days {
+            0 => i64::MIN,
+            days => days_ago(days),
+        };
+        let kept_after = rollup_after_days.map_or(i64::MIN, days_ago);
+        let max_records = max_records
+            .map_or(i64::MAX, |max| i64::try_from(max).unwrap_or(i64::MAX));
+        with_retries(|| {
+            diesel::sql_query(
+                "SELECT COUNT(*) AS count FROM (
+                    SELECT created_at, ROW_NUMBER() OVER (
+                        PARTITION BY source
+                        ORDER BY created_at DESC, id DESC
+                    ) AS newest
+                    FROM rss_items
+                ) WHERE created_at >= ? AND (created_at < ? OR newest > ?)",
+            )
+            .bind::<diesel::sql_types::BigInt, _>(kept_after)
+            .bind::<diesel::sql_types::BigInt, _>(expire_after)
+            .bind::<diesel::sql_types::BigInt, _>(max_records)
+            .get_result::<Count>(&mut self.conn)
+        })
+        .map(|row| row.count)
+    }
+
+    /// Count the items of each source by their age: 0-7, 7-30, 30-90 and
+    /// 90+ days
+    pub fn age_histogram(&mut self) -> Result<Vec<AgeHistogram>, DieselError> {
+        let now = chrono::Utc::now().timestamp();
+        let days_ago = |days: i64| now - days * 24 * 60 * 60;
+        with_retries(|| {
+            diesel::sql_query(
+                "SELECT source,
+                    SUM(created_at >= ?1) AS days_0_7,
+                    SUM(created_at < ?1 AND created_at >= ?2) AS days_7_30,
+                    SUM(created_at < ?2 AND created_at >= ?3) AS days_30_90,
+                    SUM(created_at < ?3) AS days_90_plus
+                FROM rss_items GROUP BY source ORDER BY source",
+            )
+            .bind::<diesel::sql_types::BigInt, _>(days_ago(7))
+            .bind::<diesel::sql_types::BigInt, _>(days_ago(30))
+            .bind::<diesel::sql_types::BigInt, _>(days_ago(90))
+            .load(&mut self.conn)
+        })
+    }
+
     /// Get IDs of the news items whose IDs are not in the database yet
     pub fn get_ids_to_pull(
         &mut self,
         news_source: &str,
         prefetched_ids: Vec<i64>,
-    ) -> Vec<i64> {
+    ) -> Result<Vec<i64>, DieselError> {
         use crate::schemas::prelude::rss_items::dsl::{id, rss_items, source};
 
-        let existing_ids: Vec<i64> = rss_items
-            .select(id)
-            .filter(source.eq(news_source))
-            .filter(id.eq_any(&prefetched_ids))
-            .load::<i64>(&mut self.conn)
-            .expect("Error loading IDs");
+        // Only the source's IDs among the prefetched ones are loaded, so the
+        // lookup set stays as small as the prefetched list
+        let existing_ids: HashSet<i64> = with_retries(|| {
+            rss_items
+                .select(id)
+                .filter(source.eq(news_source))
+                .filter(id.eq_any(&prefetched_ids))
+                .load::<i64>(&mut self.conn)
+        })?
+        .into_iter()
+        .collect();
 
-        prefetched_ids
+        Ok(prefetched_ids
             .into_iter()
             .filter(|item_id| !existing_ids.contains(item_id))
-            .collect()
+            .collect())
     }
 
-    /// Store the news items in the database. It's the same feed generally,
-    /// so we just give it a source
+    /// Like `get_ids_to_pull`, but across `news_source` and all of its
+    /// `<news_source>:<list>` sources, e.g. `hackernews:ask`, so an item on
+    /// two lists is only pulled once
+    pub fn get_ids_to_pull_any_list(
+        &mut self,
+        news_source: &str,
+        prefetched_ids: Vec<i64>,
+    ) -> Result<Vec<i64>, DieselError> {
+        use crate::schemas::prelude::rss_items::dsl::{id, rss_items, source};
+
+        let lists = format!("{news_source}:%");
+        let existing_ids: HashSet<i64> = with_retries(|| {
+            rss_items
+                .select(id)
+                .filter(source.eq(news_source).or(source.like(&lists)))
+                .filter(id.eq_any(&prefetched_ids))
+                .load::<i64>(&mut self.conn)
+        })?
+        .into_iter()
+        .collect();
+
+        Ok(prefetched_ids
+            .into_iter()
+            .filter(|item_id| !existing_ids.contains(item_id))
+            .collect())
+    }
+
+    /// Store the news items in the database, tagged with the ID of the run.
+    /// It's the same feed generally, so we just give it a source
     pub fn store_news_items(
         &mut self,
         digest: &[DigestItem],
+        run_id: &str,
     ) -> Result<(), diesel::result::Error> {
-        self.store_feed_items("hackernews", digest)
+        self.store_feed_items("hackernews", digest, run_id)
     }
 
     /// Store the RSS items in the database. The `feed_source` is part of the primary key
-    /// so we can store multiple feeds in the same table
+    /// so we can store multiple feeds in the same table. An ID repeated in the
+    /// digest is stored once, so it doesn't fail the whole batch
     pub fn store_feed_items(
         &mut self,
         feed_source: &str,
         digest: &[DigestItem],
+        run_id: &str,
     ) -> Result<(), diesel::result::Error> {
-        use crate::schemas::prelude::rss_items::dsl::*;
+        use crate::schemas::prelude::rss_items::dsl::rss_items;
 
         let current_timestamp = chrono::Utc::now().timestamp();
+        let mut seen_ids = HashSet::new();
         let feed_items: Vec<FeedItem> = digest
             .iter()
+            .filter(|item| seen_ids.insert(item.id))
             .map(|item| FeedItem {
                 id: item.id,
                 source: feed_source.to_string(),
                 created_at: current_timestamp,
+                domain: url_domain(&item.news_url),
+                run_id: run_id.to_string(),
+                title: stored_detail(&item.news_title),
+                url: stored_detail(&item.news_url),
+                score: item.score,
+            })
+            .collect();
+
+        with_retries(|| {
+            diesel::insert_into(rss_items)
+                .values(&feed_items)
+                .execute(&mut self.conn)
+        })?;
+
+        Ok(())
+    }
+
+    /// Remember the normalized URLs of the digested items, so the same URL
+    /// can be recognized in the digests of any source later
+    pub fn store_sent_urls(
+        &mut self,
+        feed_source: &str,
+        digest: &[DigestItem],
+    ) -> Result<(), DieselError> {
+        let current_timestamp = chrono::Utc::now().timestamp();
+        let mut seen_urls = HashSet::new();
+        let urls: Vec<SentUrl> = digest
+            .iter()
+            .map(|item| normalize_url(&item.news_url))
+            .filter(|url| seen_urls.insert(url.clone()))
+            .map(|url| SentUrl {
+                url,
+                source: feed_source.to_string(),
+                sent_at: current_timestamp,
+            })
+            .collect();
+
+        with_retries(|| {
+            diesel::replace_into(sent_urls::table)
+                .values(&urls)
+                .execute(&mut self.conn)
+        })?;
+
+        Ok(())
+    }
+
+    /// Count a failed fetch of the source, on top of the failures of the
+    /// previous runs in a row. Return the number of the failures in a row
+    pub fn record_source_failure(
+        &mut self,
+        feed_source: &str,
+        error: &str,
+    ) -> Result<i64, DieselError> {
+        let failed_at = chrono::Utc::now().timestamp();
+        with_retries(|| {
+            diesel::insert_into(source_failures::table)
+                .values(SourceFailure {
+                    source: feed_source.to_string(),
+                    failures: 1,
+                    last_error: error.to_string(),
+                    failed_at,
+                })
+                .on_conflict(source_failures::source)
+                .do_update()
+                .set((
+                    source_failures::failures.eq(source_failures::failures + 1),
+                    source_failures::last_error.eq(error),
+                    source_failures::failed_at.eq(failed_at),
+                ))
+                .returning(source_failures::failures)
+                .get_result(&mut self.conn)
+        })
+    }
+
+    /// Forget the failures of the source, once it's fetched successfully
+    pub fn reset_source_failures(
+        &mut self,
+        feed_source: &str,
+    ) -> Result<(), DieselError> {
+        with_retries(|| {
+            diesel::delete(
+                source_failures::table
+                    .filter(source_failures::source.eq(feed_source)),
+            )
+            .execute(&mut self.conn)
+        })?;
+
+        Ok(())
+    }
+
+    /// Get the sources which failed at least `min_failures` runs in a row,
+    /// the most failed first
+    pub fn get_failing_sources(
+        &mut self,
+        min_failures: i64,
+    ) -> Result<Vec<SourceFailure>, DieselError> {
+        with_retries(|| {
+            source_failures::table
+                .filter(source_failures::failures.ge(min_failures))
+                .order((
+                    source_failures::failures.desc(),
+                    source_failures::source.asc(),
+                ))
+                .select(SourceFailure::as_select())
+                .load(&mut self.conn)
+        })
+    }
+
+    /// Check if an item with the URL was digested since the given time, by
+    /// the normalized URL
+    pub fn is_sent_since(
+        &mut self,
+        url: &str,
+        since: i64,
+    ) -> Result<bool, DieselError> {
+        let url = normalize_url(url);
+        with_retries(|| {
+            diesel::select(diesel::dsl::exists(
+                sent_urls::table
+                    .filter(sent_urls::url.eq(&url))
+                    .filter(sent_urls::sent_at.ge(since)),
+            ))
+            .get_result(&mut self.conn)
+        })
+    }
+
+    /// Check if any stored item, of any source, has a URL of the domain
+    pub fn is_known_domain(
+        &mut self,
+        item_domain: &str,
+    ) -> Result<bool, DieselError> {
+        use crate::schemas::prelude::rss_items::dsl::{domain, rss_items};
+
+        with_retries(|| {
+            diesel::select(diesel::dsl::exists(
+                rss_items.filter(domain.eq(item_domain)),
+            ))
+            .get_result(&mut self.conn)
+        })
+    }
+
+    /// Get IDs of the prefetched items left unpulled by the previous runs, in
+    /// the order they were set in, i.e. by their top stories rank
+    pub fn get_pending_ids(
+        &mut self,
+        news_source: &str,
+    ) -> Result<Vec<i64>, DieselError> {
+        use crate::schemas::prelude::pending_items::dsl::{
+            id, pending_items, source,
+        };
+
+        with_retries(|| {
+            pending_items
+                .select(id)
+                .filter(source.eq(news_source))
+                // The rows are inserted in the order of the IDs given
+                .order(diesel::dsl::sql::<diesel::sql_types::BigInt>("rowid"))
+                .load::<i64>(&mut self.conn)
+        })
+    }
+
+    /// Replace the pending IDs of the source with the given ones, so the next
+    /// run can continue from where this one stopped
+    pub fn set_pending_ids(
+        &mut self,
+        news_source: &str,
+        ids: &[i64],
+    ) -> Result<(), diesel::result::Error> {
+        use crate::schemas::prelude::pending_items::dsl::{
+            pending_items, source,
+        };
+
+        let items: Vec<PendingItem> = ids
+            .iter()
+            .map(|item_id| PendingItem {
+                id: *item_id,
+                source: news_source.to_string(),
+            })
+            .collect();
+
+        with_retries(|| {
+            self.conn.transaction(|conn| {
+                diesel::delete(pending_items.filter(source.eq(news_source)))
+                    .execute(conn)?;
+                diesel::insert_into(pending_items)
+                    .values(&items)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Get the timestamp of the source's last run, if it ever ran
+    pub fn get_last_run(
+        &mut self,
+        news_source: &str,
+    ) -> Result<Option<i64>, DieselError> {
+        use crate::schemas::prelude::run_markers::dsl::{
+            last_run_at, run_markers, source,
+        };
+
+        with_retries(|| {
+            run_markers
+                .select(last_run_at)
+                .filter(source.eq(news_source))
+                .first::<i64>(&mut self.conn)
+                .optional()
+        })
+    }
+
+    /// Set the timestamp of the source's last run
+    pub fn set_last_run(
+        &mut self,
+        news_source: &str,
+        timestamp: i64,
+    ) -> Result<(), DieselError> {
+        use crate::schemas::prelude::run_markers::dsl::run_markers;
+
+        let marker = RunMarker {
+            source: news_source.to_string(),
+            last_run_at: timestamp,
+        };
+        with_retries(|| {
+            diesel::replace_into(run_markers)
+                .values(&marker)
+                .execute(&mut self.conn)
+        })?;
+
+        Ok(())
+    }
+
+    /// Replace the last digest of the given name (the subject it is sent
+    /// with) with the given items, so it can be resent
+    pub fn set_last_digest(
+        &mut self,
+        digest_name: &str,
+        digest_items: &[DigestItem],
+    ) -> Result<(), DieselError> {
+        use crate::schemas::prelude::last_digests::dsl::{
+            digest, last_digests,
+        };
+
+        let items: Vec<LastDigestItem> = digest_items
+            .iter()
+            .zip(0..)
+            .map(|(item, position)| LastDigestItem {
+                digest: digest_name.to_string(),
+                position,
+                id: item.id,
+                news_title: item.news_title.clone(),
+                news_url: item.news_url.clone(),
+                created_at: item.created_at,
+                snippet: item.snippet.clone(),
+                label: item.source.clone(),
+                category: item.category.clone(),
             })
             .collect();
 
-        diesel::insert_into(rss_items)
-            .values(feed_items)
-            .execute(&mut self.conn)?;
+        with_retries(|| {
+            self.conn.transaction(|conn| {
+                diesel::delete(last_digests.filter(digest.eq(digest_name)))
+                    .execute(conn)?;
+                diesel::insert_into(last_digests)
+                    .values(&items)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Get the last digest of each source, by the digest name, in the
+    /// order of the names
+    pub fn get_last_digests(
+        &mut self,
+    ) -> Result<Vec<(String, Vec<DigestItem>)>, DieselError> {
+        use crate::schemas::prelude::last_digests::dsl::{
+            digest, last_digests, position,
+        };
+
+        let items = with_retries(|| {
+            last_digests
+                .order((digest.asc(), position.asc()))
+                .select(LastDigestItem::as_select())
+                .load(&mut self.conn)
+        })?;
+
+        Ok(group_digests(items.into_iter().map(|item| {
+            let digest_item = DigestItem {
+                id: item.id,
+                news_title: item.news_title,
+                news_url: item.news_url,
+                created_at: item.created_at,
+                snippet: item.snippet,
+                source: item.label,
+                category: item.category,
+                ..Default::default()
+            };
+            (item.digest, digest_item)
+        })))
+    }
+
+    /// Get the top scored items stored since the timestamp, highest score
+    /// first, for a roll-up of the period. Only the items stored with their
+    /// title, URL and score count
+    pub fn get_top_items(
+        &mut self,
+        since: i64,
+        limit: i64,
+    ) -> Result<Vec<DigestItem>, DieselError> {
+        use crate::schemas::prelude::rss_items::dsl::{
+            created_at, rss_items, score, title, url,
+        };
+
+        let items = with_retries(|| {
+            rss_items
+                .filter(created_at.ge(since))
+                .filter(title.is_not_null())
+                .filter(url.is_not_null())
+                .filter(score.is_not_null())
+                .order((score.desc(), created_at.desc()))
+                .limit(limit)
+                .select(FeedItem::as_select())
+                .load(&mut self.conn)
+        })?;
+
+        Ok(items
+            .into_iter()
+            .map(|item| DigestItem {
+                id: item.id,
+                news_title: item.title.unwrap_or_default(),
+                news_url: item.url.unwrap_or_default(),
+                created_at: item.created_at,
+                score: item.score,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    /// Keep the digest of the given name to send it after the quiet hours.
+    /// The items are appended to the ones deferred by the earlier runs
+    pub fn defer_digest(
+        &mut self,
+        digest_name: &str,
+        digest_items: &[DigestItem],
+    ) -> Result<(), DieselError> {
+        use crate::schemas::prelude::deferred_digests::dsl::{
+            deferred_digests, digest, position,
+        };
+
+        with_retries(|| {
+            self.conn.transaction(|conn| {
+                let next_position = deferred_digests
+                    .filter(digest.eq(digest_name))
+                    .select(diesel::dsl::max(position))
+                    .first::<Option<i32>>(conn)?
+                    .map_or(0, |last| last + 1);
+                let items: Vec<DeferredDigestItem> = digest_items
+                    .iter()
+                    .zip(next_position..)
+                    .map(|(item, item_position)| DeferredDigestItem {
+                        digest: digest_name.to_string(),
+                        position: item_position,
+                        id: item.id,
+                        news_title: item.news_title.clone(),
+                        news_url: item.news_url.clone(),
+                        created_at: item.created_at,
+                        snippet: item.snippet.clone(),
+                        label: item.source.clone(),
+                        category: item.category.clone(),
+                    })
+                    .collect();
+                diesel::insert_into(deferred_digests)
+                    .values(&items)
+                    .execute(conn)?;
+                Ok(())
+            })
+        })
+    }
+
+    /// Get the deferred digests, by the digest name, in the order of the
+    /// names
+    pub fn get_deferred_digests(
+        &mut self,
+    ) -> Result<Vec<(String, Vec<DigestItem>)>, DieselError> {
+        use crate::schemas::prelude::deferred_digests::dsl::{
+            deferred_digests, digest, position,
+        };
+
+        let items = with_retries(|| {
+            deferred_digests
+                .order((digest.asc(), position.asc()))
+                .select(DeferredDigestItem::as_select())
+                .load(&mut self.conn)
+        })?;
+
+        Ok(group_digests(items.into_iter().map(|item| {
+            let digest_item = DigestItem {
+                id: item.id,
+                news_title: item.news_title,
+                news_url: item.news_url,
+                created_at: item.created_at,
+                snippet: item.snippet,
+                source: item.label,
+                category: item.category,
+                ..Default::default()
+            };
+            (item.digest, digest_item)
+        })))
+    }
+
+    /// Forget the deferred digest of the given name once it is sent
+    pub fn clear_deferred_digest(
+        &mut self,
+        digest_name: &str,
+    ) -> Result<(), DieselError> {
+        use crate::schemas::prelude::deferred_digests::dsl::{
+            deferred_digests, digest,
+        };
+
+        with_retries(|| {
+            diesel::delete(deferred_digests.filter(digest.eq(digest_name)))
+                .execute(&mut self.conn)
+        })?;
 
         Ok(())
     }
 
+    /// Bring the database schema up to date. A database migrated by a newer
+    /// version of the app is rejected rather than misread
     fn run_migrations(
         &mut self,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-        pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+        let known: HashSet<String> =
+            MigrationSource::<Sqlite>::migrations(&SQLITE_MIGRATIONS)?
+                .iter()
+                .map(|migration| migration.name().version().to_string())
+                .collect();
+        let applied = self.conn.applied_migrations()?;
+        let unknown: Vec<String> = applied
+            .iter()
+            .map(ToString::to_string)
+            .filter(|version| !known.contains(version))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!(
+                "The database schema is newer than this version knows \
+                 (unknown migrations: {}); upgrade the app or use another db_file",
+                unknown.join(", ")
+            )
+            .into());
+        }
+
+        let pending = self.conn.pending_migrations(SQLITE_MIGRATIONS)?;
+        if !applied.is_empty() && !pending.is_empty() {
+            eprintln!(
+                "Upgrading the database schema: {} migrations to apply",
+                pending.len()
+            );
+        }
         self.conn.run_pending_migrations(SQLITE_MIGRATIONS)?;
 
         Ok(())
     }
 }
+
+/// Keep the item's title or URL to store, unless it's the placeholder of a
+/// skipped item
+fn stored_detail(value: &str) -> Option<String> {
+    (value != "-").then(|| value.to_string())
+}
+
+/// Collect the items, sorted by the digest name, into the digests
+fn group_digests(
+    items: impl Iterator<Item = (String, DigestItem)>,
+) -> Vec<(String, Vec<DigestItem>)> {
+    let mut digests: Vec<(String, Vec<DigestItem>)> = Vec::new();
+    for (digest_name, item) in items {
+        match digests.last_mut() {
+            Some((name, items)) if *name == digest_name => items.push(item),
+            _ => digests.push((digest_name, vec![item])),
+        }
+    }
+
+    digests
+}
+
+/// Number of times a query is retried if the database is busy
+const MAX_RETRIES: u32 = 3;
+
+/// Run the query, retrying it with an exponential backoff (50ms, 100ms, ...)
+/// while the database is locked by another connection. Other errors are
+/// returned right away.
+fn with_retries<T>(
+    mut query: impl FnMut() -> Result<T, DieselError>,
+) -> Result<T, DieselError> {
+    let mut attempt = 0;
+    loop {
+        match query() {
+            Err(e) if attempt < MAX_RETRIES && is_transient(&e) => {
+                eprintln!("Retrying the query: {e}");
+                std::thread::sleep(std::time::Duration::from_millis(
+                    50 << attempt,
+                ));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Check if the error is a transient one, i.e. the database is locked
+fn is_transient(error: &DieselError) -> bool {
+    match error {
+        DieselError::DatabaseError(_, info) => {
+            info.message().contains("database is locked")
+                || info.message().contains("database table is locked")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        with_retries, AgeHistogram, DieselError, FeedItem, ItemRollup, SentUrl,
+        Storage, MAX_RETRIES,
+    };
+    use crate::{rss_items, sent_urls, AppConfig, DigestItem};
+    use diesel::prelude::*;
+    use diesel::result::DatabaseErrorKind;
+
+    fn item(id: i64) -> DigestItem {
+        DigestItem {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// An item with a title and a URL of its own
+    fn item_with_url(id: i64) -> DigestItem {
+        DigestItem {
+            news_title: format!("Item #{id}"),
+            news_url: format!("https://example.com/{id}"),
+            ..item(id)
+        }
+    }
+
+    #[test]
+    fn test_vacuum_zero_retention() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        // An item stored long ago, well past any retention period
+        diesel::insert_into(rss_items::table)
+            .values(FeedItem {
+                id: 1,
+                source: "hackernews".to_string(),
+                created_at: 1_000_000_000,
+                domain: None,
+                run_id: String::new(),
+                title: None,
+                url: None,
+                score: None,
+            })
+            .execute(&mut storage.conn)
+            .unwrap();
+
+        assert_eq!(storage.vacuum(0).unwrap(), 0, "Zero retention purged");
+        assert!(
+            storage
+                .get_ids_to_pull("hackernews", vec![1])
+                .unwrap()
+                .is_empty(),
+            "Zero retention must keep the items",
+        );
+        assert_eq!(storage.vacuum(30).unwrap(), 1, "Expired item kept");
+    }
+
+    #[test]
+    fn test_trim_sources() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let now = chrono::Utc::now().timestamp();
+        let items: Vec<FeedItem> = (1..=5)
+            .map(|id| FeedItem {
+                id,
+                source: "Habr".to_string(),
+                created_at: now - id * 60,
+                domain: None,
+                run_id: String::new(),
+                title: None,
+                url: None,
+                score: None,
+            })
+            .chain(std::iter::once(FeedItem {
+                id: 1,
+                source: "hackernews".to_string(),
+                created_at: now - 100 * 24 * 60 * 60,
+                domain: None,
+                run_id: String::new(),
+                title: None,
+                url: None,
+                score: None,
+            }))
+            .collect();
+        diesel::insert_into(rss_items::table)
+            .values(&items)
+            .execute(&mut storage.conn)
+            .unwrap();
+
+        assert_eq!(storage.trim_sources(2).unwrap(), 3, "Wrong items trimmed");
+        assert_eq!(
+            storage
+                .get_ids_to_pull("Habr", vec![1, 2, 3, 4, 5])
+                .unwrap(),
+            vec![3, 4, 5],
+            "The newest items of each source must be kept",
+        );
+        assert_eq!(
+            storage.vacuum(30).unwrap(),
+            1,
+            "The age purge must still apply to the trimmed sources",
+        );
+    }
+
+    #[test]
+    fn test_vacuum_dry_run() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let now = chrono::Utc::now().timestamp();
+        let items: Vec<FeedItem> = [1, 10, 40, 100, 200]
+            .into_iter()
+            .map(|days| FeedItem {
+                id: days,
+                source: "Habr".to_string(),
+                created_at: now - days * 24 * 60 * 60,
+                domain: None,
+                run_id: String::new(),
+                title: None,
+                url: None,
+                score: None,
+            })
+            .chain(std::iter::once(FeedItem {
+                id: 1,
+                source: "hackernews".to_string(),
+                created_at: now,
+                domain: None,
+                run_id: String::new(),
+                title: None,
+                url: None,
+                score: None,
+            }))
+            .collect();
+        diesel::insert_into(rss_items::table)
+            .values(&items)
+            .execute(&mut storage.conn)
+            .unwrap();
+
+        let histogram = storage.age_histogram().unwrap();
+        assert_eq!(
+            AgeHistogram::table(&histogram),
+            "Source        0-7d   7-30d  30-90d    90d+\n\
+             Habr             1       1       1       2\n\
+             hackernews       1       0       0       0",
+        );
+        assert_eq!(storage.count_older_than(150).unwrap(), 1);
+        assert_eq!(storage.count_vacuum(30, None, None).unwrap(), 3);
+        assert_eq!(
+            storage.count_vacuum(0, Some(2), Some(150)).unwrap(),
+            2,
+            "The rolled up items must not be counted again",
+        );
+        assert_eq!(storage.count_vacuum(30, Some(2), None).unwrap(), 3);
+        assert_eq!(
+            storage.age_histogram().unwrap(),
+            histogram,
+            "The counts must not change the items",
+        );
+        assert_eq!(storage.vacuum(30).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_rollup() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let item = |id: i64, source: &str, date: &str| FeedItem {
+            id,
+            source: source.to_string(),
+            created_at: chrono::DateTime::parse_from_rfc3339(date)
+                .unwrap()
+                .timestamp(),
+            domain: None,
+            run_id: String::new(),
+            title: None,
+            url: None,
+            score: None,
+        };
+        let insert = |storage: &mut Storage, items: &[FeedItem]| {
+            diesel::insert_into(rss_items::table)
+                .values(items)
+                .execute(&mut storage.conn)
+                .unwrap();
+        };
+        let rollup = |source: &str, month: &str, count: i64| ItemRollup {
+            source: source.to_string(),
+            month: month.to_string(),
+            count,
+        };
+
+        insert(
+            &mut storage,
+            &[
+                item(1, "Habr", "2024-01-05T10:00:00Z"),
+                item(2, "Habr", "2024-01-25T10:00:00Z"),
+                item(3, "Habr", "2024-02-01T10:00:00Z"),
+                item(1, "hackernews", "2024-01-10T10:00:00Z"),
+                item(4, "Habr", &chrono::Utc::now().to_rfc3339()),
+            ],
+        );
+        assert_eq!(storage.rollup(30).unwrap(), 4, "Wrong items rolled up");
+        assert_eq!(
+            storage.get_ids_to_pull("Habr", vec![1, 2, 3, 4]).unwrap(),
+            vec![1, 2, 3],
+            "Only the recent items must be kept",
+        );
+
+        // A later rollup adds to the counts of the same month
+        insert(&mut storage, &[item(5, "Habr", "2024-01-31T23:00:00Z")]);
+        assert_eq!(storage.rollup(30).unwrap(), 1);
+        assert_eq!(
+            storage.get_rollups().unwrap(),
+            vec![
+                rollup("Habr", "2024-01", 3),
+                rollup("Habr", "2024-02", 1),
+                rollup("hackernews", "2024-01", 1),
+            ],
+            "Wrong rollups",
+        );
+    }
+
+    #[test]
+    /// The pending IDs keep their rank order, not the numeric one
+    fn test_pending_ids_order() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        storage
+            .set_pending_ids("hackernews", &[30, 10, 20])
+            .unwrap();
+        storage.set_pending_ids("Habr", &[5]).unwrap();
+        assert_eq!(
+            storage.get_pending_ids("hackernews").unwrap(),
+            vec![30, 10, 20],
+        );
+
+        storage.set_pending_ids("hackernews", &[40, 10]).unwrap();
+        assert_eq!(
+            storage.get_pending_ids("hackernews").unwrap(),
+            vec![40, 10]
+        );
+    }
+
+    #[test]
+    fn test_store_duplicate_ids() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        storage
+            .store_feed_items("Habr", &[item(1), item(2), item(1)], "run")
+            .expect("Duplicate IDs must not fail the insert");
+        let count: i64 = rss_items::table
+            .filter(rss_items::source.eq("Habr"))
+            .count()
+            .get_result(&mut storage.conn)
+            .unwrap();
+        assert_eq!(count, 2, "Each ID must be stored once");
+    }
+
+    #[test]
+    fn test_run_ids() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        // An item stored before the runs had IDs
+        diesel::sql_query(
+            "INSERT INTO rss_items (id, source, created_at) VALUES (1, 'Habr', 0)",
+        )
+        .execute(&mut storage.conn)
+        .unwrap();
+        storage
+            .store_feed_items("Habr", &[item(2)], "first")
+            .unwrap();
+        storage.store_news_items(&[item(3)], "second").unwrap();
+
+        let run_ids: Vec<(i64, String)> = rss_items::table
+            .order(rss_items::id.asc())
+            .select(FeedItem::as_select())
+            .load(&mut storage.conn)
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.id, item.run_id))
+            .collect();
+        assert_eq!(
+            run_ids,
+            vec![
+                (1, String::new()),
+                (2, "first".to_string()),
+                (3, "second".to_string()),
+            ],
+            "The items must be tagged with the run that stored them",
+        );
+    }
+
+    #[test]
+    fn test_top_items() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let item = |id, score| DigestItem {
+            score,
+            ..item_with_url(id)
+        };
+
+        storage
+            .store_news_items(
+                &[
+                    item(1, Some(10)),
+                    item(2, Some(300)),
+                    item(3, Some(50)),
+                    crate::skipped_item(4, 0),
+                ],
+                "",
+            )
+            .unwrap();
+        storage
+            .store_feed_items("Habr", &[item(5, None)], "")
+            .unwrap();
+        // An item stored before the last week
+        diesel::sql_query(
+            "INSERT INTO rss_items (id, source, created_at, title, url, score) \
+             VALUES (6, 'hackernews', 0, 'Old', 'https://example.com/6', 900)",
+        )
+        .execute(&mut storage.conn)
+        .unwrap();
+
+        let since = chrono::Utc::now().timestamp() - 7 * 86_400;
+        let top: Vec<(i64, String, Option<i64>)> = storage
+            .get_top_items(since, 2)
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.id, item.news_title, item.score))
+            .collect();
+        assert_eq!(
+            top,
+            vec![
+                (2, "Item #2".to_string(), Some(300)),
+                (3, "Item #3".to_string(), Some(50)),
+            ],
+            "The top scored items of the period must be returned",
+        );
+    }
+
+    #[test]
+    /// With `resurface_after_days`, a persistently deduplicated URL is new
+    /// again once it wasn't digested for that many days
+    fn test_resurface_after_days() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let now = chrono::Utc::now().timestamp();
+        let days = 24 * 60 * 60;
+        for (url, sent_at) in [
+            ("https://example.com/under", now - 30 * days + 3600),
+            ("https://example.com/over", now - 30 * days - 3600),
+        ] {
+            diesel::insert_into(sent_urls::table)
+                .values(SentUrl {
+                    url: url.to_string(),
+                    source: "hackernews".to_string(),
+                    sent_at,
+                })
+                .execute(&mut storage.conn)
+                .unwrap();
+        }
+        let mut is_sent = |config: &AppConfig, url: &str| {
+            let since = config.get_sent_since().unwrap();
+            storage.is_sent_since(url, since).unwrap()
+        };
+
+        let config = AppConfig {
+            dedup_scope: Some("persistent".to_string()),
+            resurface_after_days: Some(30),
+            ..Default::default()
+        };
+        assert!(
+            is_sent(&config, "https://example.com/under"),
+            "A URL digested just under the days ago must still be skipped",
+        );
+        assert!(
+            !is_sent(&config, "https://example.com/over"),
+            "A URL digested just over the days ago must resurface",
+        );
+
+        let config = AppConfig {
+            resurface_after_days: None,
+            ..config
+        };
+        assert!(
+            is_sent(&config, "https://example.com/over"),
+            "Without the days, a URL must never resurface",
+        );
+    }
+
+    #[test]
+    fn test_source_failures() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+
+        assert_eq!(storage.record_source_failure("Habr", "404").unwrap(), 1);
+        assert_eq!(storage.record_source_failure("Habr", "500").unwrap(), 2);
+        storage
+            .record_source_failure("Lobsters", "timeout")
+            .unwrap();
+
+        let failing = storage.get_failing_sources(2).unwrap();
+        assert_eq!(failing.len(), 1, "Only Habr failed twice in a row");
+        assert_eq!(failing[0].source, "Habr");
+        assert_eq!(failing[0].failures, 2);
+        assert_eq!(failing[0].last_error, "500");
+
+        storage.reset_source_failures("Habr").unwrap();
+        assert!(
+            storage.get_failing_sources(1).unwrap()[0].source == "Lobsters",
+            "A success must reset the failures of the source only",
+        );
+        assert_eq!(storage.record_source_failure("Habr", "404").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_last_digests() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let item = |id| DigestItem {
+            category: Some("Rust".to_string()),
+            ..item_with_url(id)
+        };
+
+        storage.set_last_digest("HackerNews", &[item(1)]).unwrap();
+        storage
+            .set_last_digest("HackerNews", &[item(3), item(2)])
+            .unwrap();
+        storage.set_last_digest("Habr", &[item(4)]).unwrap();
+
+        let digests: Vec<(String, Vec<i64>)> = storage
+            .get_last_digests()
+            .unwrap()
+            .into_iter()
+            .map(|(name, items)| {
+                (name, items.iter().map(|item| item.id).collect())
+            })
+            .collect();
+        assert_eq!(
+            digests,
+            vec![
+                ("Habr".to_string(), vec![4]),
+                ("HackerNews".to_string(), vec![3, 2]),
+            ],
+            "Only the last digest of each source must be kept, in order",
+        );
+    }
+
+    #[test]
+    fn test_deferred_digests() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+
+        storage
+            .defer_digest("HackerNews", &[item_with_url(1), item_with_url(2)])
+            .unwrap();
+        storage.defer_digest("Habr", &[item_with_url(3)]).unwrap();
+        storage
+            .defer_digest("HackerNews", &[item_with_url(4)])
+            .unwrap();
+
+        let ids = |storage: &mut Storage| -> Vec<(String, Vec<i64>)> {
+            storage
+                .get_deferred_digests()
+                .unwrap()
+                .into_iter()
+                .map(|(name, items)| {
+                    (name, items.iter().map(|item| item.id).collect())
+                })
+                .collect()
+        };
+        assert_eq!(
+            ids(&mut storage),
+            vec![
+                ("Habr".to_string(), vec![3]),
+                ("HackerNews".to_string(), vec![1, 2, 4]),
+            ],
+            "The deferred items must accumulate in order",
+        );
+
+        storage.clear_deferred_digest("HackerNews").unwrap();
+        assert_eq!(ids(&mut storage), vec![("Habr".to_string(), vec![3])]);
+    }
+
+    #[test]
+    fn test_newer_schema() {
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        assert!(storage.run_migrations().is_ok(), "Up to date schema failed");
+
+        // A migration applied by a newer version of the app
+        diesel::sql_query(
+            "INSERT INTO __diesel_schema_migrations (version) \
+             VALUES ('99991231000000')",
+        )
+        .execute(&mut storage.conn)
+        .unwrap();
+        let error = storage.run_migrations().unwrap_err().to_string();
+        assert!(error.contains("99991231000000"), "{error}");
+    }
+
+    #[test]
+    /// The fetchers open their own connections to the same database, and
+    /// the writes of each are seen by the others, none overwritten
+    fn test_connections_share_writes() {
+        let db_file = std::env::temp_dir()
+            .join(format!("hnd-shared-{}.sqlite3", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let mut hn_storage =
+            Storage::new(Storage::establish_connection(&db_file));
+        let mut feeds_storage =
+            Storage::new(Storage::establish_connection(&db_file));
+        hn_storage
+            .store_news_items(&[item_with_url(1)], "run")
+            .unwrap();
+        feeds_storage
+            .store_feed_items("Habr", &[item_with_url(2)], "run")
+            .unwrap();
+        hn_storage
+            .store_news_items(&[item_with_url(3)], "run")
+            .unwrap();
+
+        let hn_ids = feeds_storage.get_ids_to_pull("hackernews", vec![1, 3, 4]);
+        let feed_ids = hn_storage.get_ids_to_pull("Habr", vec![2, 4]);
+        drop((hn_storage, feeds_storage));
+        std::fs::remove_file(&db_file).unwrap();
+        assert_eq!(hn_ids.unwrap(), vec![4], "HN items must be shared");
+        assert_eq!(feed_ids.unwrap(), vec![4], "Feed items must be shared");
+    }
+
+    #[test]
+    /// A write waits for the lock of another connection, e.g. an
+    /// overlapping run, for up to the busy timeout instead of failing
+    fn test_busy_timeout() {
+        let db_file = std::env::temp_dir()
+            .join(format!("hnd-busy-{}.sqlite3", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut locking = Storage::new(Storage::establish_connection(&db_file));
+        diesel::sql_query("BEGIN EXCLUSIVE")
+            .execute(&mut locking.conn)
+            .unwrap();
+        // Held for longer than the query retries wait for
+        let unlock = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(600));
+            diesel::sql_query("COMMIT")
+                .execute(&mut locking.conn)
+                .unwrap();
+        });
+
+        let mut storage =
+            Storage::new(Storage::establish_connection_with_timeout(
+                &db_file,
+                std::time::Duration::from_secs(5),
+            ));
+        let result = storage.store_news_items(&[item(1)], "run");
+        unlock.join().unwrap();
+        drop(storage);
+        std::fs::remove_file(&db_file).unwrap();
+        assert!(result.is_ok(), "The write must wait for the lock");
+    }
+
+    #[test]
+    fn test_enable_wal() {
+        #[derive(QueryableByName)]
+        struct JournalMode {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            journal_mode: String,
+        }
+
+        let db_file = std::env::temp_dir()
+            .join(format!("hnd-wal-{}.sqlite3", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let journal_mode = |conn: &mut SqliteConnection| {
+            diesel::sql_query("PRAGMA journal_mode")
+                .get_result::<JournalMode>(conn)
+                .unwrap()
+                .journal_mode
+        };
+
+        let mut conn = Storage::establish_connection(&db_file);
+        assert_eq!(journal_mode(&mut conn), "delete", "WAL must be opt-in");
+        Storage::enable_wal(&mut conn);
+        assert_eq!(journal_mode(&mut conn), "wal");
+        drop(conn);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{db_file}{suffix}"));
+        }
+    }
+
+    #[test]
+    fn test_stored_items_survive_reopen() {
+        let db_file = std::env::temp_dir()
+            .join(format!("hnd-reopen-{}.sqlite3", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let mut storage = Storage::new(Storage::establish_connection(&db_file));
+        storage
+            .store_news_items(&[item_with_url(1), item_with_url(2)], "run")
+            .unwrap();
+        storage
+            .store_news_items(&[item_with_url(3)], "run")
+            .unwrap();
+        drop(storage);
+
+        let mut storage = Storage::new(Storage::establish_connection(&db_file));
+        let ids = storage.get_ids_to_pull("hackernews", vec![1, 2, 3, 4]);
+        drop(storage);
+        std::fs::remove_file(&db_file).unwrap();
+        assert_eq!(ids.unwrap(), vec![4], "Stored items must be kept");
+    }
+
+    #[test]
+    fn test_retry_locked_database() {
+        let locked = || {
+            DieselError::DatabaseError(
+                DatabaseErrorKind::Unknown,
+                Box::new("database is locked".to_string()),
+            )
+        };
+
+        let mut calls = 0;
+        let result = with_retries(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(locked())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3, "Locked database must be retried");
+
+        let mut calls = 0;
+        let result: Result<(), DieselError> = with_retries(|| {
+            calls += 1;
+            Err(locked())
+        });
+        assert!(result.is_err(), "Retries must give up eventually");
+        assert_eq!(calls, MAX_RETRIES + 1, "Wrong number of retries");
+
+        let mut calls = 0;
+        let result: Result<(), DieselError> = with_retries(|| {
+            calls += 1;
+            Err(DieselError::NotFound)
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1, "Other errors must not be retried");
+    }
+}
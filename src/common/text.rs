@@ -0,0 +1,69 @@
+use std::collections::BTreeSet;
+
+/// Convert an HTML fragment to plain text: strip the tags, decode the
+/// entities and collapse the whitespace
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                // tags like <p> or <br> separate words
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    html_escape::decode_html_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Truncate the text to `max_len` characters, marking the cut with an ellipsis
+pub fn truncate_text(text: &str, max_len: usize) -> String {
+    if text.chars().count() <= max_len {
+        return text.to_string();
+    }
+
+    let truncated: String = text.chars().take(max_len).collect();
+    format!("{}…", truncated.trim_end())
+}
+
+/// Average reading speed for the read time estimates, in words per minute
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Estimate the time to read the plain text, in whole minutes rounded up;
+/// `None` if the text has no words
+pub fn read_time_minutes(text: &str) -> Option<usize> {
+    match text.split_whitespace().count() {
+        0 => None,
+        words => Some(words.div_ceil(WORDS_PER_MINUTE)),
+    }
+}
+
+/// Split the title into a set of lowercase alphanumeric words
+fn title_tokens(title: &str) -> BTreeSet<String> {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Get the similarity of two titles as the share of the words they have in
+/// common (Jaccard index of the normalized words), from 0.0 to 1.0
+#[allow(clippy::cast_precision_loss)]
+pub fn title_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (title_tokens(a), title_tokens(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+
+    a.intersection(&b).count() as f64 / union as f64
+}
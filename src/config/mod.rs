@@ -1,76 +1,1610 @@
 use crate::{
-    sender::{DummySender, Sender, SmtpSender, TelegramSender},
-    Deserialize, ItemFilter,
+    sender::{
+        DigestSender, DummySender, FailedQueue, MaildirSender, PreviewSender,
+        Sender, SmtpSender, TelegramSender,
+    },
+    Deserialize, ItemFilter, Serialize, Storage,
 };
 
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, Offset, Utc};
+use std::collections::HashMap;
+
 const DEFAULT_DB_FILE: &str = "./db.sqlite3";
+const DEFAULT_TELEGRAM_RATE_LIMIT_PER_SEC: f64 = 1.0;
+const DEFAULT_ALLOWED_TYPES: [&str; 2] = ["story", "job"];
+const DEFAULT_FAILED_QUEUE_MAX: usize = 10;
+const DEFAULT_WEEKLY_TOP_N: usize = 10;
+const DEFAULT_BUSY_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_PRUNE_AFTER_FAILURES: i64 = 10;
+/// The source the Hacker News items are stored under by default, and the
+/// prefix of the `hackernews:<list>` ones
+pub const DEFAULT_HN_SOURCE: &str = "hackernews";
+const DEFAULT_FEED_ACCEPT: &str = "application/rss+xml, application/atom+xml, \
+    application/xml;q=0.9, */*;q=0.8";
 
-#[derive(Clone, Deserialize)]
-pub struct SmtpConfig {
+/// The digest email settings shared by the SMTP and Maildir senders
+#[derive(Clone, Deserialize, Serialize)]
+pub struct EmailConfig {
     pub from: String,
     pub to: String,
-    pub host: String,
-    pub password: String,
     pub subject: String,
-    pub username: String,
+    /// The sources' own subjects to use instead of `subject`, by the source
+    /// name; set from the sources' `subject`
+    #[serde(skip)]
+    pub source_subjects: HashMap<String, String>,
+    /// Text to put before the `<source> <subject>` subject, e.g. an emoji;
+    /// `{source}` and `{count}` (the number of the digest items) in it are
+    /// replaced
+    pub subject_prefix: Option<String>,
+    /// Text to put after the subject, with the same placeholders, e.g.
+    /// ` — {count} items`
+    pub subject_suffix: Option<String>,
+    /// Send a plain-text email instead of the HTML one with a text part
+    pub plaintext_only: Option<bool>,
+    /// Derive the plain-text body from the HTML one, so the two always match
+    pub derive_text_from_html: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Absent, null or empty with an unauthenticated relay, e.g. one on a
+    /// private network: the mail is sent without AUTH then
+    pub password: Option<String>,
+    pub username: Option<String>,
     // pub port: u16,
+    #[serde(flatten)]
+    pub email: EmailConfig,
 }
 
-#[derive(Clone, Deserialize)]
+/// Store the digest email into a local Maildir instead of sending it
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MaildirConfig {
+    /// The Maildir directory; `tmp`, `new` and `cur` are created if missing
+    pub path: String,
+    #[serde(flatten)]
+    pub email: EmailConfig,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct TelegramConfig {
     pub token: String,
-    pub chat_id: String,
+    pub chat_id: ChatIds,
+    /// Max number of messages sent per second; default is 1
+    pub rate_limit_per_sec: Option<f64>,
+    /// How the messages are formatted: `MarkdownV2` (default), `HTML` or
+    /// `none` for plain text
+    pub parse_mode: Option<String>,
+}
+
+/// One or more Telegram chats to send the digest to
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ChatIds {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl ChatIds {
+    /// Get the list of the chat IDs
+    #[must_use]
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            ChatIds::Single(chat_id) => vec![chat_id.clone()],
+            ChatIds::Multiple(chat_ids) => chat_ids.clone(),
+        }
+    }
+}
+
+impl TelegramConfig {
+    /// Get the delay to keep between two consecutive messages; a rate too
+    /// small for the delay to fit a `Duration` falls back to the default
+    #[must_use]
+    pub fn get_send_interval(&self) -> std::time::Duration {
+        let rate = self
+            .rate_limit_per_sec
+            .filter(|rate| *rate > 0.0)
+            .unwrap_or(DEFAULT_TELEGRAM_RATE_LIMIT_PER_SEC);
+        std::time::Duration::try_from_secs_f64(1.0 / rate).unwrap_or_else(
+            |_| {
+                std::time::Duration::from_secs_f64(
+                    1.0 / DEFAULT_TELEGRAM_RATE_LIMIT_PER_SEC,
+                )
+            },
+        )
+    }
+}
+
+/// A daily window in which no digest is sent, e.g. from 22:00 to 07:00
+#[derive(Clone, Deserialize, Serialize)]
+pub struct QuietHours {
+    /// Start of the window, as `HH:MM`
+    pub start: String,
+    /// End of the window, as `HH:MM`; a window ending before its start ends
+    /// on the next day
+    pub end: String,
+    /// Time zone of the window: `local` (default), `UTC` or an offset like
+    /// `+02:00`
+    pub tz: Option<String>,
+    /// What to do with the digests built in the window: `skip` (default)
+    /// drops them, `defer` keeps them to send on the next run after it
+    pub mode: Option<String>,
 }
 
-#[derive(Clone, Deserialize)]
+impl QuietHours {
+    /// Parse the window's start and end times
+    fn window(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|e| {
+                format!("quiet_hours time must be HH:MM, got {time}: {e}")
+            })
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    /// Get the UTC offset of the window's time zone at the given moment
+    fn offset(&self, now: DateTime<Utc>) -> Result<FixedOffset, String> {
+        match self.tz.as_deref() {
+            None | Some("local") => Ok(*now.with_timezone(&Local).offset()),
+            Some("UTC") => Ok(Utc.fix()),
+            Some(tz) => tz.parse().map_err(|_| {
+                format!(
+                    "quiet_hours.tz must be local, UTC or an offset like +02:00, got {tz}"
+                )
+            }),
+        }
+    }
+
+    /// Check that the times, the time zone and the mode are valid
+    fn validate(&self) -> Result<(), String> {
+        self.window()?;
+        self.offset(Utc::now())?;
+        if let Some(mode) = self.mode.as_deref() {
+            if !matches!(mode, "skip" | "defer") {
+                return Err(format!(
+                    "quiet_hours.mode must be skip or defer, got {mode}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the given moment falls in the window. An invalid window,
+    /// which the config validation rejects, is never quiet
+    #[must_use]
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let (Ok((start, end)), Ok(offset)) = (self.window(), self.offset(now))
+        else {
+            return false;
+        };
+        let time = now.with_timezone(&offset).time();
+        if start <= end {
+            start <= time && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+
+    /// Check if the digests built in the window are kept to send later
+    /// instead of being dropped
+    #[must_use]
+    pub fn defers(&self) -> bool {
+        self.mode.as_deref() == Some("defer")
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct RssSource {
     pub url: String,
     pub name: String,
+    /// HTTP headers to send with the feed request, e.g. `Authorization`;
+    /// `${NAME}` in a value is replaced with the `NAME` environment variable
+    pub headers: Option<HashMap<String, String>>,
+    /// Sources of a higher priority are digested first, and win the title
+    /// deduplication against the later ones; default is 0
+    pub priority: Option<i32>,
+    /// The RSS element to take the items' publication time from: `pubDate`,
+    /// `dc:date` or a namespaced one like `atom:updated`. If absent, the
+    /// first of them the item has is used
+    pub date_field: Option<String>,
+    /// The email subject of the source's digest instead of the global one,
+    /// with the same placeholders as `subject_prefix`
+    pub subject: Option<String>,
+    /// The `Accept` header to request the feed with instead of the global
+    /// `feed_accept`
+    pub accept: Option<String>,
+}
+
+impl RssSource {
+    /// Get the request headers with the environment variables substituted
+    ///
+    /// # Errors
+    ///
+    /// Fails if a referenced environment variable is not set
+    pub fn get_headers(
+        &self,
+    ) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+        let mut headers = HashMap::new();
+        for (name, value) in self.headers.iter().flatten() {
+            headers.insert(name.clone(), expand_env_vars(value)?);
+        }
+
+        Ok(headers)
+    }
+}
+
+/// Parts of the config keys, and of the header names, which values are
+/// secrets
+const SECRET_KEYS: [&str; 7] = [
+    "password",
+    "token",
+    "secret",
+    "authorization",
+    "cookie",
+    "api_key",
+    "api-key",
+];
+
+/// Replace the string values of the secret keys in the JSON with `***`
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if value.is_string()
+                    && SECRET_KEYS.iter().any(|secret| key.contains(secret))
+                {
+                    *value = serde_json::Value::from("***");
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            values.iter_mut().for_each(redact_secrets);
+        }
+        _ => {}
+    }
+}
+
+/// Replace each `${NAME}` in the value with the `NAME` environment variable;
+/// a variable that is not set is an error
+fn expand_env_vars(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+        let var = std::env::var(name)
+            .map_err(|_| format!("Environment variable {name} is not set"))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct AppConfig {
     pub blacklisted_domains: Vec<String>,
+    /// Public suffixes to skip the items of, e.g. `ru` or `co.uk`; a TLD
+    /// covers the suffixes under it too, so `uk` covers `co.uk`
+    pub blacklisted_tlds: Option<Vec<String>>,
+    /// Public suffixes to only digest the items of; the others are skipped
+    /// as blacklisted. `blacklisted_domains` is checked first
+    pub whitelisted_tlds: Option<Vec<String>>,
     pub db_file: Option<String>,
+    /// How long a statement waits for the database locked by another
+    /// connection, e.g. an overlapping run, in milliseconds; default is 5000
+    pub busy_timeout_ms: Option<u64>,
+    /// Keep the database in the write-ahead log mode, so a reader doesn't
+    /// wait for a fetching run; default is false. The database gets the
+    /// `-wal` and `-shm` files next to it, and keeps the mode once set
+    pub sqlite_wal: Option<bool>,
     pub filters: Vec<ItemFilter>,
+    /// What the filters without their own `match_field` are matched
+    /// against: `title` (default), `body` or `both`
+    pub match_field: Option<String>,
+    /// ISO 639-3 codes of the languages to digest the items in, e.g. `eng`;
+    /// the items reliably detected to be in another language are skipped.
+    /// All languages are digested if absent or empty
+    pub languages: Option<Vec<String>>,
+    /// JSON file with an array of filters to add to the inline `filters`;
+    /// a relative path is resolved against the config file's directory
+    pub filters_file: Option<String>,
+    /// Number of days to keep the stored items for; 0 never purges them
     pub purge_after_days: i64,
+    /// Max number of items to keep per source; `--vacuum` deletes the
+    /// oldest ones over it, on top of the `purge_after_days` purge. Keep it
+    /// above the number of items a feed lists, or they are digested again.
+    /// Absent or 0 keeps them all
+    pub max_records_per_source: Option<usize>,
+    /// Replace the items older than this many days with their counts per
+    /// source and month on `--vacuum`, so the history outlives the items.
+    /// Off if absent; keep it below `purge_after_days`, which deletes the
+    /// items without counting them
+    pub rollup_after_days: Option<i64>,
     pub smtp: Option<SmtpConfig>,
+    pub maildir: Option<MaildirConfig>,
     pub telegram: Option<TelegramConfig>,
     pub rss_sources: Option<Vec<RssSource>>,
+    /// Directory of `.url` files to add to `rss_sources`, one feed each: the
+    /// feed URL on the first line and, optionally, its name on the second
+    /// (the file name otherwise). A relative path is resolved against the
+    /// config file's directory; the feeds already listed are left out
+    pub rss_sources_dir: Option<String>,
+    /// The `Accept` header to request the RSS sources with, for the servers
+    /// answering with HTML otherwise; default prefers RSS and Atom. A source's
+    /// own `accept`, or `Accept` in its `headers`, wins over it
+    pub feed_accept: Option<String>,
+    /// Number of the runs in a row an RSS source must fail to fetch in for
+    /// `--prune-sources` to remove it; default is 10
+    pub prune_after_failures: Option<i64>,
+    /// Max number of items to send in one digest; the rest are summarized
+    pub max_digest_items: Option<usize>,
+    /// Max number of new items to pull per run; the rest are pulled next runs
+    pub max_items_per_run: Option<usize>,
+    /// Max length of the text snippet shown under the item's title; text
+    /// posts (Ask HN, etc.) are only digested when it is set
+    pub snippet_len: Option<usize>,
+    /// Show the estimated reading time next to the items with a text: the
+    /// text posts (Ask HN, etc.) and the feed items with a description
+    pub show_read_time: Option<bool>,
+    /// Min number of characters in a title for the item to be digested;
+    /// shorter items are still stored. Absent or 0 disables the check
+    pub min_title_len: Option<usize>,
+    /// Collapse the digest items which titles share at least this part of
+    /// their words (0.0 to 1.0), keeping the first seen one. Off if absent
+    pub dedup_title_similarity: Option<f64>,
+    /// Max number of HTTP redirects to follow; 0 treats a redirect as an
+    /// error. The reqwest default (10) is used if absent
+    pub max_redirects: Option<usize>,
+    /// Show the `[source]` label before each item of the digest; default is
+    /// true. Turn it off to avoid the clutter in single-source digests
+    pub source_labels: Option<bool>,
+    /// Skip the items created before the source's previous run, even if
+    /// their IDs are new; default is false
+    pub only_since_last_run: Option<bool>,
+    /// Hacker News item types to digest; default is `["story", "job"]`
+    pub allowed_types: Option<Vec<String>>,
+    /// The source to store the Hacker News items under: `hackernews`
+    /// (default) or `hackernews:<list>`, e.g. `hackernews:top`. The items
+    /// stored under any of them are not pulled again
+    pub hn_source: Option<String>,
+    /// The email subject of the Hacker News digest instead of the global
+    /// one, like the RSS sources' `subject`
+    pub hn_subject: Option<String>,
+    /// Only log the SMTP/Telegram delivery failures instead of failing the
+    /// run with a nonzero exit status; default is false
+    pub lenient_delivery: Option<bool>,
+    /// Ask on the terminal before sending a digest of more items than this,
+    /// e.g. on the first run; absent or 0 never asks
+    pub confirm_threshold: Option<usize>,
+    /// What to do with such a digest when there's no terminal to ask on,
+    /// e.g. in cron: `send` (default) or `skip`
+    pub confirm_unattended: Option<String>,
+    /// JSON file to keep the digests none of the senders delivered in, to
+    /// send them with `--flush-queue`; without it they are dropped
+    pub failed_queue: Option<String>,
+    /// Max number of digests in `failed_queue`, the oldest ones are dropped
+    /// over it; default is 10
+    pub failed_queue_max: Option<usize>,
+    /// Number of the top scored items of the week `--weekly` sends; default
+    /// is 10
+    pub weekly_top_n: Option<usize>,
+    /// Directory to keep the raw JSON of every Hacker News item pulled in,
+    /// one `<id>.json` file per item, for `--reprocess`; not kept without it
+    pub raw_cache_dir: Option<String>,
+    /// Delay between two Hacker News item requests, and between two feed
+    /// requests, in milliseconds; default is 0
+    pub fetch_delay_ms: Option<u64>,
+    /// Max number of the HTTP requests of a run: the top stories prefetch,
+    /// the item, comment and feed requests of all the sources together. Once
+    /// it is reached, nothing more is fetched: the pulled items are stored
+    /// and sent, and the rest are left to the next run. Unlimited if absent
+    pub max_requests_per_run: Option<usize>,
+    /// Max number of feeds to fetch, or links to check, at the same time;
+    /// default is 1. With `fetch_delay_ms`, each feed waits for the delay
+    /// before its request
+    pub concurrency: Option<usize>,
+    /// Max number of idle HTTP connections to keep open per host; default is
+    /// `concurrency`, so the concurrent requests to a host reuse their
+    /// connections without piling up more of them. Lower it on a host
+    /// short of file descriptors
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds to keep an idle HTTP connection open for; the reqwest
+    /// default (90) is used if absent
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Only digest the items of the domains none of the stored items has,
+    /// to discover new sites; default is false. An item of a new domain
+    /// kept by the filters makes the domain seen for the rest of the run
+    pub only_new_domains: Option<bool>,
+    /// Skip the items which URLs were digested, by any source, in the last
+    /// this many days; off if absent or 0. The digested URLs are only
+    /// recorded while it is on
+    pub suppress_recently_sent_days: Option<i64>,
+    /// Where a digested URL is looked for to drop the items repeating it:
+    /// `source` (default) in the same source's digest, `run` in the digests
+    /// of all the sources of the run, or `persistent` in the URLs digested
+    /// ever. `persistent` is `suppress_recently_sent_days` without the time
+    /// limit: it records and checks the same URLs, which are only kept for
+    /// `purge_after_days`, and it wins over the days if both are set
+    pub dedup_scope: Option<String>,
+    /// With the `persistent` dedup scope, digest a URL again once it wasn't
+    /// digested in the last this many days; absent or 0 never does
+    pub resurface_after_days: Option<i64>,
+    /// Check the digest items' links with HEAD requests before sending, up
+    /// to `concurrency` at a time, and mark the ones answering with an error
+    /// status as dead; default is false. Hacker News permalinks are exempt
+    pub check_links: Option<bool>,
+    /// Drop the items with dead links from the digest instead of marking
+    /// them; only with `check_links`
+    pub drop_dead_links: Option<bool>,
+    /// Number of the top comments to show under each Hacker News item of
+    /// the digest, cut to `snippet_len` (200 if absent) characters; absent
+    /// or 0 is off. The comments are fetched up to `concurrency` at a time
+    pub include_comments: Option<usize>,
+    /// Store the processed Hacker News items every that many items instead
+    /// of only at the end of the run, so a crash on a long run doesn't make
+    /// the next one fetch them all again; absent or 0 is off
+    pub flush_every: Option<usize>,
+    /// Store the skipped Hacker News items (deleted, blacklisted, filtered
+    /// out, etc.) so they are never examined again; default is true. With
+    /// false the database keeps no junk, but the still listed skipped items
+    /// are fetched and skipped again on every run
+    pub store_skipped: Option<bool>,
+    /// Don't send the digests in this daily window; the items are still
+    /// fetched and stored
+    pub quiet_hours: Option<QuietHours>,
+    /// Print the SMTP emails instead of sending them; set from the command line
+    #[serde(skip)]
+    pub preview_email: bool,
+    /// Print the console digest as HTML instead of plain text; set from the
+    /// command line
+    #[serde(skip)]
+    pub console_html: bool,
+}
+
+/// Parse the config from a JSON string; `filters_file` and `rss_sources_dir`
+/// are resolved against the current directory
+impl std::str::FromStr for AppConfig {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::parse(contents)?;
+        config.load_filters_file(std::path::Path::new("."))?;
+        config.load_rss_sources_dir(std::path::Path::new("."))?;
+        config.validate()?;
+
+        Ok(config)
+    }
 }
 
 impl AppConfig {
+    /// Read the config from the JSON file
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read, is not a valid config, its
+    /// `filters_file` can't be loaded or its `rss_sources_dir` can't be read
     pub fn from_file(
-        file_name: &String,
+        file_name: &str,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let contents = std::fs::read_to_string(file_name)?;
-        let config: AppConfig = serde_json::from_str(&contents)?;
+        let contents = std::fs::read_to_string(file_name)
+            .map_err(|e| format!("Cannot read config file {file_name}: {e}"))?;
+        let mut config = Self::parse(&contents)
+            .map_err(|e| format!("Invalid config {file_name}: {e}"))?;
+        let config_dir = std::path::Path::new(file_name)
+            .parent()
+            .unwrap_or(std::path::Path::new("."));
+        config.load_filters_file(config_dir)?;
+        config.load_rss_sources_dir(config_dir)?;
+        config.validate()?;
 
         Ok(config)
     }
 
-    #[allow(dead_code)]
-    pub fn from_str(
-        contents: &str,
+    /// Read the config JSON from the reader, e.g. stdin; `filters_file` and
+    /// `rss_sources_dir` are resolved against the current directory
+    ///
+    /// # Errors
+    ///
+    /// Fails if the reader fails, the JSON is not a valid config, its
+    /// `filters_file` can't be loaded or its `rss_sources_dir` can't be read
+    pub fn from_reader(
+        mut reader: impl std::io::Read,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let config: AppConfig = serde_json::from_str(contents)?;
+        let mut contents = String::new();
+        reader
+            .read_to_string(&mut contents)
+            .map_err(|e| format!("Cannot read config from stdin: {e}"))?;
+        let mut config = Self::parse(&contents)
+            .map_err(|e| format!("Invalid config from stdin: {e}"))?;
+        config.load_filters_file(std::path::Path::new("."))?;
+        config.load_rss_sources_dir(std::path::Path::new("."))?;
+        config.validate()?;
 
         Ok(config)
     }
 
+    /// Append the filters from `filters_file` to the inline ones. A relative
+    /// path is resolved against `base_dir`, the config file's directory.
+    fn load_filters_file(
+        &mut self,
+        base_dir: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(filters_file) = &self.filters_file else {
+            return Ok(());
+        };
+
+        let path = base_dir.join(filters_file);
+        let contents = std::fs::read_to_string(&path).map_err(|e| {
+            format!("Cannot read filters file {}: {e}", path.display())
+        })?;
+        let filters: Vec<ItemFilter> = serde_json::from_str(&contents)
+            .map_err(|e| {
+                format!("Invalid filters file {}: {e}", path.display())
+            })?;
+        self.filters.extend(filters);
+
+        Ok(())
+    }
+
+    /// Add the feeds of the `.url` files in `rss_sources_dir` to the inline
+    /// `rss_sources`, in the file names order. A relative path is resolved
+    /// against `base_dir`, the config file's directory. The unreadable and
+    /// empty files are skipped with a warning, and the feeds which URLs are
+    /// listed already are skipped silently.
+    fn load_rss_sources_dir(
+        &mut self,
+        base_dir: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(sources_dir) = &self.rss_sources_dir else {
+            return Ok(());
+        };
+
+        let dir = base_dir.join(sources_dir);
+        let mut paths: Vec<std::path::PathBuf> = std::fs::read_dir(&dir)
+            .map_err(|e| {
+                format!("Cannot read RSS sources dir {}: {e}", dir.display())
+            })?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "url"))
+            .collect();
+        paths.sort();
+
+        let sources = self.rss_sources.get_or_insert_with(Vec::new);
+        for path in paths {
+            let contents = match std::fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    eprintln!("Skipping RSS source {}: {e}", path.display());
+                    continue;
+                }
+            };
+            let mut lines = contents.lines().map(str::trim);
+            let Some(url) = lines.next().filter(|url| !url.is_empty()) else {
+                eprintln!("Skipping RSS source {}: no URL", path.display());
+                continue;
+            };
+            if sources.iter().any(|source| source.url.trim() == url) {
+                continue;
+            }
+            let name = match lines.next().filter(|name| !name.is_empty()) {
+                Some(name) => name.to_string(),
+                None => path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            };
+            sources.push(RssSource {
+                url: url.to_string(),
+                name,
+                ..Default::default()
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Parse the config JSON. On an error, parse it once again to point the
+    /// message at the offending field, e.g. `smtp.host: missing field`
+    fn parse(contents: &str) -> Result<Self, String> {
+        serde_json::from_str(contents).map_err(|e| {
+            let de = &mut serde_json::Deserializer::from_str(contents);
+            match serde_path_to_error::deserialize::<_, AppConfig>(de) {
+                Err(path_err) => path_err.to_string(),
+                Ok(_) => e.to_string(),
+            }
+        })
+    }
+
+    /// Check the values serde cannot check on its own
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.purge_after_days < 0 {
+            return Err(format!(
+                "purge_after_days must be 0 (never purge) or more, got {}",
+                self.purge_after_days
+            )
+            .into());
+        }
+        if let Some(days) = self.rollup_after_days.filter(|days| *days < 1) {
+            return Err(format!(
+                "rollup_after_days must be 1 or more, got {days}"
+            )
+            .into());
+        }
+        if let Some(parse_mode) = self
+            .telegram
+            .as_ref()
+            .and_then(|telegram| telegram.parse_mode.as_deref())
+        {
+            if !matches!(parse_mode, "MarkdownV2" | "HTML" | "none") {
+                return Err(format!(
+                    "telegram.parse_mode must be MarkdownV2, HTML or none, got {parse_mode}"
+                )
+                .into());
+            }
+        }
+        if let Some(quiet_hours) = &self.quiet_hours {
+            quiet_hours.validate()?;
+        }
+        let match_fields = self
+            .filters
+            .iter()
+            .filter_map(|filter| filter.match_field.as_deref())
+            .chain(self.match_field.as_deref());
+        for match_field in match_fields {
+            if !matches!(match_field, "title" | "body" | "both") {
+                return Err(format!(
+                    "match_field must be title, body or both, got {match_field}"
+                )
+                .into());
+            }
+        }
+        for mode in self.filters.iter().filter_map(|f| f.mode.as_deref()) {
+            if !matches!(mode, "include" | "exclude") {
+                return Err(format!(
+                    "filter mode must be include or exclude, got {mode}"
+                )
+                .into());
+            }
+        }
+        for lang in self.languages.iter().flatten() {
+            if whatlang::Lang::from_code(lang.to_lowercase()).is_none() {
+                return Err(format!(
+                    "languages must be ISO 639-3 codes like eng, got {lang}"
+                )
+                .into());
+            }
+        }
+        if let Some(hn_source) = self.hn_source.as_deref() {
+            let is_list = hn_source
+                .strip_prefix(DEFAULT_HN_SOURCE)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .is_some_and(|list| !list.is_empty());
+            if hn_source != DEFAULT_HN_SOURCE && !is_list {
+                return Err(format!(
+                    "hn_source must be {DEFAULT_HN_SOURCE} or {DEFAULT_HN_SOURCE}:<list>, got {hn_source}"
+                )
+                .into());
+            }
+        }
+        if let Some(unattended) = self.confirm_unattended.as_deref() {
+            if !matches!(unattended, "send" | "skip") {
+                return Err(format!(
+                    "confirm_unattended must be send or skip, got {unattended}"
+                )
+                .into());
+            }
+        }
+        let dedup_scope = self.get_dedup_scope();
+        if !matches!(dedup_scope, "source" | "run" | "persistent") {
+            return Err(format!(
+                "dedup_scope must be source, run or persistent, got {dedup_scope}"
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Remove the RSS source with the given name from the config file and
+    /// write the file back. Return false if there is no such source.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read, parsed or written back
+    pub fn remove_source(
+        file_name: &str,
+        source_name: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(file_name)?;
+        let mut config: serde_json::Value = serde_json::from_str(&contents)?;
+
+        let Some(sources) = config
+            .get_mut("rss_sources")
+            .and_then(serde_json::Value::as_array_mut)
+        else {
+            return Ok(false);
+        };
+        let num_sources = sources.len();
+        sources.retain(|source| {
+            source.get("name").and_then(serde_json::Value::as_str)
+                != Some(source_name)
+        });
+        if sources.len() == num_sources {
+            return Ok(false);
+        }
+
+        std::fs::write(file_name, serde_json::to_string_pretty(&config)?)?;
+
+        Ok(true)
+    }
+
+    /// Override the from/to addresses of the SMTP and Maildir emails
+    ///
+    /// # Errors
+    ///
+    /// Fails if an address is not a valid email address
+    pub fn set_email_addresses(
+        &mut self,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for address in [from, to].into_iter().flatten() {
+            address.parse::<lettre::message::Mailbox>().map_err(|e| {
+                format!("Invalid email address '{address}': {e}")
+            })?;
+        }
+
+        let emails = [
+            self.smtp.as_mut().map(|smtp| &mut smtp.email),
+            self.maildir.as_mut().map(|maildir| &mut maildir.email),
+        ];
+        for email in emails.into_iter().flatten() {
+            if let Some(from) = from {
+                email.from = from.to_string();
+            }
+            if let Some(to) = to {
+                email.to = to.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the digest is printed to the console, i.e. none of SMTP,
+    /// Maildir and Telegram is configured
+    #[must_use]
+    pub fn is_console_output(&self) -> bool {
+        self.smtp.is_none() && self.maildir.is_none() && self.telegram.is_none()
+    }
+
+    /// Get the email subjects of the sources which have their own, by the
+    /// source name
+    #[must_use]
+    pub fn get_source_subjects(&self) -> HashMap<String, String> {
+        let rss_subjects =
+            self.rss_sources.iter().flatten().filter_map(|source| {
+                Some((source.name.clone(), source.subject.clone()?))
+            });
+        self.hn_subject
+            .iter()
+            .map(|subject| ("HackerNews".to_string(), subject.clone()))
+            .chain(rss_subjects)
+            .collect()
+    }
+
+    /// Build the sender to deliver the digest with. SMTP is used if it is
+    /// configured, then Maildir, then Telegram; without any, the digest is
+    /// printed
+    #[must_use]
     pub fn get_sender(&self) -> Sender {
-        if let Some(config) = &self.smtp {
-            Sender::Smtp(SmtpSender::new(config))
+        let sender: Box<dyn DigestSender> = if let Some(config) = &self.smtp {
+            let mut config = config.clone();
+            config.email.source_subjects = self.get_source_subjects();
+            if self.preview_email {
+                Box::new(PreviewSender::new(&config))
+            } else {
+                Box::new(SmtpSender::new(&config))
+            }
+        } else if let Some(config) = &self.maildir {
+            let mut config = config.clone();
+            config.email.source_subjects = self.get_source_subjects();
+            Box::new(MaildirSender::new(&config))
         } else if let Some(config) = &self.telegram {
-            Sender::Telegram(TelegramSender::new(config))
+            Box::new(TelegramSender::new(config))
         } else {
-            Sender::Dummy(DummySender {})
+            Box::new(DummySender {
+                html: self.console_html,
+            })
+        };
+
+        Sender::default()
+            .register(sender)
+            .lenient(self.lenient_delivery.unwrap_or(false))
+            .quiet_hours(self.quiet_hours.clone())
+            .confirm(
+                self.confirm_threshold,
+                self.confirm_unattended.as_deref() == Some("skip"),
+            )
+            .failed_queue(self.get_failed_queue())
+    }
+
+    /// Get the config as pretty JSON, with the secrets (passwords, tokens,
+    /// authorization headers, etc.) shown as `***`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the config can't be serialized, which never happens
+    #[must_use]
+    pub fn to_redacted_json(&self) -> String {
+        let mut value =
+            serde_json::to_value(self).expect("Config must be serializable");
+        redact_secrets(&mut value);
+        serde_json::to_string_pretty(&value)
+            .expect("Config must be serializable")
+    }
+
+    /// Get the number of the failed runs in a row to prune a source after
+    #[must_use]
+    pub fn get_prune_after_failures(&self) -> i64 {
+        self.prune_after_failures
+            .filter(|&failures| failures > 0)
+            .unwrap_or(DEFAULT_PRUNE_AFTER_FAILURES)
+    }
+
+    /// Get the max number of items to keep per source; `None` if unlimited,
+    /// which 0 is too
+    #[must_use]
+    pub fn get_max_records_per_source(&self) -> Option<usize> {
+        self.max_records_per_source.filter(|&max| max > 0)
+    }
+
+    /// Get the source to store the Hacker News items under
+    #[must_use]
+    pub fn get_hn_source(&self) -> &str {
+        self.hn_source.as_deref().unwrap_or(DEFAULT_HN_SOURCE)
+    }
+
+    /// Get the number of the top items the weekly roll-up sends
+    #[must_use]
+    pub fn get_weekly_top_n(&self) -> usize {
+        self.weekly_top_n.unwrap_or(DEFAULT_WEEKLY_TOP_N)
+    }
+
+    /// Get the queue of the undelivered digests, if configured
+    #[must_use]
+    pub fn get_failed_queue(&self) -> Option<FailedQueue> {
+        self.failed_queue.as_deref().map(|path| {
+            FailedQueue::new(
+                path,
+                self.failed_queue_max.unwrap_or(DEFAULT_FAILED_QUEUE_MAX),
+            )
+        })
+    }
+
+    /// Build the HTTP client the fetchers share the settings of
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized
+    #[must_use]
+    pub fn get_http_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder().pool_max_idle_per_host(
+            self.pool_max_idle_per_host
+                .unwrap_or_else(|| self.get_concurrency()),
+        );
+        if let Some(secs) = self.pool_idle_timeout_secs {
+            builder =
+                builder.pool_idle_timeout(std::time::Duration::from_secs(secs));
         }
+        if let Some(max_redirects) = self.max_redirects {
+            builder = builder
+                .redirect(reqwest::redirect::Policy::limited(max_redirects));
+        }
+        builder.build().expect("Failed to build the HTTP client")
+    }
+
+    /// Get the Hacker News item types to digest
+    #[must_use]
+    pub fn get_allowed_types(&self) -> Vec<String> {
+        self.allowed_types.clone().unwrap_or_else(|| {
+            DEFAULT_ALLOWED_TYPES
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        })
+    }
+
+    /// Get the `Accept` header to request the source's feed with
+    #[must_use]
+    pub fn get_feed_accept<'a>(&'a self, source: &'a RssSource) -> &'a str {
+        source
+            .accept
+            .as_deref()
+            .or(self.feed_accept.as_deref())
+            .unwrap_or(DEFAULT_FEED_ACCEPT)
+    }
+
+    /// Get the RSS sources in the order to digest them: by priority, highest
+    /// first; the sources of the same priority keep the config order
+    #[must_use]
+    pub fn get_rss_sources(&self) -> Vec<RssSource> {
+        let mut sources = self.rss_sources.clone().unwrap_or_default();
+        sources.sort_by_key(|source| {
+            std::cmp::Reverse(source.priority.unwrap_or(0))
+        });
+        sources
     }
 
+    /// Get the max number of feeds to fetch, or links to check, at the same
+    /// time
+    #[must_use]
+    pub fn get_concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(1).max(1)
+    }
+
+    /// Get where the digested URLs are looked for to drop the duplicates:
+    /// `source`, `run` or `persistent`
+    #[must_use]
+    pub fn get_dedup_scope(&self) -> &str {
+        self.dedup_scope.as_deref().unwrap_or("source")
+    }
+
+    /// Get the time since which a digested URL is not digested again, by
+    /// any source: with the `persistent` dedup scope, ever or the last
+    /// `resurface_after_days` days, or else the last
+    /// `suppress_recently_sent_days` days. `None` if the URLs are not checked
+    #[must_use]
+    pub fn get_sent_since(&self) -> Option<i64> {
+        let days_ago =
+            |days: i64| chrono::Utc::now().timestamp() - days * 24 * 60 * 60;
+        if self.get_dedup_scope() == "persistent" {
+            return Some(
+                self.resurface_after_days
+                    .filter(|days| *days > 0)
+                    .map_or(0, days_ago),
+            );
+        }
+        let days = self.suppress_recently_sent_days.filter(|days| *days > 0)?;
+        Some(days_ago(days))
+    }
+
+    /// Get the delay to keep between two consecutive fetch requests
+    #[must_use]
+    pub fn get_fetch_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.fetch_delay_ms.unwrap_or(0))
+    }
+
+    #[must_use]
     pub fn get_db_file(&self) -> String {
         self.db_file
             .clone()
             .unwrap_or_else(|| DEFAULT_DB_FILE.to_string())
     }
+
+    #[must_use]
+    pub fn get_busy_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(
+            self.busy_timeout_ms.unwrap_or(DEFAULT_BUSY_TIMEOUT_MS),
+        )
+    }
+
+    /// Open the storage of the `db_file`, waiting for the locks of the other
+    /// connections up to `busy_timeout_ms`, in the WAL mode if `sqlite_wal`
+    /// is on
+    ///
+    /// # Panics
+    ///
+    /// Panics if the database can't be opened or migrated
+    #[must_use]
+    pub fn get_storage(&self) -> Storage {
+        let mut conn = Storage::establish_connection_with_timeout(
+            &self.get_db_file(),
+            self.get_busy_timeout(),
+        );
+        if self.sqlite_wal == Some(true) {
+            Storage::enable_wal(&mut conn);
+        }
+
+        Storage::new(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AppConfig, ChatIds, RssSource, TelegramConfig};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_remove_source() {
+        let file_name = std::env::temp_dir()
+            .join(format!("hnd-remove-source-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(
+            &file_name,
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "rss_sources": [
+                    {"url": "https://example.com/rss", "name": "Example"},
+                    {"url": "https://example.org/rss", "name": "Other"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(AppConfig::remove_source(&file_name, "Example").unwrap());
+        assert!(
+            !AppConfig::remove_source(&file_name, "Missing").unwrap(),
+            "Removing a missing source must be reported",
+        );
+
+        let contents = std::fs::read_to_string(&file_name).unwrap();
+        let config = AppConfig::from_file(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        let sources = config.rss_sources.unwrap();
+        assert_eq!(sources.len(), 1, "Source removal failed");
+        assert_eq!(sources[0].name, "Other", "Wrong source removed");
+
+        let keys = ["purge_after_days", "blacklisted_domains", "rss_sources"];
+        let positions: Vec<usize> = keys
+            .iter()
+            .map(|key| contents.find(&format!("\"{key}\"")).unwrap())
+            .collect();
+        assert!(
+            positions.is_sorted(),
+            "The keys must keep their order: {contents}",
+        );
+    }
+
+    #[test]
+    fn test_telegram_chat_ids() {
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "telegram": {"token": "token", "chat_id": "123"}
+            }"#,
+        )
+        .unwrap();
+        let chat_ids = config.telegram.unwrap().chat_id.to_vec();
+        assert_eq!(chat_ids, vec!["123"], "Single chat ID parsing failed");
+
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "telegram": {"token": "token", "chat_id": ["123", "-456"]}
+            }"#,
+        )
+        .unwrap();
+        let chat_ids = config.telegram.unwrap().chat_id.to_vec();
+        assert_eq!(
+            chat_ids,
+            vec!["123", "-456"],
+            "Multiple chat IDs parsing failed",
+        );
+    }
+
+    #[test]
+    fn test_negative_purge_after_days() {
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": -1,
+                "blacklisted_domains": [],
+                "filters": []
+            }"#,
+        );
+        assert!(config.is_err(), "Negative retention must be rejected");
+    }
+
+    #[test]
+    fn test_hn_source() {
+        let parse = |hn_source: &str| {
+            AppConfig::from_str(&format!(
+                r#"{{
+                    "purge_after_days": 0,
+                    "blacklisted_domains": [],
+                    "filters": [],
+                    "hn_source": "{hn_source}"
+                }}"#
+            ))
+        };
+        assert_eq!(AppConfig::default().get_hn_source(), "hackernews");
+        assert_eq!(
+            parse("hackernews:ask").unwrap().get_hn_source(),
+            "hackernews:ask"
+        );
+        assert!(parse("hackernews").is_ok());
+        assert!(parse("hackernews:").is_err(), "Empty list must be rejected");
+        assert!(parse("hn:ask").is_err(), "Other prefixes must be rejected");
+    }
+
+    #[test]
+    fn test_filter_mode() {
+        let parse = |mode: &str| {
+            AppConfig::from_str(&format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [{{"value": "rust", "mode": "{mode}"}}]
+                }}"#
+            ))
+        };
+
+        assert!(parse("include").is_ok());
+        assert!(parse("exclude").is_ok());
+        assert!(parse("exlude").is_err(), "Unknown mode must be rejected");
+    }
+
+    #[test]
+    fn test_telegram_parse_mode() {
+        let parse = |parse_mode: &str| {
+            AppConfig::from_str(&format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [],
+                    "telegram": {{
+                        "token": "token",
+                        "chat_id": "1",
+                        "parse_mode": "{parse_mode}"
+                    }}
+                }}"#
+            ))
+        };
+
+        assert!(parse("HTML").is_ok());
+        assert!(parse("none").is_ok());
+        assert!(parse("Markdown").is_err(), "Unknown mode must be rejected");
+    }
+
+    #[test]
+    fn test_telegram_send_interval() {
+        let interval = |rate_limit_per_sec| {
+            TelegramConfig {
+                token: "token".to_string(),
+                chat_id: ChatIds::Single("1".to_string()),
+                rate_limit_per_sec,
+                parse_mode: None,
+            }
+            .get_send_interval()
+        };
+
+        assert_eq!(interval(Some(4.0)), Duration::from_millis(250));
+        assert_eq!(interval(None), Duration::from_secs(1));
+        assert_eq!(interval(Some(0.0)), Duration::from_secs(1));
+        assert_eq!(
+            interval(Some(1e-300)),
+            Duration::from_secs(1),
+            "A too long interval must fall back to the default",
+        );
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let parse = |quiet_hours: &str| {
+            AppConfig::from_str(&format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [],
+                    "quiet_hours": {quiet_hours}
+                }}"#
+            ))
+        };
+        let at = |time: &str| {
+            chrono::DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+        };
+
+        let config =
+            parse(r#"{"start": "22:00", "end": "07:00", "tz": "+02:00"}"#)
+                .unwrap();
+        let quiet_hours = config.quiet_hours.unwrap();
+        assert!(quiet_hours.contains(at("2026-10-17T20:30:00Z")));
+        assert!(quiet_hours.contains(at("2026-10-17T04:59:00Z")));
+        assert!(
+            !quiet_hours.contains(at("2026-10-17T05:00:00Z")),
+            "The window must end at its end time, in its time zone",
+        );
+        assert!(!quiet_hours.contains(at("2026-10-17T12:00:00Z")));
+        assert!(!quiet_hours.defers(), "Must skip by default");
+
+        let config = parse(
+            r#"{"start": "09:00", "end": "17:00", "tz": "UTC", "mode": "defer"}"#,
+        )
+        .unwrap();
+        let quiet_hours = config.quiet_hours.unwrap();
+        assert!(quiet_hours.contains(at("2026-10-17T12:00:00Z")));
+        assert!(!quiet_hours.contains(at("2026-10-17T20:00:00Z")));
+        assert!(quiet_hours.defers());
+
+        assert!(parse(r#"{"start": "25:00", "end": "07:00"}"#).is_err());
+        assert!(parse(r#"{"start": "22:00", "end": "07:00", "tz": "Mars"}"#)
+            .is_err());
+        assert!(
+            parse(r#"{"start": "22:00", "end": "07:00", "mode": "drop"}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_set_email_addresses() {
+        let mut config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "smtp": {
+                    "from": "digest@example.com",
+                    "to": "team@example.com",
+                    "subject": "Digest",
+                    "host": "localhost",
+                    "username": "",
+                    "password": ""
+                }
+            }"#,
+        )
+        .unwrap();
+
+        config
+            .set_email_addresses(None, Some("Me <me@example.com>"))
+            .unwrap();
+        let email = &config.smtp.as_ref().unwrap().email;
+        assert_eq!(email.from, "digest@example.com", "From must be kept");
+        assert_eq!(email.to, "Me <me@example.com>");
+
+        let error = config
+            .set_email_addresses(Some("not an address"), None)
+            .unwrap_err();
+        assert!(error.to_string().contains("not an address"), "{error}");
+        assert_eq!(config.smtp.unwrap().email.from, "digest@example.com");
+    }
+
+    #[test]
+    fn test_smtp_without_credentials() {
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "smtp": {
+                    "from": "digest@example.com",
+                    "to": "team@example.com",
+                    "subject": "Digest",
+                    "host": "relay.internal",
+                    "username": null
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let smtp = config.smtp.unwrap();
+        assert_eq!(smtp.username, None);
+        assert_eq!(smtp.password, None);
+    }
+
+    #[test]
+    fn test_error_path() {
+        let error = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "smtp": {"from": "a@example.com", "to": "b@example.com"}
+            }"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(
+            error.starts_with("smtp: missing field"),
+            "The error must point at the field path: {error}",
+        );
+
+        let error = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [{"value": 42}]
+            }"#,
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(
+            error.starts_with("filters[0].value: invalid type"),
+            "The error must point at the field path: {error}",
+        );
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let config = AppConfig::from_reader(
+            r#"{"purge_after_days": 7, "blacklisted_domains": [], "filters": []}"#
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(config.purge_after_days, 7);
+
+        let error = AppConfig::from_reader(r#"{"filters": []}"#.as_bytes())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with("Invalid config from stdin: "),
+            "Wrong error: {error}",
+        );
+        let error = AppConfig::from_file("/nonexistent/config.json")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with(
+                "Cannot read config file /nonexistent/config.json: "
+            ),
+            "Wrong error: {error}",
+        );
+    }
+
+    #[test]
+    fn test_filters_file() {
+        let file_name = std::env::temp_dir()
+            .join(format!("hnd-filters-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(
+            &file_name,
+            r#"[{"value": "rust"}, {"value": "crypto", "mode": "exclude"}]"#,
+        )
+        .unwrap();
+
+        let config = AppConfig::from_str(
+            format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [{{"value": "python"}}],
+                    "filters_file": {file_name:?}
+                }}"#
+            )
+            .as_str(),
+        );
+        std::fs::write(&file_name, "not json").unwrap();
+        let invalid = AppConfig::from_str(
+            format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [],
+                    "filters_file": {file_name:?}
+                }}"#
+            )
+            .as_str(),
+        );
+        std::fs::remove_file(&file_name).unwrap();
+
+        let values: Vec<String> = config
+            .unwrap()
+            .filters
+            .into_iter()
+            .map(|f| f.value)
+            .collect();
+        assert_eq!(values, vec!["python", "rust", "crypto"]);
+        assert!(invalid.is_err(), "Invalid filters file must be an error");
+
+        let missing = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "filters_file": "/nonexistent/filters.json"
+            }"#,
+        );
+        assert!(missing.is_err(), "Missing filters file must be an error");
+    }
+
+    #[test]
+    fn test_rss_sources_dir() {
+        let dir = std::env::temp_dir()
+            .join(format!("hnd-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file_name, contents) in [
+            ("b.url", "https://example.com/b.xml\nFeed B\n"),
+            ("a.url", "https://example.com/a.xml"),
+            ("inline.url", "https://example.com/inline.xml\nDuplicate"),
+            ("empty.url", "\n"),
+            ("notes.txt", "https://example.com/notes.xml"),
+        ] {
+            std::fs::write(dir.join(file_name), contents).unwrap();
+        }
+
+        let config = AppConfig::from_str(
+            format!(
+                r#"{{
+                    "purge_after_days": 7,
+                    "blacklisted_domains": [],
+                    "filters": [],
+                    "rss_sources": [{{
+                        "url": "https://example.com/inline.xml",
+                        "name": "Inline"
+                    }}],
+                    "rss_sources_dir": {:?}
+                }}"#,
+                dir.to_string_lossy()
+            )
+            .as_str(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let sources: Vec<(String, String)> = config
+            .unwrap()
+            .get_rss_sources()
+            .into_iter()
+            .map(|source| (source.name, source.url))
+            .collect();
+        let expected = [
+            ("Inline", "https://example.com/inline.xml"),
+            ("a", "https://example.com/a.xml"),
+            ("Feed B", "https://example.com/b.xml"),
+        ]
+        .map(|(name, url)| (name.to_string(), url.to_string()));
+        assert_eq!(
+            sources, expected,
+            "The .url files must be added in order, without the duplicates",
+        );
+
+        let missing = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [],
+                "rss_sources_dir": "/nonexistent/feeds"
+            }"#,
+        );
+        assert!(missing.is_err(), "Missing sources dir must be an error");
+    }
+
+    #[test]
+    fn test_rss_sources_priority() {
+        let source = |name: &str, priority| RssSource {
+            name: name.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let config = AppConfig {
+            rss_sources: Some(vec![
+                source("Low", Some(-1)),
+                source("Default", None),
+                source("High", Some(10)),
+                source("Zero", Some(0)),
+            ]),
+            ..Default::default()
+        };
+
+        let names: Vec<String> = config
+            .get_rss_sources()
+            .into_iter()
+            .map(|source| source.name)
+            .collect();
+        assert_eq!(names, vec!["High", "Default", "Zero", "Low"]);
+    }
+
+    #[test]
+    /// 0 items per source is unlimited, not an empty database
+    fn test_max_records_per_source() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.get_max_records_per_source(), None);
+        config.max_records_per_source = Some(0);
+        assert_eq!(config.get_max_records_per_source(), None);
+        config.max_records_per_source = Some(100);
+        assert_eq!(config.get_max_records_per_source(), Some(100));
+    }
+
+    #[test]
+    fn test_source_subjects() {
+        let source = |name: &str, subject: Option<&str>| RssSource {
+            name: name.to_string(),
+            subject: subject.map(ToString::to_string),
+            ..Default::default()
+        };
+        let mut config = AppConfig {
+            rss_sources: Some(vec![
+                source("Habr", Some("Habr articles")),
+                source("Lobsters", None),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.get_source_subjects(),
+            HashMap::from([("Habr".to_string(), "Habr articles".to_string())])
+        );
+
+        config.hn_subject = Some("Top stories".to_string());
+        let subjects = config.get_source_subjects();
+        assert_eq!(subjects.len(), 2);
+        assert_eq!(subjects["HackerNews"], "Top stories");
+    }
+
+    #[test]
+    /// The printed config shows the secrets as `***` and keeps the rest
+    fn test_redacted_json() {
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 7,
+                "blacklisted_domains": [],
+                "filters": [{"value": "rust"}],
+                "smtp": {
+                    "host": "smtp.example.com",
+                    "username": "user",
+                    "password": "hunter2",
+                    "from": "from@example.com",
+                    "to": "to@example.com",
+                    "subject": "Digest"
+                },
+                "telegram": {"token": "123:abc", "chat_id": "42"},
+                "rss_sources": [{
+                    "url": "https://example.com/feed.xml",
+                    "name": "Example",
+                    "headers": {"Authorization": "Bearer xyz"}
+                }]
+            }"#,
+        )
+        .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&config.to_redacted_json()).unwrap();
+
+        assert_eq!(json["smtp"]["password"], "***");
+        assert_eq!(json["telegram"]["token"], "***");
+        assert_eq!(json["rss_sources"][0]["headers"]["Authorization"], "***");
+        assert_eq!(json["smtp"]["username"], "user");
+        assert_eq!(json["smtp"]["subject"], "Digest");
+        assert_eq!(json["filters"][0]["value"], "rust");
+        assert_eq!(json["purge_after_days"], 7);
+    }
+
+    #[test]
+    fn test_source_headers() {
+        std::env::set_var("HND_TEST_FEED_TOKEN", "secret");
+        let source = RssSource {
+            headers: Some(HashMap::from([
+                (
+                    "Authorization".to_string(),
+                    "Bearer ${HND_TEST_FEED_TOKEN}".to_string(),
+                ),
+                ("X-Plain".to_string(), "as is".to_string()),
+            ])),
+            ..Default::default()
+        };
+        let headers = source.get_headers().unwrap();
+        assert_eq!(headers["Authorization"], "Bearer secret");
+        assert_eq!(headers["X-Plain"], "as is");
+
+        let source = RssSource {
+            headers: Some(HashMap::from([(
+                "Authorization".to_string(),
+                "Bearer ${HND_TEST_MISSING_VAR}".to_string(),
+            )])),
+            ..Default::default()
+        };
+        assert!(source.get_headers().is_err(), "Missing variable accepted");
+        assert!(RssSource::default().get_headers().unwrap().is_empty());
+    }
 }
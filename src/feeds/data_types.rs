@@ -1,17 +1,33 @@
 use serde::Deserialize;
+use url::Url;
 
 #[derive(Clone, Deserialize)]
 pub struct FeedItem {
     pub id: i64,
     pub title: String,
-    // pub description: String,
-    pub guid: String,
+    /// The item's full content, or its description if there is none; may
+    /// be HTML
+    pub description: String,
+    /// The item's link or permalink guid, whichever is a web URL first;
+    /// empty if neither
+    pub url: String,
+    /// The item's publication time; 0 if unknown
     pub created_at: i64,
     // pub categories: Vec<String>,
 }
 
 impl FeedItem {
-    pub fn from(item: &rss::Item) -> FeedItem {
+    /// Convert an RSS item into a feed item. The ID is the number at the end
+    /// of the guid (a URL path or a tag URI), or a hash of the guid without
+    /// one, or of the link without a guid (see `item_id`). Items with neither
+    /// are malformed, so `None` is returned for them instead of an item with
+    /// a zero ID that could collide with others. The publication time is
+    /// taken from the `date_field` element if given, or from the first of
+    /// `DATE_FIELDS` the item has otherwise.
+    pub fn from(
+        item: &rss::Item,
+        date_field: Option<&str>,
+    ) -> Option<FeedItem> {
         // let categories = item
         //     .categories()
         //     .iter()
@@ -21,20 +37,187 @@ impl FeedItem {
             Some(g) => g.value().to_string(),
             None => String::new(),
         };
-        let id = guid
-            .trim_end_matches('/')
-            .split('/')
-            .next_back()
-            .unwrap_or_default()
-            .parse()
-            .unwrap_or_default();
-        Self {
+        let id = item_id(&guid, item.link().unwrap_or_default())?;
+        // Many feeds put an opaque ID into the guid, so the link is preferred.
+        // A guid with `isPermaLink="false"` is only the ID to dedup the item
+        // by, even if it looks like a URL
+        let is_permalink = item.guid().is_some_and(rss::Guid::is_permalink);
+        let url = match item.link().map(str::trim) {
+            Some(link) if is_web_url(link) => link.to_string(),
+            _ if is_permalink && is_web_url(&guid) => guid,
+            _ => String::new(),
+        };
+        Some(Self {
             id,
-            guid,
+            url,
             title: item.title().unwrap_or("").to_string(),
-            created_at: 0,
-            // description: item.description().unwrap_or("").to_string(),
+            created_at: item_timestamp(item, date_field),
+            description: item
+                .content()
+                .or(item.description())
+                .unwrap_or_default()
+                .to_string(),
             // categories,
+        })
+    }
+}
+
+/// The date elements to take the item's publication time from, in the order
+/// of preference
+const DATE_FIELDS: [&str; 3] = ["pubDate", "dc:date", "atom:updated"];
+
+/// Get the value of the item's date element: `pubDate`, `dc:date` or any
+/// namespaced extension element, like `atom:updated`
+fn item_date<'a>(item: &'a rss::Item, field: &str) -> Option<&'a str> {
+    match field {
+        "pubDate" => item.pub_date(),
+        "dc:date" => item
+            .dublin_core_ext()
+            .and_then(|dc| dc.dates().first())
+            .map(String::as_str),
+        _ => {
+            let (prefix, name) = field.split_once(':')?;
+            item.extensions().get(prefix)?.get(name)?.first()?.value()
         }
     }
 }
+
+/// Get the item's publication time as a timestamp from the given date
+/// element, or the first one the item has; 0 if there is no valid date
+fn item_timestamp(item: &rss::Item, date_field: Option<&str>) -> i64 {
+    let date = match date_field {
+        Some(field) => {
+            let date = item_date(item, field);
+            if date.is_none() {
+                eprintln!(
+                    "No {field} date in item {:?}",
+                    item.guid().map(rss::Guid::value)
+                );
+            }
+            date
+        }
+        None => DATE_FIELDS.iter().find_map(|field| item_date(item, field)),
+    };
+    let Some(date) = date.map(str::trim) else {
+        return 0;
+    };
+
+    // RSS dates are RFC 2822 ones, Dublin Core and Atom use RFC 3339
+    chrono::DateTime::parse_from_rfc2822(date)
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(date))
+        .map_or_else(
+            |_| {
+                eprintln!(
+                    "Invalid date {date:?} in item {:?}",
+                    item.guid().map(rss::Guid::value)
+                );
+                0
+            },
+            |date| date.timestamp(),
+        )
+}
+
+/// A JSON Feed (<https://jsonfeed.org>) document; only the items are used
+#[derive(Deserialize)]
+pub struct JsonFeed {
+    pub items: Vec<JsonFeedItem>,
+}
+
+/// An item of a JSON Feed document
+#[derive(Deserialize)]
+pub struct JsonFeedItem {
+    /// A string by the spec, but some feeds put a number there
+    pub id: serde_json::Value,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub external_url: Option<String>,
+    /// RFC 3339 date
+    pub date_published: Option<String>,
+    pub content_text: Option<String>,
+    pub content_html: Option<String>,
+}
+
+impl JsonFeedItem {
+    /// Get the item's ID as a string, whether it is a string or a number
+    pub fn id_string(&self) -> String {
+        match &self.id {
+            serde_json::Value::String(id) => id.clone(),
+            id => id.to_string(),
+        }
+    }
+}
+
+impl FeedItem {
+    /// Convert a JSON Feed item into a feed item. Like with RSS, the ID is
+    /// the number at the end of the item's `id`, or a hash of the `id` (or
+    /// of the `url` if the `id` is empty) otherwise.
+    pub fn from_json(item: &JsonFeedItem) -> Option<FeedItem> {
+        let guid = item.id_string();
+        let id = item_id(&guid, item.url.as_deref().unwrap_or_default())?;
+        let url = [item.url.as_deref(), item.external_url.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(str::trim)
+            .chain(std::iter::once(guid.as_str()))
+            .find(|url| is_web_url(url))
+            .unwrap_or_default()
+            .to_string();
+        Some(Self {
+            id,
+            url,
+            title: item.title.clone().unwrap_or_default(),
+            description: item
+                .content_text
+                .clone()
+                .or_else(|| item.content_html.clone())
+                .unwrap_or_default(),
+            created_at: item
+                .date_published
+                .as_deref()
+                .and_then(|date| {
+                    chrono::DateTime::parse_from_rfc3339(date.trim()).ok()
+                })
+                .map_or(0, |date| date.timestamp()),
+        })
+    }
+}
+
+/// Get the item's ID from its guid or, if it has none, its link: the number
+/// at the end of the guid if there is one, a hash of the guid or the link
+/// otherwise (slugs, UUIDs, `urn:` guids). The hashed IDs are negative, so
+/// they never collide with the numeric ones. `None` without both
+fn item_id(guid: &str, link: &str) -> Option<i64> {
+    let guid = guid.trim();
+    if let Some(id) = id_from_guid(guid) {
+        return Some(id);
+    }
+    [guid, link.trim()]
+        .into_iter()
+        .find(|key| !key.is_empty())
+        .map(hash_id)
+}
+
+/// Hash the key into a negative ID, stable across the runs and the builds:
+/// 64-bit FNV-1a, unlike `DefaultHasher` which may change between releases
+fn hash_id(key: &str) -> i64 {
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    i64::try_from(hash >> 1).map_or(-1, |id| -id - 1)
+}
+
+/// Get the numeric ID at the end of a guid: the last segment of a URL path
+/// or of a tag URI
+fn id_from_guid(guid: &str) -> Option<i64> {
+    guid.trim_end_matches('/')
+        .split(['/', ':'])
+        .next_back()
+        .unwrap_or_default()
+        .parse()
+        .ok()
+}
+
+/// Check if the value is an HTTP(S) URL; tag URIs and such parse as URLs too
+fn is_web_url(value: &str) -> bool {
+    Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
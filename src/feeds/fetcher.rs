@@ -1,16 +1,23 @@
-use regex::Regex;
+use futures::{stream, StreamExt};
 use rss::Channel;
+use std::collections::HashSet;
 
 use crate::{
+    common::{
+        check_links, deduplicate_run, deduplicate_titles, dump_raw_items,
+        is_missing_url, is_recently_sent, is_seen_domain, sort_stable,
+    },
     config::{AppConfig, RssSource},
-    DigestItem, Fetch, Filters, Storage,
+    html_to_text, read_time_minutes, CompiledFilter, Delivery, DigestItem,
+    Fetch, Filters, RunOptions, RunStats, SkipReason, Storage,
 };
 
-use super::prelude::FeedItem;
+use super::prelude::{FeedItem, JsonFeed};
 
 pub struct RssFetcher {
     config: AppConfig,
-    filters: Vec<Regex>,
+    client: reqwest::Client,
+    filters: Vec<CompiledFilter>,
     storage: Storage,
 }
 
@@ -20,111 +27,439 @@ impl RssFetcher {
     pub fn new(config: &AppConfig, storage: Storage) -> RssFetcher {
         Self {
             config: config.clone(),
+            client: config.get_http_client(),
             filters: Filters::compile(&config.filters),
             storage,
         }
     }
 
+    /// Pull the feeds concurrently, keeping the sources order. `None` is
+    /// given for the feeds skipped when stopping or out of the request budget
+    async fn pull_sources(
+        &self,
+        sources: &[RssSource],
+        options: &RunOptions,
+    ) -> Vec<Option<Result<(Vec<DigestItem>, i64), String>>> {
+        // The requests are spaced out from the start of the pulling, as the
+        // concurrent ones would otherwise wait out the delay all together
+        let fetch_delay = self.config.get_fetch_delay();
+        let started_at = tokio::time::Instant::now();
+        stream::iter(sources.iter().enumerate())
+            .map(|(n, source)| async move {
+                if n > 0 && !fetch_delay.is_zero() {
+                    let n = u32::try_from(n).unwrap_or(u32::MAX);
+                    tokio::time::sleep_until(
+                        started_at + fetch_delay.saturating_mul(n),
+                    )
+                    .await;
+                }
+                if options.is_stopping()
+                    || !options.take_request(self.config.max_requests_per_run)
+                {
+                    return None;
+                }
+                let run_started_at = chrono::Utc::now().timestamp();
+                Some(
+                    self.pull_feed_items(source)
+                        .await
+                        .map(|items| (items, run_started_at))
+                        .map_err(|e| e.to_string()),
+                )
+            })
+            .buffered(self.config.get_concurrency())
+            .collect()
+            .await
+    }
+
     async fn pull_feed_items(
         &self,
-        source_url: &str,
-        reverse: bool,
+        source: &RssSource,
     ) -> Result<Vec<DigestItem>, Box<dyn std::error::Error>> {
-        let content = reqwest::get(source_url).await?.bytes().await?;
-        let channel = Channel::read_from(&content[..])?;
-        let news_items: Vec<FeedItem> =
-            channel.items().iter().map(FeedItem::from).collect();
-
-        let mut items = Vec::new();
-        for item in news_items {
-            if self.keep_item(&item.title.clone(), reverse) {
-                items.push(DigestItem {
+        let source_url = &source.url;
+        let headers = source.get_headers()?;
+        let mut request = self.client.get(source_url);
+        if !headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("accept"))
+        {
+            request = request.header(
+                reqwest::header::ACCEPT,
+                self.config.get_feed_accept(source),
+            );
+        }
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        let is_json_feed = is_json_feed(&response);
+        // Shown when the body is not a feed, e.g. an HTML page
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("none")
+            .to_string();
+        let content = response.bytes().await?;
+
+        let news_items: Vec<FeedItem> = if is_json_feed {
+            let feed: JsonFeed =
+                serde_json::from_slice(&content).map_err(|e| {
+                    format!(
+                        "Not a JSON feed (content type {content_type}): {e}"
+                    )
+                })?;
+            feed.items
+                .iter()
+                .filter_map(|item| {
+                    let feed_item = FeedItem::from_json(item);
+                    if feed_item.is_none() {
+                        eprintln!(
+                            "Skipping malformed item from {source_url}: id {:?}",
+                            item.id_string()
+                        );
+                    }
+                    feed_item
+                })
+                .collect()
+        } else {
+            let channel = Channel::read_from(&content[..]).map_err(|e| {
+                format!("Not an RSS feed (content type {content_type}): {e}")
+            })?;
+            channel
+                .items()
+                .iter()
+                .filter_map(|item| {
+                    let feed_item =
+                        FeedItem::from(item, source.date_field.as_deref());
+                    if feed_item.is_none() {
+                        eprintln!(
+                            "Skipping malformed item from {source_url}: guid {:?}",
+                            item.guid().map(rss::Guid::value)
+                        );
+                    }
+                    feed_item
+                })
+                .collect()
+        };
+
+        let show_read_time = self.config.show_read_time == Some(true);
+        Ok(news_items
+            .into_iter()
+            .map(|item| {
+                let body = html_to_text(&item.description);
+                DigestItem {
                     id: item.id,
                     news_title: item.title,
-                    news_url: item.guid,
+                    news_url: item.url,
                     created_at: item.created_at,
-                });
-            }
-        }
-
-        Ok(items)
+                    read_time: if show_read_time {
+                        read_time_minutes(&body)
+                    } else {
+                        None
+                    },
+                    body: Some(body).filter(|body| !body.is_empty()),
+                    ..Default::default()
+                }
+            })
+            .collect())
     }
 
-    /// Fetch the latest news from the Habr API
+    /// Fetch the latest news from the Habr API. Return the digest of new items
+    /// fetched and the reasons the other new items were skipped for. `run`
+    /// pulls the feeds concurrently instead, so this one is for the tests
+    #[cfg(test)]
     async fn fetch(
         &mut self,
         source: &RssSource,
-        reverse: bool,
-    ) -> Result<Vec<DigestItem>, Box<dyn std::error::Error>> {
+        options: &RunOptions,
+    ) -> Result<(Vec<DigestItem>, Vec<SkipReason>), Box<dyn std::error::Error>>
+    {
+        let run_started_at = chrono::Utc::now().timestamp();
+        let prefetched_items = self.pull_feed_items(source).await?;
+        self.digest_items(source, prefetched_items, run_started_at, options)
+    }
+
+    /// Compile the digest of the new items pulled from the feed and store
+    /// them. Return the digest and the reasons the other new items were
+    /// skipped for
+    fn digest_items(
+        &mut self,
+        source: &RssSource,
+        mut prefetched_items: Vec<DigestItem>,
+        run_started_at: i64,
+        options: &RunOptions,
+    ) -> Result<(Vec<DigestItem>, Vec<SkipReason>), Box<dyn std::error::Error>>
+    {
         let mut digest = Vec::new();
-        let prefetched_items =
-            self.pull_feed_items(&source.url, reverse).await?;
+        let mut skipped = Vec::new();
+        let mut skip_reasons = Vec::new();
+        let mut raw_items = Vec::new();
+        let last_run = self.storage.get_last_run(&source.name)?;
+        let mut run_domains = HashSet::new();
+        for item in &mut prefetched_items {
+            item.source = self.source_label(&source.name);
+        }
         let items_ids: Vec<i64> =
             prefetched_items.iter().map(|item| item.id).collect();
 
-        // Get the items that are not already in the database
-        self.storage
-            .get_ids_to_pull(&source.name, items_ids)
-            // Compile a digest from the items that are not in the database yet
-            .iter()
-            .for_each(|id| {
-                let item = prefetched_items.iter().find(|item| item.id == *id);
-                if let Some(item) = item {
-                    digest.push(item.clone());
+        // Get the items that are not already in the database and compile
+        // a digest from those of them that pass the checks and the filters
+        for id in self.storage.get_ids_to_pull(&source.name, items_ids)? {
+            let Some(item) = prefetched_items.iter().find(|item| item.id == id)
+            else {
+                continue;
+            };
+
+            // Skip blacklisted domains, items without a usable URL, with too
+            // short titles, created before the last run or in other
+            // languages, but store them in the database
+            let skip_reason = if self.is_blacklisted(&item.news_url) {
+                Some(SkipReason::Blacklisted)
+            } else if is_missing_url(&item.news_url) {
+                Some(SkipReason::MissingUrl)
+            } else if self.is_short_title(&item.news_title) {
+                Some(SkipReason::ShortTitle)
+            } else if self.is_before_last_run(item.created_at, last_run) {
+                Some(SkipReason::BeforeLastRun)
+            } else if self
+                .is_other_language(&item.news_title, item.body.as_deref())
+            {
+                Some(SkipReason::Language)
+            } else if !self.keep_item_with_body(
+                &item.news_title,
+                item.body.as_deref(),
+                &source.name,
+                options.reverse,
+            ) {
+                Some(SkipReason::FilteredOut)
+            } else if is_recently_sent(
+                &mut self.storage,
+                &item.news_url,
+                self.config.get_sent_since(),
+            )? {
+                Some(SkipReason::RecentlySent)
+            } else if self.config.only_new_domains == Some(true)
+                && is_seen_domain(
+                    &mut self.storage,
+                    &item.news_url,
+                    &mut run_domains,
+                )?
+            {
+                Some(SkipReason::SeenDomain)
+            } else {
+                None
+            };
+            raw_items.push(self.raw_item(item, &source.name, skip_reason));
+            match skip_reason {
+                None => digest.push(DigestItem {
+                    category: self.match_category_with_body(
+                        &item.news_title,
+                        item.body.as_deref(),
+                        &source.name,
+                        options.reverse,
+                    ),
+                    ..item.clone()
+                }),
+                // Filtered out items are not stored
+                Some(SkipReason::FilteredOut) => {
+                    skip_reasons.push(SkipReason::FilteredOut);
                 }
-            });
+                Some(reason) => {
+                    skip_reasons.push(reason);
+                    skipped.push(item.clone());
+                }
+            }
+        }
 
-        // Store the news items in the database
-        self.storage.store_feed_items(&source.name, &digest)?;
+        if let Some(file_name) = &options.dump_raw {
+            dump_raw_items(file_name, &raw_items)?;
+        }
+
+        if !options.no_store {
+            // Store the skipped news items in the database
+            self.storage.store_feed_items(
+                &source.name,
+                &skipped,
+                &options.run_id,
+            )?;
+            // Store the news items in the database
+            self.storage.store_feed_items(
+                &source.name,
+                &digest,
+                &options.run_id,
+            )?;
+            if self.config.get_sent_since().is_some() {
+                self.storage.store_sent_urls(&source.name, &digest)?;
+            }
+            self.storage.set_last_run(&source.name, run_started_at)?;
+        }
 
-        Ok(digest)
+        Ok((digest, skip_reasons))
     }
 }
 
+/// Check if the feed is a JSON Feed rather than RSS, by its content type or,
+/// if the server sends a generic one, by the `.json` extension of its URL
+fn is_json_feed(response: &reqwest::Response) -> bool {
+    let is_json_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.starts_with("application/feed+json")
+                || value.starts_with("application/json")
+        });
+    let has_json_extension = std::path::Path::new(response.url().path())
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+    is_json_type || has_json_extension
+}
+
 impl Fetch for RssFetcher {
     async fn run(
         &mut self,
-        reverse: bool,
-    ) -> Result<usize, Box<dyn std::error::Error>> {
-        let mut total_fetched = 0;
-        for source in self.config.rss_sources.clone().unwrap_or_default() {
-            let digest = self.fetch(&source, reverse).await?;
+        options: &RunOptions,
+    ) -> Result<Vec<RunStats>, Box<dyn std::error::Error>> {
+        let mut stats = Vec::new();
+        // Titles of the items digested from the previous sources of the run
+        let mut seen_titles = Vec::new();
+        let sources = self.config.get_rss_sources();
+
+        // The feeds are pulled concurrently, but digested and stored one by
+        // one in the sources order, as the storage is not shared
+        let pulled = self.pull_sources(&sources, options).await;
+
+        for (source, pulled) in sources.iter().zip(pulled) {
+            let (items, run_started_at) = match pulled {
+                Some(Ok(pulled)) => {
+                    self.storage.reset_source_failures(&source.name)?;
+                    pulled
+                }
+                Some(Err(e)) => {
+                    // A failed feed must not keep the others from the digest;
+                    // the failures in a row are counted for --prune-sources
+                    eprintln!("Could not fetch the {} feed: {e}", source.name);
+                    self.storage.record_source_failure(&source.name, &e)?;
+                    stats.push(RunStats::failed(&source.name, &e));
+                    continue;
+                }
+                None => {
+                    let reason = if options.is_stopping() {
+                        "Stopping"
+                    } else {
+                        "Request budget reached"
+                    };
+                    eprintln!("{reason}: skipping the {} feed", source.name);
+                    continue;
+                }
+            };
+            let (mut digest, skip_reasons) =
+                self.digest_items(source, items, run_started_at, options)?;
+            if self.config.get_dedup_scope() == "run" {
+                digest = deduplicate_run(&source.name, digest, options);
+            }
+            if let Some(threshold) = self.get_title_similarity() {
+                digest =
+                    deduplicate_titles(&digest, &mut seen_titles, threshold);
+            }
+            if self.config.check_links == Some(true) {
+                digest = check_links(
+                    &self.client,
+                    digest,
+                    self.config.get_concurrency(),
+                    self.config.drop_dead_links == Some(true),
+                )
+                .await;
+            }
+            if options.sort_stable {
+                sort_stable(&mut digest);
+            }
             // Send an email with the digest if it's not empty
-            if !digest.is_empty() {
-                // send the digest to the email address in the config, if given
-                self.config
+            if !digest.is_empty() && !options.count_only {
+                // Keep the digest to resend it with --resend-last if needed
+                if !options.no_store {
+                    self.storage.set_last_digest(&source.name, &digest)?;
+                }
+                // send the digest to the email address in the config, if given;
+                // a failed delivery must not keep the other feeds from theirs
+                let delivery = match self
+                    .config
                     .get_sender()
-                    .send_digest(&source.name, &digest)
-                    .await?;
-                total_fetched += digest.len();
+                    .send_digest(
+                        &source.name,
+                        &digest,
+                        self.config.max_digest_items,
+                    )
+                    .await
+                {
+                    Ok(delivery) => delivery,
+                    Err(e) => {
+                        eprintln!(
+                            "Could not send the {} digest: {e}",
+                            source.name
+                        );
+                        stats.push(RunStats::failed(
+                            &source.name,
+                            &e.to_string(),
+                        ));
+                        continue;
+                    }
+                };
+                // Keep the digest held back by the quiet hours for the next run
+                if delivery == Delivery::Deferred && !options.no_store {
+                    self.storage.defer_digest(&source.name, &digest)?;
+                }
             }
+            stats.push(RunStats::new(
+                &source.name,
+                digest.len(),
+                &skip_reasons,
+            ));
         }
-        Ok(total_fetched)
+        Ok(stats)
     }
 
-    fn get_filters(&self) -> &Vec<Regex> {
+    fn get_filters(&self) -> &Vec<CompiledFilter> {
         &self.filters
     }
+
+    fn get_config(&self) -> &AppConfig {
+        &self.config
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{AppConfig, RssFetcher};
-    use crate::{feeds::prelude::FeedItem, Fetch, ItemFilter, Storage};
+    use super::{AppConfig, RssFetcher, RssSource};
+    use crate::{
+        feeds::prelude::FeedItem, DigestItem, Fetch, ItemFilter, RunOptions,
+        SkipReason, Storage,
+    };
     use tokio::test;
 
+    /// A filter keeping every item; without any filters, none are kept
+    fn keep_all() -> Vec<ItemFilter> {
+        vec![ItemFilter {
+            value: ".".to_string(),
+            ..Default::default()
+        }]
+    }
+
     #[test]
     async fn test_filter_fetched() {
         let config = AppConfig {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "rust,python".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![],
+            ..Default::default()
         };
 
         // Filter with direct filtering first
@@ -136,25 +471,25 @@ mod test {
             FeedItem {
                 id: 123,
                 title: "Python is a programming language".to_string(),
-                guid: "https://example.com/items/123".to_string(),
+                url: "https://example.com/items/123".to_string(),
                 created_at: 0,
-                // description: String::from("Some description"),
+                description: String::from("Some description"),
                 // categories: vec![String::from("Python")],
             },
             FeedItem {
                 id: 202,
                 title: "Rust is cool".to_string(),
-                guid: "https://example.com/items/202".to_string(),
+                url: "https://example.com/items/202".to_string(),
                 created_at: 0,
-                // description: String::from("Some description"),
+                description: String::from("Some description"),
                 // categories: vec![String::from("Rust")],
             },
             FeedItem {
                 id: 303,
                 title: "1C is not cool".to_string(),
-                guid: "https://example.com/items/303".to_string(),
+                url: "https://example.com/items/303".to_string(),
                 created_at: 0,
-                // description: String::from("Some description"),
+                description: String::from("Some description"),
                 // categories: vec![String::from("1C")],
             },
         ];
@@ -162,7 +497,11 @@ mod test {
         assert_eq!(
             pulled_items
                 .iter()
-                .filter(|i| fetcher.keep_item(&i.title.clone(), reverse))
+                .filter(|i| fetcher.keep_item(
+                    &i.title.clone(),
+                    "Habr",
+                    reverse
+                ))
                 .count(),
             2,
             "Filter/keep check failed",
@@ -175,10 +514,1241 @@ mod test {
         assert_eq!(
             pulled_items
                 .iter()
-                .filter(|i| fetcher.keep_item(&i.title.clone(), reverse))
+                .filter(|i| fetcher.keep_item(
+                    &i.title.clone(),
+                    "Habr",
+                    reverse
+                ))
                 .count(),
             1,
             "Reverse filter/keep check failed",
         );
     }
+
+    #[test]
+    /// The items without a numeric guid get a hashed ID: of the guid, or of
+    /// the link without one; only the items with neither are skipped
+    async fn test_skip_malformed_items() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/202</guid>
+                        </item>
+                        <item>
+                            <title>Rust 1.84.0 released</title>
+                            <guid>https://blog.example.com/2025/01/09/Rust-1.84.0.html</guid>
+                        </item>
+                        <item>
+                            <title>Rust with a UUID guid</title>
+                            <guid isPermaLink="false">3f2b8c1e-9a4d-4e6f-b1a2-7c8d9e0f1a2b</guid>
+                        </item>
+                        <item>
+                            <title>Rust with a URN guid</title>
+                            <guid isPermaLink="false">urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a</guid>
+                        </item>
+                        <item>
+                            <title>Rust without a guid</title>
+                            <link>https://example.com/posts/rust-no-guid</link>
+                        </item>
+                        <item>
+                            <title>Rust without a guid and a link</title>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let config = AppConfig {
+            filters: vec![ItemFilter {
+                value: "rust".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let fetcher = RssFetcher::new(&config, storage);
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            ..Default::default()
+        };
+        let items = fetcher.pull_feed_items(&source).await.unwrap();
+        feed_mock.assert();
+
+        let titles: Vec<&str> =
+            items.iter().map(|item| item.news_title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Rust is cool",
+                "Rust 1.84.0 released",
+                "Rust with a UUID guid",
+                "Rust with a URN guid",
+                "Rust without a guid",
+            ],
+            "Only the items without a guid and a link must be skipped",
+        );
+        assert_eq!(items[0].id, 202, "Numeric guids must be kept as IDs");
+        assert!(
+            items[1..].iter().all(|item| item.id < 0),
+            "Hashed IDs must not collide with the numeric ones",
+        );
+        let ids: std::collections::HashSet<i64> =
+            items.iter().map(|item| item.id).collect();
+        assert_eq!(ids.len(), items.len(), "Hashed IDs must differ");
+
+        let pulled_again = fetcher.pull_feed_items(&source).await.unwrap();
+        let ids_again: Vec<i64> =
+            pulled_again.iter().map(|item| item.id).collect();
+        let ids: Vec<i64> = items.iter().map(|item| item.id).collect();
+        assert_eq!(ids, ids_again, "Hashed IDs must be stable");
+    }
+
+    #[test]
+    /// Items with tag-URI guids, numeric or not, link to their `<link>`;
+    /// those without a usable URL and those from blacklisted domains are
+    /// skipped but stored
+    async fn test_feed_item_urls() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust with a link</title>
+                            <link>https://example.com/posts/rust</link>
+                            <guid isPermaLink="false">tag:example.com,2024:101</guid>
+                        </item>
+                        <item>
+                            <title>Rust without a link</title>
+                            <guid isPermaLink="false">tag:example.com,2024:102</guid>
+                        </item>
+                        <item>
+                            <title>Rust from a blacklisted domain</title>
+                            <link>https://www.blacklisted.com/rust</link>
+                            <guid isPermaLink="false">tag:example.com,2024:103</guid>
+                        </item>
+                        <item>
+                            <title>Rust with a URL guid</title>
+                            <guid>https://example.com/items/104</guid>
+                        </item>
+                        <item>
+                            <title>Rust with a slug guid</title>
+                            <link>https://example.com/posts/rust-slug</link>
+                            <guid isPermaLink="false">tag:example.com,2024:rust-slug</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: vec![ItemFilter {
+                value: "rust".to_string(),
+                ..Default::default()
+            }],
+            blacklisted_domains: vec!["www.blacklisted.com".to_string()],
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let (digest, skipped) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+        feed_mock.assert();
+
+        let urls: Vec<&str> =
+            digest.iter().map(|item| item.news_url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/posts/rust",
+                "https://example.com/items/104",
+                "https://example.com/posts/rust-slug",
+            ],
+            "Item URLs are wrong",
+        );
+        assert_eq!(
+            skipped,
+            vec![SkipReason::MissingUrl, SkipReason::Blacklisted],
+            "Skipped items are wrong",
+        );
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("Example", vec![101, 102, 103, 104])
+                .unwrap()
+                .is_empty(),
+            "Skipped items must be stored",
+        );
+    }
+
+    #[test]
+    /// A filter scoped to other sources doesn't affect the feed's items
+    async fn test_source_scoped_filters() {
+        let config = AppConfig {
+            filters: vec![
+                ItemFilter {
+                    title: Some("Rust".to_string()),
+                    value: "rust".to_string(),
+                    sources: Some(vec!["hackernews".to_string()]),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    title: Some("Go".to_string()),
+                    value: "\\bgo\\b".to_string(),
+                    sources: Some(vec!["habr".to_string()]),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    value: "crypto".to_string(),
+                    mode: Some("exclude".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let fetcher = RssFetcher::new(&config, storage);
+        let kept = |source: &str| -> Vec<&str> {
+            ["Rust news", "Go news", "Go crypto news"]
+                .into_iter()
+                .filter(|title| fetcher.keep_item(title, source, false))
+                .collect()
+        };
+
+        assert_eq!(
+            kept("Habr"),
+            vec!["Go news"],
+            "Only the Habr filters and the unscoped ones must apply",
+        );
+        assert_eq!(kept("hackernews"), vec!["Rust news"]);
+        assert_eq!(
+            fetcher.match_category("Go news", "Habr", false).as_deref(),
+            Some("Go"),
+        );
+        assert_eq!(fetcher.match_category("Go news", "Other", false), None);
+    }
+
+    #[test]
+    /// With the `run` dedup scope, a URL digested by a source is dropped from
+    /// the digests of the later sources of the run; with `persistent`, the
+    /// URLs digested by the earlier runs are skipped with no time limit
+    async fn test_dedup_scope() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        for (path, id) in [("/first", 601), ("/second", 602)] {
+            server.mock(|when, then| {
+                when.method(GET).path(path);
+                then.status(200)
+                    .header("content-type", "application/rss+xml")
+                    .body(format!(
+                        r#"<?xml version="1.0"?>
+                        <rss version="2.0"><channel>
+                            <title>Example</title>
+                            <item>
+                                <title>The same Rust post</title>
+                                <link>https://example.com/posts/same</link>
+                                <guid>https://example.com/items/{id}</guid>
+                            </item>
+                        </channel></rss>"#
+                    ));
+            });
+        }
+        let source = |name: &str, path: &str| RssSource {
+            url: server.url(path),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let fetch = |dedup_scope: &str| {
+            let config = AppConfig {
+                filters: keep_all(),
+                rss_sources: Some(vec![
+                    source("First", "/first"),
+                    source("Second", "/second"),
+                ]),
+                dedup_scope: Some(dedup_scope.to_string()),
+                ..Default::default()
+            };
+            let storage =
+                Storage::new(Storage::establish_connection(":memory:"));
+            RssFetcher::new(&config, storage)
+        };
+        let options = RunOptions {
+            count_only: true,
+            ..Default::default()
+        };
+
+        let stats = fetch("source").run(&options).await.unwrap();
+        assert_eq!(stats[1].fetched, 1, "Sources must not share the URLs");
+        let stats = fetch("run").run(&options).await.unwrap();
+        assert_eq!(stats[0].fetched, 1, "The first source must keep it");
+        assert_eq!(stats[1].fetched, 0, "The URL must be dropped in the run");
+
+        let mut fetcher = fetch("persistent");
+        let sent_item = DigestItem {
+            news_url: "https://example.com/posts/same".to_string(),
+            ..Default::default()
+        };
+        fetcher
+            .storage
+            .store_sent_urls("hackernews", &[sent_item])
+            .unwrap();
+        let (digest, skipped) = fetcher
+            .fetch(&source("First", "/first"), &RunOptions::default())
+            .await
+            .unwrap();
+        assert!(digest.is_empty(), "A URL sent ever must be skipped");
+        assert_eq!(skipped, vec![SkipReason::RecentlySent]);
+    }
+
+    #[test]
+    /// With `suppress_recently_sent_days`, the items which URLs were digested
+    /// recently, by any source, are skipped, and the digested URLs are
+    /// recorded
+    async fn test_suppress_recently_sent() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust sent by HN</title>
+                            <link>https://example.com/posts/sent/</link>
+                            <guid>https://example.com/items/101</guid>
+                        </item>
+                        <item>
+                            <title>Rust not sent yet</title>
+                            <link>https://example.com/posts/new</link>
+                            <guid>https://example.com/items/102</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            suppress_recently_sent_days: Some(7),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::new(Storage::establish_connection(":memory:"));
+        let sent_item = DigestItem {
+            news_url: "https://example.com/posts/sent?utm_source=hn"
+                .to_string(),
+            ..Default::default()
+        };
+        storage.store_sent_urls("hackernews", &[sent_item]).unwrap();
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let (digest, skipped) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![102], "A recently sent URL must be skipped");
+        assert_eq!(skipped, vec![SkipReason::RecentlySent]);
+        assert!(
+            fetcher
+                .storage
+                .is_sent_since("https://example.com/posts/new", 0)
+                .unwrap(),
+            "The digested URLs must be recorded",
+        );
+    }
+
+    #[test]
+    /// A guid is only taken for the URL if it is a permalink; with
+    /// `isPermaLink="false"` it is just the ID, and the link is used
+    async fn test_guid_permalink() {
+        let channel = rss::Channel::read_from(
+            r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel>
+                <title>Example</title>
+                <item>
+                    <title>Permalink guid</title>
+                    <guid isPermaLink="true">https://example.com/items/101</guid>
+                </item>
+                <item>
+                    <title>ID guid with a link</title>
+                    <link>https://example.com/posts/102</link>
+                    <guid isPermaLink="false">https://example.com/ids/102</guid>
+                </item>
+                <item>
+                    <title>ID guid without a link</title>
+                    <guid isPermaLink="false">https://example.com/ids/103</guid>
+                </item>
+                <item>
+                    <title>Opaque guid with a link</title>
+                    <link>https://example.com/posts/opaque</link>
+                    <guid isPermaLink="false">a8f3e2c1b7d94f06</guid>
+                </item>
+            </channel></rss>"#
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let mut items: Vec<(i64, String)> = channel
+            .items()
+            .iter()
+            .filter_map(|item| FeedItem::from(item, None))
+            .map(|item| (item.id, item.url))
+            .collect();
+        // The opaque guid is only the dedup key, hashed into the ID
+        let (opaque_id, opaque_url) = items.pop().unwrap();
+        assert!(opaque_id < 0, "Opaque guid must be hashed");
+        assert_eq!(opaque_url, "https://example.com/posts/opaque");
+        assert_eq!(
+            items,
+            vec![
+                (101, "https://example.com/items/101".to_string()),
+                (102, "https://example.com/posts/102".to_string()),
+                (103, String::new()),
+            ],
+            "Only permalink guids must be used as URLs",
+        );
+    }
+
+    #[test]
+    /// With `languages` set, the items reliably detected to be in another
+    /// language are skipped; the too short titles to tell are kept
+    async fn test_languages() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>How we moved our database to the cloud without losing a single record</title>
+                            <guid>https://example.com/items/401</guid>
+                        </item>
+                        <item>
+                            <title>Как мы перенесли базу данных в облако и не потеряли ни одной записи</title>
+                            <guid>https://example.com/items/402</guid>
+                        </item>
+                        <item>
+                            <title>Rust 2.0</title>
+                            <guid>https://example.com/items/403</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            languages: Some(vec!["eng".to_string()]),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let (digest, skipped) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![401, 403], "Only the Russian item is skipped");
+        assert_eq!(skipped, vec![SkipReason::Language]);
+    }
+
+    #[test]
+    /// With `match_field` set to `body` or `both`, the filters are matched
+    /// against the description too, so an item mentioning the keyword only
+    /// there is kept
+    async fn test_match_field() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>This week's news</title>
+                            <description>All about &lt;b&gt;Rust&lt;/b&gt;</description>
+                            <guid>https://example.com/items/301</guid>
+                        </item>
+                        <item>
+                            <title>Other news</title>
+                            <description>Gardening tips</description>
+                            <guid>https://example.com/items/302</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+
+        for (match_field, expected) in [
+            (None, vec![]),
+            (Some("title"), vec![]),
+            (Some("body"), vec![301]),
+            (Some("both"), vec![301]),
+        ] {
+            let config = AppConfig {
+                filters: vec![ItemFilter {
+                    title: Some("Rust".to_string()),
+                    value: "rust".to_string(),
+                    ..Default::default()
+                }],
+                match_field: match_field.map(str::to_string),
+                ..Default::default()
+            };
+            let storage =
+                Storage::new(Storage::establish_connection(":memory:"));
+            let mut fetcher = RssFetcher::new(&config, storage);
+
+            let (digest, _) = fetcher
+                .fetch(&source, &RunOptions::default())
+                .await
+                .unwrap();
+            let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+            assert_eq!(ids, expected, "Wrong items kept by {match_field:?}");
+            assert!(
+                digest
+                    .iter()
+                    .all(|item| item.category.as_deref() == Some("Rust")),
+                "The body match must give the category",
+            );
+        }
+    }
+
+    #[test]
+    /// Items with titles shorter than `min_title_len` are skipped but stored
+    async fn test_skip_short_titles() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust 2.0</title>
+                            <guid>https://example.com/items/201</guid>
+                        </item>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/202</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            min_title_len: Some(10),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let (digest, skipped) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+        feed_mock.assert();
+
+        assert_eq!(digest.len(), 1, "Short titles must be skipped");
+        assert_eq!(digest[0].id, 202, "Wrong item kept");
+        assert_eq!(skipped, vec![SkipReason::ShortTitle], "Wrong skip reason");
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("Example", vec![201, 202])
+                .unwrap()
+                .is_empty(),
+            "Skipped items must be stored",
+        );
+    }
+
+    #[test]
+    /// Every fetched item is dumped with its status and the matched filter
+    async fn test_dump_raw_items() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/201</guid>
+                        </item>
+                        <item>
+                            <title>Go is cool</title>
+                            <guid>https://example.com/items/202</guid>
+                        </item>
+                        <item>
+                            <title>This week's news</title>
+                            <description>All about Rust</description>
+                            <guid>https://example.com/items/203</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let file_name = std::env::temp_dir()
+            .join(format!("hnd-dump-raw-{}.ndjson", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: vec![ItemFilter {
+                value: "rust".to_string(),
+                ..Default::default()
+            }],
+            match_field: Some("both".to_string()),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+        let options = RunOptions {
+            dump_raw: Some(file_name.clone()),
+            ..Default::default()
+        };
+
+        let (digest, _) = fetcher.fetch(&source, &options).await.unwrap();
+        assert_eq!(digest.len(), 2, "Dumping must not affect the digest");
+
+        let contents = std::fs::read_to_string(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3, "All fetched items must be dumped");
+        assert_eq!(lines[0]["id"], 201);
+        assert_eq!(lines[0]["kept"], true);
+        assert_eq!(lines[0]["matched_filter"], "rust");
+        assert_eq!(lines[1]["news_title"], "Go is cool");
+        assert_eq!(lines[1]["kept"], false);
+        assert_eq!(lines[1]["skip_reason"], "filtered out");
+        assert!(lines[1]["matched_filter"].is_null());
+        assert_eq!(lines[2]["kept"], true);
+        assert_eq!(
+            lines[2]["matched_filter"], "rust",
+            "A body match must be shown too",
+        );
+    }
+
+    #[test]
+    /// Redirect chains are followed up to `max_redirects` hops
+    async fn test_max_redirects() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/a");
+            then.status(301).header("location", server.url("/b"));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/b");
+            then.status(302).header("location", server.url("/rss"));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/202</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let pull = |max_redirects| {
+            let config = AppConfig {
+                max_redirects,
+                ..Default::default()
+            };
+            let storage =
+                Storage::new(Storage::establish_connection(":memory:"));
+            let source = RssSource {
+                url: server.url("/a"),
+                ..Default::default()
+            };
+            async move {
+                RssFetcher::new(&config, storage)
+                    .pull_feed_items(&source)
+                    .await
+                    .is_ok()
+            }
+        };
+
+        assert!(pull(None).await, "Default redirect policy failed");
+        assert!(pull(Some(2)).await, "Redirects within the limit failed");
+        assert!(!pull(Some(1)).await, "Redirects over the limit followed");
+        assert!(!pull(Some(0)).await, "Redirect must fail with 0 allowed");
+    }
+
+    #[test]
+    /// With `only_since_last_run`, items published before the previous run
+    /// are skipped even if their IDs are new, and the run marker moves on
+    async fn test_only_since_last_run() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Old Rust news</title>
+                            <guid>https://example.com/items/201</guid>
+                            <pubDate>Tue, 14 Nov 2023 22:00:00 +0000</pubDate>
+                        </item>
+                        <item>
+                            <title>New Rust news</title>
+                            <guid>https://example.com/items/202</guid>
+                            <pubDate>Wed, 15 Nov 2023 22:00:00 +0000</pubDate>
+                        </item>
+                        <item>
+                            <title>Undated Rust news</title>
+                            <guid>https://example.com/items/203</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            only_since_last_run: Some(true),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+        fetcher
+            .storage
+            .set_last_run("Example", 1_700_000_000)
+            .unwrap();
+
+        let (digest, skipped) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![202, 203], "Old items must be skipped");
+        assert_eq!(skipped, vec![SkipReason::BeforeLastRun]);
+        assert!(
+            fetcher.storage.get_last_run("Example").unwrap().unwrap()
+                > 1_700_000_000,
+            "The run marker must be updated",
+        );
+    }
+
+    #[test]
+    async fn test_source_headers() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/rss")
+                .header("authorization", "Bearer token");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/202</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            headers: Some(std::collections::HashMap::from([(
+                "Authorization".to_string(),
+                "Bearer token".to_string(),
+            )])),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let fetcher = RssFetcher::new(&AppConfig::default(), storage);
+
+        let items = fetcher.pull_feed_items(&source).await.unwrap();
+        feed_mock.assert();
+        assert_eq!(items.len(), 1, "Authenticated feed not pulled");
+    }
+
+    #[test]
+    /// The feeds are requested with the RSS-friendly `Accept` header, and a
+    /// body which is not a feed fails with its content type
+    async fn test_feed_accept() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss").header(
+                "accept",
+                "application/rss+xml, application/atom+xml, \
+                application/xml;q=0.9, */*;q=0.8",
+            );
+            then.status(200).header("content-type", "text/html").body(
+                r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust is cool</title>
+                            <guid>https://example.com/items/203</guid>
+                        </item>
+                    </channel></rss>"#,
+            );
+        });
+        let html_mock = server.mock(|when, then| {
+            when.method(GET).path("/html").header("accept", "text/html");
+            then.status(200)
+                .header("content-type", "text/html; charset=utf-8")
+                .body("<!DOCTYPE html><html><body>Hi</body></html>");
+        });
+
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let fetcher = RssFetcher::new(&AppConfig::default(), storage);
+
+        let source = RssSource {
+            url: server.url("/rss"),
+            ..Default::default()
+        };
+        let items = fetcher.pull_feed_items(&source).await.unwrap();
+        feed_mock.assert();
+        assert_eq!(items.len(), 1, "RSS served as text/html not parsed");
+
+        let source = RssSource {
+            url: server.url("/html"),
+            accept: Some("text/html".to_string()),
+            ..Default::default()
+        };
+        let Err(error) = fetcher.pull_feed_items(&source).await else {
+            panic!("HTML page pulled as a feed");
+        };
+        html_mock.assert();
+        assert!(
+            error.to_string().contains("text/html; charset=utf-8"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    /// JSON Feed documents go through the same pipeline as the RSS ones
+    async fn test_json_feed() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/feed");
+            then.status(200)
+                .header("content-type", "application/feed+json")
+                .body(
+                    r#"{
+                        "version": "https://jsonfeed.org/version/1.1",
+                        "title": "Example",
+                        "items": [
+                            {
+                                "id": "https://example.com/items/301",
+                                "title": "Rust in a JSON Feed",
+                                "url": "https://example.com/posts/rust",
+                                "date_published": "2023-11-15T22:00:00Z"
+                            },
+                            {
+                                "id": 302,
+                                "title": "Rust with a numeric id",
+                                "external_url": "https://example.org/rust"
+                            },
+                            {
+                                "id": "b5a2c9f0-4e1d-4c3b-9f8a-6d7e8f9a0b1c",
+                                "title": "Rust with a UUID id",
+                                "url": "https://example.com/posts/rust-uuid"
+                            },
+                            {
+                                "id": "https://example.com/posts/rust-slug",
+                                "title": "Rust with a slug id"
+                            },
+                            {
+                                "id": "",
+                                "title": "Rust without an id and a url"
+                            }
+                        ]
+                    }"#,
+                );
+        });
+
+        let source = RssSource {
+            url: server.url("/feed"),
+            name: "Example".to_string(),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(
+            &AppConfig {
+                filters: keep_all(),
+                ..Default::default()
+            },
+            storage,
+        );
+
+        let (digest, _) = fetcher
+            .fetch(&source, &RunOptions::default())
+            .await
+            .unwrap();
+        feed_mock.assert();
+
+        assert_eq!(digest.len(), 4, "Malformed items must be skipped");
+        assert_eq!(digest[0].id, 301);
+        assert_eq!(digest[0].news_url, "https://example.com/posts/rust");
+        assert_eq!(digest[0].created_at, 1_700_085_600);
+        assert_eq!(digest[1].id, 302);
+        assert_eq!(digest[1].news_url, "https://example.org/rust");
+        assert!(digest[2].id < 0, "Non-numeric ids must be hashed");
+        assert_eq!(digest[2].news_url, "https://example.com/posts/rust-uuid");
+        assert!(digest[3].id < 0, "Non-numeric ids must be hashed");
+        assert_ne!(digest[2].id, digest[3].id);
+        assert_eq!(digest[3].news_url, "https://example.com/posts/rust-slug");
+    }
+
+    #[test]
+    /// The items' time comes from the source's `date_field` if it is set,
+    /// from the first date element the item has otherwise
+    async fn test_date_field() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"
+                        xmlns:dc="http://purl.org/dc/elements/1.1/"
+                        xmlns:atom="http://www.w3.org/2005/Atom">
+                    <channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust news</title>
+                            <guid>https://example.com/items/401</guid>
+                            <pubDate>Tue, 14 Nov 2023 22:00:00 +0000</pubDate>
+                            <dc:date>2023-11-15T22:00:00Z</dc:date>
+                            <atom:updated>2023-11-16T22:00:00Z</atom:updated>
+                        </item>
+                        <item>
+                            <title>More Rust news</title>
+                            <guid>https://example.com/items/402</guid>
+                            <dc:date>2023-11-15T22:00:00Z</dc:date>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let fetch = |date_field: Option<&str>| {
+            let source = RssSource {
+                url: server.url("/rss"),
+                name: "Example".to_string(),
+                date_field: date_field.map(ToString::to_string),
+                ..Default::default()
+            };
+            async move {
+                let storage =
+                    Storage::new(Storage::establish_connection(":memory:"));
+                let mut fetcher = RssFetcher::new(
+                    &AppConfig {
+                        filters: keep_all(),
+                        ..Default::default()
+                    },
+                    storage,
+                );
+                let (digest, _) = fetcher
+                    .fetch(&source, &RunOptions::default())
+                    .await
+                    .unwrap();
+                digest
+                    .iter()
+                    .map(|item| item.created_at)
+                    .collect::<Vec<i64>>()
+            }
+        };
+
+        assert_eq!(fetch(None).await, vec![1_699_999_200, 1_700_085_600]);
+        assert_eq!(
+            fetch(Some("dc:date")).await,
+            vec![1_700_085_600, 1_700_085_600],
+        );
+        assert_eq!(
+            fetch(Some("atom:updated")).await,
+            vec![1_700_172_000, 0],
+            "Items without the date element must get 0",
+        );
+    }
+
+    #[test]
+    /// The feeds are pulled concurrently, and a failed one doesn't keep the
+    /// others from being digested
+    async fn test_concurrent_sources() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let broken_mock = server.mock(|when, then| {
+            when.method(GET).path("/broken");
+            then.status(500).body("Internal Server Error");
+        });
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust news</title>
+                            <guid>https://example.com/items/501</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+
+        let source = |name: &str, path: &str| RssSource {
+            url: server.url(path),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            rss_sources: Some(vec![
+                source("Broken", "/broken"),
+                source("Example", "/rss"),
+            ]),
+            concurrency: Some(2),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let stats = fetcher
+            .run(&RunOptions {
+                count_only: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        broken_mock.assert();
+        feed_mock.assert();
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].source, "Broken");
+        assert!(stats[0].error.is_some(), "The broken feed must fail");
+        assert_eq!(stats[1].source, "Example");
+        assert_eq!(stats[1].fetched, 1, "The other feed must be digested");
+    }
+
+    #[test]
+    /// A failed delivery of one feed's digest doesn't keep the other feeds
+    /// from being digested and reported
+    async fn test_failed_delivery() {
+        use crate::config::{EmailConfig, MaildirConfig};
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let feed_mock = server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                        <item>
+                            <title>Rust news</title>
+                            <guid>https://example.com/items/601</guid>
+                        </item>
+                    </channel></rss>"#,
+                );
+        });
+        let empty_mock = server.mock(|when, then| {
+            when.method(GET).path("/empty");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Empty</title>
+                    </channel></rss>"#,
+                );
+        });
+
+        // The Maildir can't be created under a regular file
+        let not_a_dir = std::env::temp_dir()
+            .join(format!("hnd-failed-delivery-{}", std::process::id()));
+        std::fs::write(&not_a_dir, "").unwrap();
+        let source = |name: &str, path: &str| RssSource {
+            url: server.url(path),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let config = AppConfig {
+            filters: keep_all(),
+            rss_sources: Some(vec![
+                source("Example", "/rss"),
+                source("Empty", "/empty"),
+            ]),
+            maildir: Some(MaildirConfig {
+                path: not_a_dir.join("maildir").to_string_lossy().to_string(),
+                email: EmailConfig {
+                    from: "digest@example.com".to_string(),
+                    to: "me@example.com".to_string(),
+                    subject: "digest".to_string(),
+                    source_subjects: std::collections::HashMap::new(),
+                    subject_prefix: None,
+                    subject_suffix: None,
+                    plaintext_only: None,
+                    derive_text_from_html: None,
+                },
+            }),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let stats = fetcher.run(&RunOptions::default()).await.unwrap();
+        std::fs::remove_file(&not_a_dir).unwrap();
+        feed_mock.assert();
+        empty_mock.assert();
+
+        assert_eq!(stats.len(), 2, "Both sources must be reported");
+        assert_eq!(stats[0].source, "Example");
+        assert!(stats[0].error.is_some(), "The delivery must fail");
+        assert_eq!(stats[1].source, "Empty");
+        assert!(stats[1].error.is_none(), "The other feed must not fail");
+    }
+
+    #[test]
+    /// `fetch_delay_ms` spaces out the feed requests, concurrent or not
+    async fn test_fetch_delay() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/rss");
+            then.status(200)
+                .header("content-type", "application/rss+xml")
+                .body(
+                    r#"<?xml version="1.0"?>
+                    <rss version="2.0"><channel>
+                        <title>Example</title>
+                    </channel></rss>"#,
+                );
+        });
+
+        let sources = (1..=3)
+            .map(|n| RssSource {
+                url: server.url("/rss"),
+                name: format!("Example {n}"),
+                ..Default::default()
+            })
+            .collect();
+        let config = AppConfig {
+            rss_sources: Some(sources),
+            concurrency: Some(2),
+            fetch_delay_ms: Some(100),
+            ..Default::default()
+        };
+        let storage = Storage::new(Storage::establish_connection(":memory:"));
+        let mut fetcher = RssFetcher::new(&config, storage);
+
+        let started = std::time::Instant::now();
+        let stats = fetcher.run(&RunOptions::default()).await.unwrap();
+        assert_eq!(stats.len(), 3, "All feeds must be pulled");
+        assert!(
+            started.elapsed() >= std::time::Duration::from_millis(200),
+            "Feed requests must be spaced out",
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use crate::DigestItem;
+use crate::{html_to_text, read_time_minutes, truncate_text, DigestItem};
 use serde::Deserialize;
 
 /// A news item that has been fetched from the API
@@ -7,10 +7,82 @@ pub struct JsonNewsItem {
     id: i64,
     title: Option<String>,
     url: Option<String>,
+    text: Option<String>,
+    /// `story`, `job`, `poll`, `comment` or `pollopt`
+    #[serde(rename = "type")]
+    item_type: Option<String>,
+    #[serde(default)]
     time: i64,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    dead: bool,
+    /// IDs of the item's comments, in the ranked order
+    #[serde(default)]
+    kids: Vec<i64>,
+    #[serde(default)]
+    score: i64,
 }
 
 impl JsonNewsItem {
+    /// Check if the item was deleted or killed, so there is nothing to digest
+    pub fn is_deleted_or_dead(&self) -> bool {
+        self.deleted || self.dead
+    }
+
+    /// Check if the item is of one of the given types; items without a type
+    /// are let through
+    pub fn is_of_type(&self, types: &[String]) -> bool {
+        self.item_type
+            .as_ref()
+            .is_none_or(|item_type| types.contains(item_type))
+    }
+
+    /// Get the item's score, the number of its upvotes; 0 if it has none
+    pub fn score(&self) -> i64 {
+        self.score
+    }
+
+    /// Get the IDs of the first `count` comments of the item
+    pub fn comment_ids(&self, count: usize) -> Vec<i64> {
+        self.kids.iter().take(count).copied().collect()
+    }
+
+    /// Get the plain text of a comment, truncated to `max_len` characters;
+    /// `None` for the deleted and dead comments
+    pub fn comment_text(&self, max_len: usize) -> Option<String> {
+        if self.is_deleted_or_dead() {
+            return None;
+        }
+        self.text
+            .as_deref()
+            .map(html_to_text)
+            .filter(|text| !text.is_empty())
+            .map(|text| truncate_text(&text, max_len))
+    }
+
+    /// Get the plain text of the item's text; `None` without a text
+    pub fn plain_text(&self) -> Option<String> {
+        self.text
+            .as_deref()
+            .map(html_to_text)
+            .filter(|text| !text.is_empty())
+    }
+
+    /// Estimate the minutes to read the item's text; `None` without a text
+    pub fn read_time(&self) -> Option<usize> {
+        self.text
+            .as_deref()
+            .map(html_to_text)
+            .and_then(|text| read_time_minutes(&text))
+    }
+
+    /// Convert to a `DigestItem` to store a skipped item: only the ID and
+    /// the time are kept
+    pub fn as_skipped_item(&self) -> DigestItem {
+        skipped_item(self.id, self.time)
+    }
+
     /// Convert to a `DigestItem` for storage
     pub fn as_digest_item(&self) -> DigestItem {
         let mut item = DigestItem {
@@ -18,6 +90,7 @@ impl JsonNewsItem {
             news_title: self.title.clone().unwrap_or_default(),
             news_url: self.url.clone().unwrap_or_default(),
             created_at: self.time,
+            ..Default::default()
         };
 
         if item.news_url.is_empty() {
@@ -27,4 +100,49 @@ impl JsonNewsItem {
 
         item
     }
+
+    /// Convert to a `DigestItem` with a snippet of the item's text, truncated
+    /// to `snippet_len` characters. Text posts (Ask HN, etc.) have no URL, so
+    /// they link to their discussion page instead.
+    pub fn as_digest_item_with_snippet(
+        &self,
+        snippet_len: usize,
+    ) -> DigestItem {
+        let snippet = self
+            .text
+            .as_deref()
+            .map(html_to_text)
+            .filter(|text| !text.is_empty())
+            .map(|text| truncate_text(&text, snippet_len));
+
+        if self.url.is_none() && snippet.is_some() {
+            return DigestItem {
+                id: self.id,
+                news_title: self.title.clone().unwrap_or_default(),
+                news_url: format!(
+                    "https://news.ycombinator.com/item?id={}",
+                    self.id
+                ),
+                created_at: self.time,
+                snippet,
+                ..Default::default()
+            };
+        }
+
+        DigestItem {
+            snippet,
+            ..self.as_digest_item()
+        }
+    }
+}
+
+/// A `DigestItem` to store a skipped item with: only the ID and the time
+pub fn skipped_item(id: i64, created_at: i64) -> DigestItem {
+    DigestItem {
+        id,
+        news_title: String::from("-"),
+        news_url: String::from("-"),
+        created_at,
+        ..Default::default()
+    }
 }
@@ -1,13 +1,25 @@
 use crate::{
-    common::{deduplicate, is_missing_url},
-    config, DigestItem, Fetch, Filters, JsonNewsItem, Regex, Storage, Url,
+    common::{
+        check_links, deduplicate, deduplicate_run, deduplicate_titles,
+        dump_raw_items, is_missing_url, is_recently_sent, is_seen_domain,
+        normalize_url, report_duplicates, sort_stable,
+    },
+    config, skipped_item, CompiledFilter, Delivery, DigestItem, Fetch, Filters,
+    JsonNewsItem, RunOptions, RunStats, SkipReason, Storage,
 };
-use config::AppConfig;
+use config::{AppConfig, DEFAULT_HN_SOURCE};
+use diesel::result::Error as DieselError;
+use futures::{stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+
+/// Max length of a comment snippet if `snippet_len` is not set
+const DEFAULT_COMMENT_LEN: usize = 200;
 
 pub struct HNFetcher {
     pub config: AppConfig,
     api_base_url: String,
-    filters: Vec<Regex>,
+    client: reqwest::Client,
+    filters: Vec<CompiledFilter>,
     storage: Storage,
 }
 
@@ -20,125 +32,509 @@ impl HNFetcher {
             config: config.clone(),
             filters: Filters::compile(&config.filters),
             api_base_url: API_BASE_URL.to_string(),
+            client: config.get_http_client(),
             storage,
         }
     }
 
     /// Fetch not previously fetched news items from the API. For that, we need to:
-    /// 1. Fetch the top stories' IDs from the API
+    /// 1. Fetch the top stories' IDs from the API, unless there are pending IDs
+    ///    left over by the previous run
     /// 2. Fetch each news item by its ID if it wasn't previously fetched; existing
     ///    news items' IDs are stored in the database
     /// 3. Apply filters to each news item
     /// 4. Store the news items in the database
-    /// 5. Return the digest of new items fetched
+    /// 5. Return the digest of new items fetched and the reasons the other
+    ///    ones were skipped for
     async fn fetch(
         &mut self,
-        reverse: bool,
-    ) -> Result<Vec<DigestItem>, Box<dyn std::error::Error>> {
+        options: &RunOptions,
+    ) -> Result<(Vec<DigestItem>, Vec<SkipReason>), Box<dyn std::error::Error>>
+    {
         let mut digest = Vec::new();
         let mut skipped = Vec::new();
+        let mut skip_reasons = Vec::new();
+        let mut raw_items = Vec::new();
 
-        let prefetched = self.prefetch().await?;
-        let ids_to_pull =
-            self.storage.get_ids_to_pull("hackernews", prefetched);
-
-        for id in ids_to_pull {
-            let news_item = &self.fetch_news_item(id).await?;
-            let digest_item = &news_item.as_digest_item();
-
-            // Skip blacklisted domains, but store the news item in the database
-            if self.is_blacklisted(&digest_item.news_url) {
-                skipped.push(DigestItem {
-                    news_title: String::from("-"),
-                    news_url: String::from("-"),
-                    created_at: digest_item.created_at,
-                    id: digest_item.id,
-                });
-                continue;
+        let run_started_at = chrono::Utc::now().timestamp();
+        // The reprocessed items were pulled by the earlier runs, so they are
+        // all taken again
+        let last_run = if options.reprocess {
+            None
+        } else {
+            self.storage.get_last_run(self.config.get_hn_source())?
+        };
+        let fetch_delay = self.config.get_fetch_delay();
+        let mut run_domains = HashSet::new();
+        let num_comments = self.config.include_comments.unwrap_or(0);
+        let mut comment_ids = HashMap::new();
+        let flush_every = self.config.flush_every.filter(|&every| every > 0);
+        let mut stored = (0, 0);
+        let mut url_scores = HashMap::new();
+        let ids_to_pull = if options.reprocess {
+            self.cached_ids()?
+        } else {
+            self.get_ids_to_pull(options).await?
+        };
+
+        for (n, &id) in ids_to_pull.iter().enumerate() {
+            // On shutdown, or with the request budget spent, keep what was
+            // pulled so far and leave the rest to the next run
+            if let Some(reason) = self.stop_reason(options) {
+                eprintln!("{reason}: {} items left", ids_to_pull.len() - n);
+                if !options.no_store {
+                    self.keep_unpulled(&ids_to_pull[n..])?;
+                }
+                break;
             }
 
-            // Skip items with missing URLs from the digest, but store them in the database
-            if is_missing_url(&digest_item.news_url) {
-                skipped.push(DigestItem {
-                    news_title: String::from("-"),
-                    news_url: String::from("-"),
-                    created_at: digest_item.created_at,
-                    id: digest_item.id,
-                });
-                continue;
+            // Persist what was processed so far, so a crash loses less
+            if flush_every.is_some_and(|every| n > 0 && n % every == 0) {
+                self.flush_items(&digest, &skipped, &mut stored, options)?;
+            }
+
+            // Be gentle with the API if asked to
+            if n > 0 && !fetch_delay.is_zero() {
+                tokio::time::sleep(fetch_delay).await;
             }
+            let news_item = &self.get_news_item(id, options.reprocess).await?;
+            let digest_item = &self.digest_item(news_item);
 
-            // Apply filters
-            if !self.keep_item(&digest_item.news_title.clone(), reverse) {
-                skipped.push(DigestItem {
-                    news_title: String::from("-"),
-                    news_url: String::from("-"),
-                    created_at: digest_item.created_at,
-                    id: digest_item.id,
-                });
+            // The skipped items are left out of the digest, but stored in
+            // the database
+            let skip_reason = self.skip_reason(
+                news_item,
+                digest_item,
+                last_run,
+                options,
+                &mut run_domains,
+            )?;
+            raw_items.push(self.raw_item(
+                digest_item,
+                "HackerNews",
+                skip_reason,
+            ));
+            if let Some(reason) = skip_reason {
+                skip_reasons.push(reason);
+                skipped.push(news_item.as_skipped_item());
                 continue;
             }
 
-            digest.push(DigestItem {
-                news_title: digest_item.news_title.clone(),
-                news_url: digest_item.news_url.clone(),
-                created_at: digest_item.created_at,
-                id: digest_item.id,
-            });
+            comment_ids.insert(id, news_item.comment_ids(num_comments));
+            let item = DigestItem {
+                category: self.match_category_with_body(
+                    &digest_item.news_title,
+                    digest_item.body.as_deref(),
+                    "hackernews",
+                    options.reverse,
+                ),
+                ..digest_item.clone()
+            };
+            // Of the submissions of the same URL, only the highest scored
+            // one is digested; the others are stored as skipped
+            if let Some(dropped) = keep_highest_scored(
+                &mut digest,
+                &mut url_scores,
+                item,
+                news_item.score(),
+                stored.0,
+            ) {
+                skip_reasons.push(SkipReason::Duplicate);
+                skipped.push(dropped);
+            }
+        }
+
+        if let Some(file_name) = &options.dump_raw {
+            dump_raw_items(file_name, &raw_items)?;
+        }
+
+        self.flush_items(&digest, &skipped, &mut stored, options)?;
+        if !options.no_store {
+            self.storage
+                .set_last_run(self.config.get_hn_source(), run_started_at)?;
+        }
+
+        if options.verbose {
+            report_duplicates("HackerNews", &digest);
+        }
+        let mut digest = deduplicate(&digest);
+        if self.config.get_dedup_scope() == "run" {
+            digest = deduplicate_run("HackerNews", digest, options);
+        }
+        if let Some(threshold) = self.get_title_similarity() {
+            digest = deduplicate_titles(&digest, &mut Vec::new(), threshold);
+        }
+        self.add_comments(&mut digest, &comment_ids, options).await;
+
+        Ok((digest, skip_reasons))
+    }
+
+    /// Fetch the given comments of the digest items, by the item ID, and add
+    /// their text to the items. The comments are fetched up to `concurrency`
+    /// at a time, or read from `raw_cache_dir` when reprocessing; the ones
+    /// that fail to load or are over the request budget are left out
+    async fn add_comments(
+        &self,
+        digest: &mut [DigestItem],
+        comment_ids: &HashMap<i64, Vec<i64>>,
+        options: &RunOptions,
+    ) {
+        let cached = options.reprocess;
+        let max_len = match self.config.snippet_len {
+            Some(len) if len > 0 => len,
+            _ => DEFAULT_COMMENT_LEN,
+        };
+        let requests: Vec<(usize, i64)> = digest
+            .iter()
+            .enumerate()
+            .flat_map(|(n, item)| {
+                comment_ids
+                    .get(&item.id)
+                    .into_iter()
+                    .flatten()
+                    .map(move |&comment_id| (n, comment_id))
+            })
+            .filter(|_| cached || self.take_request(options))
+            .collect();
+        let comments: Vec<(usize, Option<String>)> = stream::iter(requests)
+            .map(|(n, comment_id)| async move {
+                match self.get_news_item(comment_id, cached).await {
+                    Ok(comment) => (n, comment.comment_text(max_len)),
+                    Err(e) => {
+                        eprintln!("Could not fetch comment {comment_id}: {e}");
+                        (n, None)
+                    }
+                }
+            })
+            .buffered(self.config.get_concurrency())
+            .collect()
+            .await;
+        for (n, comment) in comments {
+            if let Some(text) = comment {
+                digest[n].comments.push(text);
+            }
+        }
+    }
+
+    /// Get the reason to skip the news item for, if any. Deleted and dead
+    /// items, disallowed item types (polls, comments, etc.), blacklisted
+    /// domains, items with missing URLs, too short titles, created before
+    /// the last run or in other languages are skipped, and so are the
+    /// filtered out, recently sent and seen domains' ones. The reprocessed
+    /// items are sent and seen already, so those two checks are left out
+    /// for them
+    fn skip_reason(
+        &mut self,
+        news_item: &JsonNewsItem,
+        digest_item: &DigestItem,
+        last_run: Option<i64>,
+        options: &RunOptions,
+        run_domains: &mut HashSet<String>,
+    ) -> Result<Option<SkipReason>, DieselError> {
+        Ok(if news_item.is_deleted_or_dead() {
+            Some(SkipReason::Deleted)
+        } else if !news_item.is_of_type(&self.config.get_allowed_types()) {
+            Some(SkipReason::DisallowedType)
+        } else if self.is_blacklisted(&digest_item.news_url) {
+            Some(SkipReason::Blacklisted)
+        } else if is_missing_url(&digest_item.news_url) {
+            Some(SkipReason::MissingUrl)
+        } else if self.is_short_title(&digest_item.news_title) {
+            Some(SkipReason::ShortTitle)
+        } else if self.is_before_last_run(digest_item.created_at, last_run) {
+            Some(SkipReason::BeforeLastRun)
+        } else if self.is_other_language(
+            &digest_item.news_title,
+            digest_item.body.as_deref(),
+        ) {
+            Some(SkipReason::Language)
+        } else if !self.keep_item_with_body(
+            &digest_item.news_title,
+            digest_item.body.as_deref(),
+            "hackernews",
+            options.reverse,
+        ) {
+            Some(SkipReason::FilteredOut)
+        } else if !options.reprocess
+            && is_recently_sent(
+                &mut self.storage,
+                &digest_item.news_url,
+                self.config.get_sent_since(),
+            )?
+        {
+            Some(SkipReason::RecentlySent)
+        } else if !options.reprocess
+            && self.config.only_new_domains == Some(true)
+            && is_seen_domain(
+                &mut self.storage,
+                &digest_item.news_url,
+                run_domains,
+            )?
+        {
+            Some(SkipReason::SeenDomain)
+        } else {
+            None
+        })
+    }
+
+    /// Convert the news item for the digest, with its score, a snippet of its
+    /// text if `snippet_len` is set and its reading time if `show_read_time`
+    /// is on
+    fn digest_item(&self, news_item: &JsonNewsItem) -> DigestItem {
+        DigestItem {
+            source: self.source_label("HackerNews"),
+            score: Some(news_item.score()),
+            body: news_item.plain_text(),
+            read_time: if self.config.show_read_time == Some(true) {
+                news_item.read_time()
+            } else {
+                None
+            },
+            ..match self.config.snippet_len {
+                Some(len) if len > 0 => {
+                    news_item.as_digest_item_with_snippet(len)
+                }
+                _ => news_item.as_digest_item(),
+            }
+        }
+    }
+
+    /// Store the digested news items, and the skipped ones unless
+    /// `store_skipped` is off, tagged with the run ID. The digested URLs are
+    /// recorded too if the recently sent ones are skipped
+    fn store_items(
+        &mut self,
+        digest: &[DigestItem],
+        skipped: &[DigestItem],
+        options: &RunOptions,
+    ) -> Result<(), DieselError> {
+        let hn_source = self.config.get_hn_source();
+        if self.config.store_skipped.unwrap_or(true) {
+            self.storage.store_feed_items(
+                hn_source,
+                skipped,
+                &options.run_id,
+            )?;
+        }
+        self.storage
+            .store_feed_items(hn_source, digest, &options.run_id)?;
+        if self.config.get_sent_since().is_some() {
+            self.storage
+                .store_sent_urls(self.config.get_hn_source(), digest)?;
+        }
+        Ok(())
+    }
+
+    /// Store the items added since the previous flush, `stored` keeps the
+    /// numbers of the digest and skipped items stored so far
+    fn flush_items(
+        &mut self,
+        digest: &[DigestItem],
+        skipped: &[DigestItem],
+        stored: &mut (usize, usize),
+        options: &RunOptions,
+    ) -> Result<(), DieselError> {
+        if !options.no_store {
+            self.store_items(
+                &digest[stored.0..],
+                &skipped[stored.1..],
+                options,
+            )?;
         }
+        *stored = (digest.len(), skipped.len());
+        Ok(())
+    }
+
+    /// Keep the IDs left unpulled on shutdown as pending for the next run,
+    /// ahead of the ones pending already
+    fn keep_unpulled(&mut self, ids: &[i64]) -> Result<(), DieselError> {
+        let mut pending = ids.to_vec();
+        let hn_source = self.config.get_hn_source();
+        pending.extend(self.storage.get_pending_ids(hn_source)?);
+        self.storage.set_pending_ids(hn_source, &pending)
+    }
+
+    /// Get the IDs to pull in this run. The pending IDs left over by the
+    /// previous run are pulled first; the top stories are only prefetched
+    /// once that backlog is drained. With `max_items_per_run` set, the IDs
+    /// over the limit are stored as pending for the next run, unless
+    /// `no_store` is set.
+    async fn get_ids_to_pull(
+        &mut self,
+        options: &RunOptions,
+    ) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+        let pending =
+            self.storage.get_pending_ids(self.config.get_hn_source())?;
+        let candidates = if pending.is_empty() {
+            if !self.take_request(options) {
+                eprintln!("Request budget reached: the top stories are not prefetched");
+                return Ok(Vec::new());
+            }
+            match self.prefetch().await {
+                Ok(ids) => ids,
+                // A bad API URL is a misconfiguration, not an outage
+                Err(e) if e.is_builder() => return Err(e.into()),
+                // The API is unreachable, so there is nothing new this run;
+                // the other sources still run
+                Err(e) => {
+                    eprintln!("Could not prefetch the top stories: {e}");
+                    Vec::new()
+                }
+            }
+        } else {
+            pending
+        };
+        let mut ids_to_pull = self
+            .storage
+            .get_ids_to_pull_any_list(DEFAULT_HN_SOURCE, candidates)?;
 
-        // Store the skipped news items in the database
-        self.storage.store_news_items(&skipped)?;
-        // Store the news items in the database
-        self.storage.store_news_items(&digest)?;
+        let remaining = match self.config.max_items_per_run {
+            Some(max) if ids_to_pull.len() > max => ids_to_pull.split_off(max),
+            _ => Vec::new(),
+        };
+        if !options.no_store {
+            self.storage
+                .set_pending_ids(self.config.get_hn_source(), &remaining)?;
+        }
 
-        Ok(deduplicate(&digest))
+        Ok(ids_to_pull)
     }
 
     /// Fetch the top stories' IDs from the API
-    async fn prefetch(&self) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    async fn prefetch(&self) -> Result<Vec<i64>, reqwest::Error> {
         let prefetch_url = || format!("{}/topstories.json", self.api_base_url);
-        let result = reqwest::get(prefetch_url())
+        let result = self
+            .client
+            .get(prefetch_url())
+            .send()
             .await?
+            .error_for_status()?
             .json::<Vec<i64>>()
             .await?;
 
         Ok(result)
     }
 
-    /// Fetch a single news item by its ID
+    /// Get the reason to stop pulling the items for, if any: the shutdown or
+    /// the spent request budget. Otherwise the next item's request is counted
+    fn stop_reason(&self, options: &RunOptions) -> Option<&'static str> {
+        if options.is_stopping() {
+            Some("Stopping")
+        } else if !options.reprocess && !self.take_request(options) {
+            Some("Request budget reached")
+        } else {
+            None
+        }
+    }
+
+    /// Count a request against `max_requests_per_run`; false if the budget
+    /// is spent
+    fn take_request(&self, options: &RunOptions) -> bool {
+        options.take_request(self.config.max_requests_per_run)
+    }
+
+    /// Get a single news item by its ID, from the API or, if `cached`, from
+    /// `raw_cache_dir`
+    async fn get_news_item(
+        &self,
+        id: i64,
+        cached: bool,
+    ) -> Result<JsonNewsItem, Box<dyn std::error::Error>> {
+        if cached {
+            self.cached_news_item(id)
+        } else {
+            self.fetch_news_item(id).await
+        }
+    }
+
+    /// Fetch a single news item by its ID. Its raw JSON is kept in
+    /// `raw_cache_dir`, if set, to reprocess the item later
     async fn fetch_news_item(
         &self,
         id: i64,
     ) -> Result<JsonNewsItem, Box<dyn std::error::Error>> {
         let get_item_url =
             |id| format!("{}/item/{}.json", self.api_base_url, id);
-        let result = reqwest::get(get_item_url(id))
+        let body = self
+            .client
+            .get(get_item_url(id))
+            .send()
             .await?
-            .json::<JsonNewsItem>()
+            .text()
             .await?;
+        let result = serde_json::from_str::<JsonNewsItem>(&body)?;
+        if let Some(dir) = &self.config.raw_cache_dir {
+            std::fs::create_dir_all(dir)?;
+            std::fs::write(cached_item_path(dir, id), &body)?;
+        }
 
         Ok(result)
     }
 
-    /// Check if a URL's domain is in the blacklist
-    fn is_blacklisted(&self, url: &str) -> bool {
-        if url.is_empty() {
-            return false;
-        }
+    /// Read a news item kept in `raw_cache_dir` by its ID
+    fn cached_news_item(
+        &self,
+        id: i64,
+    ) -> Result<JsonNewsItem, Box<dyn std::error::Error>> {
+        let dir = self.config.raw_cache_dir.as_deref().unwrap_or_default();
+        let path = cached_item_path(dir, id);
+        let body = std::fs::read_to_string(&path).map_err(|e| {
+            format!("Cannot read cached item {}: {e}", path.display())
+        })?;
 
-        match Url::parse(url) {
-            Ok(parsed_url) => match parsed_url.domain() {
-                Some(domain) => self
-                    .config
-                    .blacklisted_domains
-                    .iter()
-                    .any(|blacklisted_domain| domain == blacklisted_domain),
-                None => false,
-            },
-            Err(_) => false,
-        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Get the IDs of the news items kept in `raw_cache_dir`, in ascending
+    /// order; the files not named by an item ID are left out
+    fn cached_ids(&self) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+        let dir = self.config.raw_cache_dir.as_deref().unwrap_or_default();
+        let mut ids: Vec<i64> = std::fs::read_dir(dir)
+            .map_err(|e| format!("Cannot read raw_cache_dir {dir}: {e}"))?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        Ok(ids)
+    }
+}
+
+/// Path of the raw JSON of the news item in the cache directory
+fn cached_item_path(dir: &str, id: i64) -> std::path::PathBuf {
+    std::path::Path::new(dir).join(format!("{id}.json"))
+}
+
+/// Add the item to the digest, unless an item with the same normalized URL
+/// and a higher or equal score is there already. The item losing is returned
+/// to be stored as a skipped one. `url_scores` maps the digest URLs to their
+/// items' positions and scores; the first `num_stored` items of the digest
+/// are stored already, so they are never replaced.
+fn keep_highest_scored(
+    digest: &mut Vec<DigestItem>,
+    url_scores: &mut HashMap<String, (usize, i64)>,
+    item: DigestItem,
+    score: i64,
+    num_stored: usize,
+) -> Option<DigestItem> {
+    let url = normalize_url(&item.news_url);
+    let Some(&(n, best_score)) = url_scores.get(&url) else {
+        url_scores.insert(url, (digest.len(), score));
+        digest.push(item);
+        return None;
+    };
+    if score <= best_score || n < num_stored {
+        return Some(skipped_item(item.id, item.created_at));
     }
+
+    url_scores.insert(url, (n, score));
+    let dropped = std::mem::replace(&mut digest[n], item);
+    Some(skipped_item(dropped.id, dropped.created_at))
 }
 
 impl Fetch for HNFetcher {
@@ -147,34 +543,91 @@ impl Fetch for HNFetcher {
     /// If digest is not empty, send an email with the digest to the email address in the config.
     async fn run(
         &mut self,
-        reverse: bool,
-    ) -> Result<usize, Box<dyn std::error::Error>> {
-        let digest = self.fetch(reverse).await?;
+        options: &RunOptions,
+    ) -> Result<Vec<RunStats>, Box<dyn std::error::Error>> {
+        let (mut digest, skip_reasons) = self.fetch(options).await?;
+        // The reprocessed digest is built offline
+        if self.config.check_links == Some(true) && !options.reprocess {
+            digest = check_links(
+                &self.client,
+                digest,
+                self.config.get_concurrency(),
+                self.config.drop_dead_links == Some(true),
+            )
+            .await;
+        }
+        if options.sort_stable {
+            sort_stable(&mut digest);
+        }
         // Send an email with the digest if it's not empty
-        if !digest.is_empty() {
-            // send the digest to the email address in the config, if given
-            self.config
+        if !digest.is_empty() && !options.count_only {
+            // Keep the digest to resend it with --resend-last if needed
+            if !options.no_store {
+                self.storage.set_last_digest("HackerNews", &digest)?;
+            }
+            // send the digest to the email address in the config, if given;
+            // the failure is reported with the stats of the other fetchers
+            let delivery = match self
+                .config
                 .get_sender()
-                .send_digest("HackerNews", &digest)
-                .await?;
+                .send_digest(
+                    "HackerNews",
+                    &digest,
+                    self.config.max_digest_items,
+                )
+                .await
+            {
+                Ok(delivery) => delivery,
+                Err(e) => {
+                    eprintln!("Could not send the HackerNews digest: {e}");
+                    return Ok(vec![RunStats::failed(
+                        "HackerNews",
+                        &e.to_string(),
+                    )]);
+                }
+            };
+            // Keep the digest held back by the quiet hours for the next run
+            if delivery == Delivery::Deferred && !options.no_store {
+                self.storage.defer_digest("HackerNews", &digest)?;
+            }
         }
-        Ok(digest.len())
+        Ok(vec![RunStats::new(
+            "HackerNews",
+            digest.len(),
+            &skip_reasons,
+        )])
     }
 
-    fn get_filters(&self) -> &Vec<Regex> {
+    fn get_filters(&self) -> &Vec<CompiledFilter> {
         &self.filters
     }
+
+    fn get_config(&self) -> &AppConfig {
+        &self.config
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{config::AppConfig, Fetch};
+    use super::{config::AppConfig, Fetch, RunOptions, RunStats, SkipReason};
     use crate::{
-        common::{deduplicate, is_missing_url},
-        DigestItem, ItemFilter, Storage,
+        common::{
+            check_links, deduplicate, deduplicate_titles, find_duplicates,
+            is_missing_url, normalize_url, sort_stable,
+        },
+        title_similarity, DigestItem, ItemFilter, Storage,
     };
+    use std::str::FromStr;
     use tokio::test;
 
+    /// A filter keeping every item; without any filters, none are kept
+    fn keep_all() -> Vec<ItemFilter> {
+        vec![ItemFilter {
+            value: ".".to_string(),
+            ..Default::default()
+        }]
+    }
+
     #[test]
     async fn test_is_empty_url() {
         let pulled_items = [
@@ -183,18 +636,21 @@ mod test {
                 news_url: "https://example.com".to_string(),
                 created_at: 1_700_000_000,
                 id: 1,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Missing URL".to_string(),
                 news_url: String::new(),
                 created_at: 1_700_000_000,
                 id: 2,
+                ..Default::default()
             },
             DigestItem {
                 news_title: String::new(),
                 news_url: String::new(),
                 created_at: 1_700_000_000,
                 id: 3,
+                ..Default::default()
             },
         ];
 
@@ -208,6 +664,56 @@ mod test {
         );
     }
 
+    #[test]
+    /// The TLD rules are checked against the public suffix, so multi-level
+    /// suffixes like `co.uk` work; the exact domains are checked first
+    async fn test_blacklisted_tlds() {
+        let fetcher = |blacklisted: &[&str], whitelisted: Option<&[&str]>| {
+            let to_vec = |tlds: &[&str]| {
+                tlds.iter().map(ToString::to_string).collect::<Vec<_>>()
+            };
+            let config = AppConfig {
+                blacklisted_domains: vec!["spam.co.uk".to_string()],
+                blacklisted_tlds: Some(to_vec(blacklisted)),
+                whitelisted_tlds: whitelisted.map(to_vec),
+                ..Default::default()
+            };
+            crate::HNFetcher::new(
+                &config,
+                Storage::new(Storage::establish_connection(":memory:")),
+            )
+        };
+        let blacklisted = |fetcher: &crate::HNFetcher, urls: &[&str]| {
+            urls.iter()
+                .map(|url| fetcher.is_blacklisted(url))
+                .collect::<Vec<bool>>()
+        };
+        let urls = [
+            "https://example.ru/a",
+            "https://www.example.co.uk/b",
+            "https://example.uk/c",
+            "https://example.com/d",
+            "https://spam.co.uk/e",
+        ];
+
+        assert_eq!(
+            blacklisted(&fetcher(&[".ru", "co.uk"], None), &urls),
+            vec![true, true, false, false, true],
+            "A multi-level suffix must only cover itself",
+        );
+        assert_eq!(
+            blacklisted(&fetcher(&["uk"], None), &urls),
+            vec![false, true, true, false, true],
+            "A TLD must cover the suffixes under it",
+        );
+        assert_eq!(
+            blacklisted(&fetcher(&[], Some(&["co.uk", "com"])), &urls),
+            vec![true, false, true, false, true],
+            "Only the whitelisted suffixes must be let through, except the \
+            blacklisted domains",
+        );
+    }
+
     #[test]
     async fn test_is_blacklisted() {
         let pulled_items = [
@@ -216,24 +722,28 @@ mod test {
                 news_url: "https://example.com".to_string(),
                 created_at: 1_700_000_000,
                 id: 1,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Rust is awesome".to_string(),
                 news_url: "https://example.org".to_string(),
                 created_at: 1_700_000_000,
                 id: 2,
+                ..Default::default()
             },
         ];
         let config = AppConfig {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "rust".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![String::from("example.com")],
+            ..Default::default()
         };
         let fetcher = crate::HNFetcher::new(
             &config,
@@ -269,12 +779,14 @@ mod test {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "rust".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![String::from("example.com")],
+            ..Default::default()
         };
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
@@ -316,12 +828,14 @@ mod test {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "rust".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![String::from("example.com")],
+            ..Default::default()
         };
 
         let storage =
@@ -350,12 +864,14 @@ mod test {
                 news_url: "https://example.com".to_string(),
                 created_at: 1_700_000_000,
                 id: 1,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Rust is awesome".to_string(),
                 news_url: "https://example.org".to_string(),
                 created_at: 1_700_000_000,
                 id: 2,
+                ..Default::default()
             },
         ];
         // create a mock http server
@@ -371,25 +887,32 @@ mod test {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "rust".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![String::from("example.com")],
+            ..Default::default()
         };
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
         let mut fetcher = crate::HNFetcher::new(&config, storage);
         fetcher.api_base_url = expected_addr_str;
         // store the pulled items in the database to have IDs to pull
-        fetcher.storage.store_news_items(&pulled_items).unwrap();
+        fetcher
+            .storage
+            .store_news_items(&pulled_items, "run")
+            .unwrap();
 
         let prefetched = fetcher.prefetch().await.unwrap();
         prefetch_mock.assert();
 
-        let ids_to_pull =
-            fetcher.storage.get_ids_to_pull("hackernews", prefetched);
+        let ids_to_pull = fetcher
+            .storage
+            .get_ids_to_pull("hackernews", prefetched)
+            .unwrap();
         assert_eq!(ids_to_pull.len(), 3, "Pulling IDs from DB failed");
         assert_eq!(ids_to_pull, vec![3, 4, 5], "Pulling IDs from DB failed");
     }
@@ -420,7 +943,7 @@ mod test {
                     }"#,
                 );
         });
-        let news_item5_mock = server.mock(|when, then| {
+        let news_item15_mock = server.mock(|when, then| {
             when.method(GET).path("/item/15.json");
             then.status(200)
                 .header("content-type", "application/json")
@@ -438,25 +961,58 @@ mod test {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: ".*".to_string(), // match all
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![String::from("example.com")],
+            ..Default::default()
         };
 
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
         let mut fetcher = crate::HNFetcher::new(&config, storage);
         fetcher.api_base_url = expected_addr_str;
-        let num_fetched = fetcher.run(false).await.unwrap();
+        let stats = fetcher.run(&RunOptions::default()).await.unwrap();
 
         prefetch_mock.assert();
         news_item_mock.assert();
-        news_item5_mock.assert();
+        news_item15_mock.assert();
 
-        assert_eq!(num_fetched, 1, "Fetched items count is wrong");
+        assert_eq!(stats[0].fetched, 1, "Fetched items count is wrong");
+        assert_eq!(stats[0].skipped, 1, "Skipped items count is wrong");
+        assert_eq!(
+            stats[0].skipped_reasons.get(&SkipReason::MissingUrl),
+            Some(&1),
+            "Skip reasons are wrong",
+        );
+        assert_eq!(
+            RunStats::counts(&stats),
+            "HackerNews: 1\ntotal: 1",
+            "Count-only report is wrong",
+        );
+        let report: serde_json::Value = serde_json::from_str(
+            &RunStats::to_json(&stats, std::time::Duration::ZERO, &[]),
+        )
+        .unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "sources": [{
+                    "source": "HackerNews",
+                    "fetched": 1,
+                    "skipped": 1,
+                    "skipped_reasons": {"no URL": 1},
+                    "error": null,
+                }],
+                "total_sent": 1,
+                "duration_secs": 0.0,
+                "errors": [],
+            }),
+            "JSON report is wrong",
+        );
     }
 
     #[test]
@@ -485,7 +1041,7 @@ mod test {
                     }"#,
                 );
         });
-        let news_item5_mock = server.mock(|when, then| {
+        let news_item15_mock = server.mock(|when, then| {
             when.method(GET).path("/item/15.json");
             then.status(200)
                 .header("content-type", "application/json")
@@ -503,152 +1059,1337 @@ mod test {
             db_file: Some(":memory:".to_string()),
             filters: vec![ItemFilter {
                 value: "item".to_string(),
+                ..Default::default()
             }],
             smtp: None,
             telegram: None,
             rss_sources: None,
             purge_after_days: 7,
             blacklisted_domains: vec![],
+            ..Default::default()
         };
 
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
         let mut fetcher = crate::HNFetcher::new(&config, storage);
         fetcher.api_base_url = expected_addr_str;
-        let num_fetched = fetcher.run(true).await.unwrap();
+        let stats = fetcher
+            .run(&RunOptions {
+                reverse: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
 
         prefetch_mock.assert();
         news_item_mock.assert();
-        news_item5_mock.assert();
+        news_item15_mock.assert();
 
-        assert_eq!(num_fetched, 1, "Fetched items count is wrong");
+        assert_eq!(stats[0].fetched, 1, "Fetched items count is wrong");
+        assert_eq!(stats[0].skipped, 1, "Skipped items count is wrong");
     }
 
     #[test]
-    /// Test filtering items based on the filters; use simple and regex filters
-    pub async fn test_filtering_items() {
-        let pulled_items = vec![
-            DigestItem {
-                news_title: "Rust is awesome".to_string(),
-                news_url: "https://example.com".to_string(),
-                created_at: 1_700_000_000,
-                id: 1,
-            },
-            DigestItem {
-                news_title: "Rust is cool".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 2,
-            },
-            DigestItem {
-                news_title: "Rust is aweful".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 3,
-            },
-            DigestItem {
-                news_title: "Go is cool".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 4,
-            },
-            DigestItem {
-                news_title: "Dart is some thing".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 5,
-            },
-        ];
-        let mut config = AppConfig {
+    /// With `no_store` set, the digest is built but nothing is stored, so the
+    /// same items come back next run
+    async fn test_no_store() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[51, 52]");
+        });
+        for id in [51, 52] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(format!(
+                        r#"{{"id": {id}, "time": 1736908019, "title": "Item {id}", "url": "https://example.org/{id}"}}"#
+                    ));
+            });
+        }
+
+        let config = AppConfig {
             db_file: Some(":memory:".to_string()),
-            filters: vec![
-                ItemFilter {
-                    value: "cool".to_string(),
-                },
-                ItemFilter {
-                    value: "awesome".to_string(),
-                },
-            ],
-            smtp: None,
-            telegram: None,
-            rss_sources: None,
-            purge_after_days: 7,
-            blacklisted_domains: vec![],
+            filters: vec![ItemFilter {
+                value: ".*".to_string(), // match all
+                ..Default::default()
+            }],
+            max_items_per_run: Some(1),
+            ..Default::default()
         };
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
-        let fetcher = crate::HNFetcher::new(&config, storage);
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+        let options = RunOptions {
+            no_store: true,
+            ..Default::default()
+        };
 
-        assert_eq!(
-            pulled_items
-                .iter()
-                .filter(|i| fetcher.keep_item(&i.news_title, false))
-                .count(),
-            3,
-            "Filtering items agains mutiple simple filters failed",
+        for _ in 0..2 {
+            let (digest, _) = fetcher.fetch(&options).await.unwrap();
+            assert_eq!(digest.len(), 1, "Digest must be built");
+            assert_eq!(digest[0].id, 51, "The same item must come back");
+        }
+        assert!(
+            fetcher
+                .storage
+                .get_pending_ids("hackernews")
+                .unwrap()
+                .is_empty(),
+            "Pending IDs must not be stored",
         );
+    }
 
-        config.filters = vec![ItemFilter {
-            value: "some\\b".to_string(),
-        }];
+    #[test]
+    /// With `show_read_time` on, the text posts get a reading time estimate
+    /// from their word count, and the link posts without a text get none
+    async fn test_show_read_time() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[43, 44]");
+        });
+        let text = "<p>word ".repeat(450);
+        server.mock(|when, then| {
+            when.method(GET).path("/item/43.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!({
+                    "id": 43,
+                    "text": text,
+                    "time": 1_736_904_177,
+                    "title": "Ask HN: How long is this post?"
+                }));
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/44.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 44,
+                        "url": "https://example.com/44",
+                        "time": 1736904177,
+                        "title": "A link post without a text"
+                    }"#,
+                );
+        });
 
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            snippet_len: Some(30),
+            show_read_time: Some(true),
+            ..Default::default()
+        };
         let storage =
             Storage::new(Storage::establish_connection(&config.get_db_file()));
-        let fetcher = crate::HNFetcher::new(&config, storage);
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
 
-        assert_eq!(
-            pulled_items
-                .iter()
-                .filter(|i| fetcher.keep_item(&i.news_title, false))
-                .count(),
-            2,
-            "Filtering items against a regex filter failed",
-        );
+        let (digest, _) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(digest.len(), 2, "Both items must be digested");
+        assert_eq!(digest[0].read_time, Some(3), "450 words take 3 minutes");
+        assert_eq!(digest[1].read_time, None, "No text, no read time");
     }
 
     #[test]
-    /// Test filtering items based on the filters; use simple and regex filters
-    pub async fn test_filter_config_from_str() {
-        let pulled_items = vec![
-            DigestItem {
-                news_title: "So You Want to Build Your Own Data Center"
-                    .to_string(),
-                news_url: "https://example.com".to_string(),
-                created_at: 1_700_000_000,
-                id: 1,
-            },
-            DigestItem {
-                news_title: "Maze Generation: Recursive Division (2011)"
-                    .to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 2,
-            },
-            DigestItem {
-                news_title: "Swedish Exports of Ball Bearings".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 3,
-            },
-            DigestItem {
-                news_title: "Obelisks".to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 4,
-            },
-            DigestItem {
-                news_title: "Bluesky accounts add 10k followers per day"
-                    .to_string(),
-                news_url: "https://example.org".to_string(),
-                created_at: 1_700_000_000,
-                id: 5,
-            },
-        ];
-        let config = AppConfig::from_str(
-            r#"{
-                "purge_after_days": 720,
-                "db_file": ":memory:",
-                "blacklisted_domains": [
+    /// A filter with `match_field` set to `body` matches the text of the
+    /// item, but not its title; the others still match the title
+    async fn test_match_field() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[121, 122]");
+        });
+        for (id, title, text) in [
+            (
+                121,
+                "Ask HN: Any advice?",
+                "<p>I just started with <i>Rust</i>",
+            ),
+            (122, "Rust or Go?", "<p>Which one to learn first"),
+        ] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(serde_json::json!({
+                        "id": id,
+                        "time": 1_736_904_177,
+                        "title": title,
+                        "text": text,
+                        "url": format!("https://example.com/{id}"),
+                    }));
+            });
+        }
+
+        for (match_field, expected) in
+            [(None, vec![122]), (Some("body"), vec![121])]
+        {
+            let config = AppConfig {
+                db_file: Some(":memory:".to_string()),
+                filters: vec![ItemFilter {
+                    value: "rust".to_string(),
+                    match_field: match_field.map(str::to_string),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let storage = Storage::new(Storage::establish_connection(
+                &config.get_db_file(),
+            ));
+            let mut fetcher = crate::HNFetcher::new(&config, storage);
+            fetcher.api_base_url = server.base_url();
+
+            let (digest, _) =
+                fetcher.fetch(&RunOptions::default()).await.unwrap();
+            let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+            assert_eq!(ids, expected, "Wrong items kept by {match_field:?}");
+        }
+    }
+
+    #[test]
+    /// With `snippet_len` set, text posts are digested with a plain-text
+    /// snippet and a link to their discussion page
+    async fn test_text_post_snippet() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[41]");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/41.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 41,
+                        "text": "<p>If so, how &#x27;was&#x27; it like?<p>What <i>happened</i>?",
+                        "time": 1736904177,
+                        "title": "Ask HN: Have any of you become homeless?"
+                    }"#,
+                );
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![ItemFilter {
+                value: ".*".to_string(), // match all
+                ..Default::default()
+            }],
+            snippet_len: Some(30),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, _) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(digest.len(), 1, "Text post must be digested");
+        assert_eq!(
+            digest[0].news_url,
+            "https://news.ycombinator.com/item?id=41"
+        );
+        assert_eq!(
+            digest[0].snippet.as_deref(),
+            Some("If so, how 'was' it like? What…"),
+        );
+    }
+
+    #[test]
+    /// Deleted and dead items are not digested, but they are stored so they
+    /// are not fetched again
+    async fn test_skip_deleted_items() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let prefetch_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[31, 32]");
+        });
+        let deleted_mock = server.mock(|when, then| {
+            when.method(GET).path("/item/31.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": 31, "deleted": true}"#);
+        });
+        let dead_mock = server.mock(|when, then| {
+            when.method(GET).path("/item/32.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 32,
+                        "dead": true,
+                        "time": 1736908019,
+                        "title": "Item 32",
+                        "url": "https://example.org/32"
+                    }"#,
+                );
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![ItemFilter {
+                value: ".*".to_string(), // match all
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+        prefetch_mock.assert();
+        deleted_mock.assert();
+        dead_mock.assert();
+
+        assert!(digest.is_empty(), "Deleted items must not be digested");
+        assert_eq!(
+            skipped,
+            vec![SkipReason::Deleted, SkipReason::Deleted],
+            "Deleted items must be counted as skipped",
+        );
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![31, 32])
+                .unwrap()
+                .is_empty(),
+            "Deleted items must be stored",
+        );
+    }
+
+    #[test]
+    /// The items are stored under `hn_source`, and the ones stored under any
+    /// `hackernews` list are not pulled again
+    async fn test_hn_source() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[61, 62, 63]");
+        });
+        let item_mock = server.mock(|when, then| {
+            when.method(GET).path("/item/63.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 63,
+                        "type": "story",
+                        "time": 1736908019,
+                        "title": "Item 63",
+                        "url": "https://example.org/63"
+                    }"#,
+                );
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: keep_all(),
+            hn_source: Some("hackernews:top".to_string()),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let stored = |id| DigestItem {
+            id,
+            ..Default::default()
+        };
+        storage.store_news_items(&[stored(61)], "run").unwrap();
+        storage
+            .store_feed_items("hackernews:ask", &[stored(62)], "run")
+            .unwrap();
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, _) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        item_mock.assert();
+
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![63], "Items of other lists must not be pulled");
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews:top", vec![63])
+                .unwrap()
+                .is_empty(),
+            "Items must be stored under hn_source",
+        );
+        assert_eq!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![63])
+                .unwrap(),
+            vec![63],
+        );
+    }
+
+    #[test]
+    /// With `store_skipped` off, the skipped items are left out of the
+    /// database, so they are fetched again next run
+    async fn test_dont_store_skipped() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[41, 42]");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/41.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"id": 41, "deleted": true}"#);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/42.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 42,
+                        "time": 1736908019,
+                        "title": "Item 42",
+                        "url": "https://example.org/42"
+                    }"#,
+                );
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![ItemFilter {
+                value: ".*".to_string(), // match all
+                ..Default::default()
+            }],
+            store_skipped: Some(false),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(digest.len(), 1, "The live item must be digested");
+        assert_eq!(skipped, vec![SkipReason::Deleted]);
+        assert_eq!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![41, 42])
+                .unwrap(),
+            vec![41],
+            "Only the digested items must be stored",
+        );
+    }
+
+    #[test]
+    /// With `only_new_domains`, an item is digested only if no stored item
+    /// has its domain, and only the first one of a new domain in a run
+    async fn test_only_new_domains() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let item_mock = |id: i64, url: &str| {
+            let body = format!(
+                r#"{{"id": {id}, "time": 1736908019, "title": "Item {id}", "url": "{url}"}}"#
+            );
+            server.mock(move |when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(body);
+            });
+        };
+        item_mock(71, "https://example.org/a");
+        item_mock(72, "https://www.Example.org/b");
+        item_mock(73, "https://example.com/c");
+        item_mock(74, "https://example.org/d");
+        let mut top_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[71, 72]");
+        });
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            only_new_domains: Some(true),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![71], "A domain must be digested once a run");
+        assert_eq!(skipped, vec![SkipReason::SeenDomain]);
+
+        top_mock.delete();
+        top_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[73, 74]");
+        });
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+        top_mock.assert();
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![73], "A stored domain must not be digested");
+        assert_eq!(skipped, vec![SkipReason::SeenDomain]);
+    }
+
+    #[test]
+    /// The first `include_comments` comments are added to the digest items,
+    /// as plain text; the deleted ones are left out
+    async fn test_include_comments() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let item_mock = |id: i64, body: String| {
+            server.mock(move |when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(body);
+            })
+        };
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[81]");
+        });
+        item_mock(
+            81,
+            r#"{"id": 81, "time": 1736908019, "title": "Item 81",
+                "url": "https://example.org/81", "kids": [82, 83, 84, 85]}"#
+                .to_string(),
+        );
+        item_mock(
+            82,
+            r#"{"id": 82, "type": "comment", "text": "<p>Nice &amp; fast</p>"}"#
+                .to_string(),
+        );
+        item_mock(83, r#"{"id": 83, "deleted": true}"#.to_string());
+        item_mock(84, r#"{"id": 84, "text": "Third"}"#.to_string());
+        let unwanted_mock = item_mock(85, r#"{"id": 85}"#.to_string());
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            include_comments: Some(3),
+            snippet_len: Some(5),
+            concurrency: Some(2),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, _) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(digest.len(), 1);
+        assert_eq!(
+            digest[0].comments,
+            vec!["Nice…".to_string(), "Third".to_string()],
+            "Wrong comments",
+        );
+        unwanted_mock.assert_calls(0);
+    }
+
+    #[test]
+    /// Items of the types not in `allowed_types` are not digested, but they
+    /// are stored so they are not fetched again
+    async fn test_skip_disallowed_types() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[51, 52]");
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/51.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 51,
+                        "type": "poll",
+                        "time": 1736908019,
+                        "title": "Poll: Tabs or spaces?",
+                        "url": "https://example.org/51"
+                    }"#,
+                );
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/item/52.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(
+                    r#"{
+                        "id": 52,
+                        "type": "story",
+                        "time": 1736908019,
+                        "title": "Item 52",
+                        "url": "https://example.org/52"
+                    }"#,
+                );
+        });
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+
+        assert_eq!(digest.len(), 1, "Polls must not be digested");
+        assert_eq!(digest[0].id, 52, "Wrong item digested");
+        assert_eq!(skipped, vec![SkipReason::DisallowedType]);
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![51])
+                .unwrap()
+                .is_empty(),
+            "Disallowed items must be stored",
+        );
+    }
+
+    #[test]
+    /// An unreachable API means no new items rather than a failed run; a bad
+    /// API URL is still an error
+    async fn test_prefetch_unavailable() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let prefetch_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(503);
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let stats = fetcher.run(&RunOptions::default()).await.unwrap();
+        prefetch_mock.assert();
+        assert_eq!(stats[0].fetched, 0, "Nothing must be fetched");
+        assert_eq!(stats[0].skipped, 0, "Nothing must be skipped");
+
+        fetcher.api_base_url = "not a url".to_string();
+        assert!(
+            fetcher.run(&RunOptions::default()).await.is_err(),
+            "A bad API URL must be an error",
+        );
+    }
+
+    #[test]
+    /// `fetch_delay_ms` spaces out the item requests
+    async fn test_fetch_delay() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[71, 72, 73]");
+        });
+        for id in [71, 72, 73] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(format!(r#"{{"id": {id}, "deleted": true}}"#));
+            });
+        }
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            fetch_delay_ms: Some(100),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let started = std::time::Instant::now();
+        let (_, skipped) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(skipped.len(), 3, "All items must be pulled");
+        assert!(
+            started.elapsed() >= std::time::Duration::from_millis(200),
+            "Item requests must be spaced out",
+        );
+    }
+
+    #[test]
+    /// On shutdown, no more items are pulled and the rest are left pending
+    async fn test_stop_fetching() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let prefetch_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[61, 62]");
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let options = RunOptions::default();
+        options.stop();
+        let (digest, _) = fetcher.fetch(&options).await.unwrap();
+        prefetch_mock.assert();
+
+        assert!(digest.is_empty(), "No items must be pulled on shutdown");
+        assert_eq!(
+            fetcher.storage.get_pending_ids("hackernews").unwrap(),
+            vec![61, 62],
+            "The items not pulled must be left pending",
+        );
+    }
+
+    #[test]
+    /// With `max_requests_per_run` set, the items over the request budget,
+    /// which the other fetchers of the run share, are left pending
+    async fn test_max_requests_per_run() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[111, 112, 113, 114]");
+        });
+        let mut item_mocks = Vec::new();
+        for id in [111, 112, 113, 114] {
+            item_mocks.push(server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(format!(
+                        r#"{{"id": {id}, "time": 1736908019, "title": "Item {id}", "url": "https://example.com/{id}"}}"#
+                    ));
+            }));
+        }
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            max_requests_per_run: Some(4),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        // Another fetcher of the run has made a request already
+        let options = RunOptions::default();
+        assert!(options.clone().take_request(config.max_requests_per_run));
+        let (digest, _) = fetcher.fetch(&options).await.unwrap();
+
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![111, 112], "The budget must stop the pulling");
+        assert_eq!(item_mocks[2].calls() + item_mocks[3].calls(), 0);
+        assert_eq!(
+            fetcher.storage.get_pending_ids("hackernews").unwrap(),
+            vec![113, 114],
+            "The items over the budget must be left pending",
+        );
+    }
+
+    #[test]
+    /// Of the submissions of the same URL, only the highest scored one is
+    /// digested; the others are stored as skipped duplicates
+    async fn test_keep_highest_scored() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[91, 92, 93, 94]");
+        });
+        for (id, url, score) in [
+            (91, "https://example.com/same", 5),
+            (92, "https://example.com/same/?utm_source=hn", 20),
+            (93, "https://example.com/other", 1),
+            (94, "https://Example.com/same", 10),
+        ] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(serde_json::json!({
+                        "id": id,
+                        "url": url,
+                        "score": score,
+                        "time": 1_736_908_019,
+                        "title": format!("Item {id}"),
+                    }));
+            });
+        }
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        let (digest, skipped) =
+            fetcher.fetch(&RunOptions::default()).await.unwrap();
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![92, 93], "The highest score must be kept");
+        assert_eq!(skipped, vec![SkipReason::Duplicate; 2]);
+        assert!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![91, 92, 93, 94])
+                .unwrap()
+                .is_empty(),
+            "The duplicates must be stored too",
+        );
+    }
+
+    #[test]
+    /// With `flush_every` set, the items processed before a failure are
+    /// stored already, so the next run doesn't pull them again
+    async fn test_flush_every() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[81, 82, 83, 84]");
+        });
+        for id in [81, 82, 83] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .body(format!(
+                        r#"{{"id": {id}, "time": 1736908019, "title": "Item {id}"}}"#
+                    ));
+            });
+        }
+        server.mock(|when, then| {
+            when.method(GET).path("/item/84.json");
+            then.status(500);
+        });
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            flush_every: Some(2),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        assert!(
+            fetcher.fetch(&RunOptions::default()).await.is_err(),
+            "The failed item must fail the run",
+        );
+        assert_eq!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![81, 82, 83, 84])
+                .unwrap(),
+            vec![83, 84],
+            "The items processed before the last flush must be stored",
+        );
+        assert_eq!(
+            fetcher.storage.get_last_run("hackernews").unwrap(),
+            None,
+            "The failed run must not be marked",
+        );
+    }
+
+    #[test]
+    /// With `raw_cache_dir` set, the pulled items are kept to be digested
+    /// again with `--reprocess`, which applies the current filters to them
+    /// without fetching or storing anything
+    async fn test_reprocess() {
+        use httpmock::prelude::*;
+
+        let dir = std::env::temp_dir()
+            .join(format!("hnd-raw-cache-{}", std::process::id()));
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[101, 102]");
+        });
+        for (id, title) in [(101, "Rust is cool"), (102, "Go is cool")] {
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/item/{id}.json"));
+                then.status(200)
+                    .header("content-type", "application/json")
+                    .json_body(serde_json::json!({
+                        "id": id,
+                        "time": 1_736_908_019,
+                        "title": title,
+                        "url": format!("https://example.com/{id}"),
+                    }));
+            });
+        }
+
+        let config = AppConfig {
+            filters: keep_all(),
+            db_file: Some(":memory:".to_string()),
+            raw_cache_dir: Some(dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+        let (digest, _) = fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(digest.len(), 2, "Both items must be digested");
+
+        let config = AppConfig {
+            filters: vec![ItemFilter {
+                value: "rust".to_string(),
+                ..Default::default()
+            }],
+            ..config
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = "http://127.0.0.1:1".to_string();
+        let options = RunOptions {
+            reprocess: true,
+            no_store: true,
+            ..Default::default()
+        };
+        let (digest, skip_reasons) = fetcher.fetch(&options).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let ids: Vec<i64> = digest.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![101], "The current filters must be applied");
+        assert_eq!(skip_reasons, vec![SkipReason::FilteredOut]);
+        assert_eq!(
+            fetcher
+                .storage
+                .get_ids_to_pull("hackernews", vec![101, 102])
+                .unwrap(),
+            vec![101, 102],
+            "The reprocessed items must not be stored",
+        );
+    }
+
+    #[test]
+    /// With `max_items_per_run` set, the IDs over the limit are pulled by the
+    /// next run without prefetching the top stories again
+    async fn test_resume_pending_ids() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        let prefetch_mock = server.mock(|when, then| {
+            when.method(GET).path("/topstories.json");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body("[21, 22, 23]");
+        });
+        let item_mocks: Vec<_> = (21..=23)
+            .map(|id| {
+                server.mock(|when, then| {
+                    when.method(GET).path(format!("/item/{id}.json"));
+                    then.status(200)
+                        .header("content-type", "application/json")
+                        .body(format!(
+                            r#"{{"id": {id}, "time": 1736908019, "title": "Item {id}"}}"#
+                        ));
+                })
+            })
+            .collect();
+
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            max_items_per_run: Some(2),
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let mut fetcher = crate::HNFetcher::new(&config, storage);
+        fetcher.api_base_url = server.base_url();
+
+        fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert_eq!(
+            fetcher.storage.get_pending_ids("hackernews").unwrap(),
+            vec![23],
+            "The IDs over the limit must be left pending",
+        );
+
+        fetcher.fetch(&RunOptions::default()).await.unwrap();
+        assert!(
+            fetcher
+                .storage
+                .get_pending_ids("hackernews")
+                .unwrap()
+                .is_empty(),
+            "The pending IDs must be drained",
+        );
+        prefetch_mock.assert_calls(1);
+        for item_mock in &item_mocks {
+            item_mock.assert_calls(1);
+        }
+    }
+
+    #[test]
+    /// Test filtering items based on the filters; use simple and regex filters
+    pub async fn test_filtering_items() {
+        let pulled_items = [
+            DigestItem {
+                news_title: "Rust is awesome".to_string(),
+                news_url: "https://example.com".to_string(),
+                created_at: 1_700_000_000,
+                id: 1,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Rust is cool".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 2,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Rust is aweful".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 3,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Go is cool".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 4,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Dart is some thing".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 5,
+                ..Default::default()
+            },
+        ];
+        let mut config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![
+                ItemFilter {
+                    value: "cool".to_string(),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    value: "awesome".to_string(),
+                    ..Default::default()
+                },
+            ],
+            smtp: None,
+            telegram: None,
+            rss_sources: None,
+            purge_after_days: 7,
+            blacklisted_domains: vec![],
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+
+        assert_eq!(
+            pulled_items
+                .iter()
+                .filter(|i| fetcher.keep_item(
+                    &i.news_title,
+                    "hackernews",
+                    false
+                ))
+                .count(),
+            3,
+            "Filtering items agains mutiple simple filters failed",
+        );
+
+        config.filters = vec![ItemFilter {
+            value: "some\\b".to_string(),
+            ..Default::default()
+        }];
+
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+
+        assert_eq!(
+            pulled_items
+                .iter()
+                .filter(|i| fetcher.keep_item(
+                    &i.news_title,
+                    "hackernews",
+                    false
+                ))
+                .count(),
+            2,
+            "Filtering items against a regex filter failed",
+        );
+    }
+
+    #[test]
+    /// Exclude filters drop the matching items even if an include filter
+    /// matches them too
+    pub async fn test_include_exclude_filters() {
+        let titles = [
+            "Rust is cool",
+            "Rust crypto wallet",
+            "Crypto is volatile",
+            "Go is cool",
+        ];
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![
+                ItemFilter {
+                    value: "rust".to_string(),
+                    mode: Some("include".to_string()),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    value: "crypto".to_string(),
+                    mode: Some("exclude".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+
+        let kept: Vec<&str> = titles
+            .into_iter()
+            .filter(|title| fetcher.keep_item(title, "hackernews", false))
+            .collect();
+        assert_eq!(kept, vec!["Rust is cool"], "Exclude filter must win");
+
+        let kept: Vec<&str> = titles
+            .into_iter()
+            .filter(|title| fetcher.keep_item(title, "hackernews", true))
+            .collect();
+        assert_eq!(
+            kept,
+            vec!["Go is cool"],
+            "Reverse mode must not bring excluded items back",
+        );
+
+        let config = AppConfig {
+            filters: vec![ItemFilter {
+                value: "crypto".to_string(),
+                mode: Some("exclude".to_string()),
+                ..Default::default()
+            }],
+            ..config
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+        assert_eq!(
+            titles
+                .iter()
+                .filter(|title| fetcher.keep_item(title, "hackernews", false))
+                .count(),
+            2,
+            "Only excluded items must be dropped without include filters",
+        );
+
+        // Without any filters, nothing is kept, or everything in reverse
+        let config = AppConfig {
+            filters: vec![],
+            ..config
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+        assert!(
+            !titles.iter().any(|title| fetcher.keep_item(
+                title,
+                "hackernews",
+                false
+            )),
+            "No filters must keep no items",
+        );
+        assert!(
+            titles.iter().all(|title| fetcher.keep_item(
+                title,
+                "hackernews",
+                true
+            )),
+            "No filters must keep all the items in reverse mode",
+        );
+    }
+
+    #[test]
+    /// The category of the first matching include filter is given to the item;
+    /// none is given in reverse mode
+    pub async fn test_match_category() {
+        let config = AppConfig {
+            db_file: Some(":memory:".to_string()),
+            filters: vec![
+                ItemFilter {
+                    title: Some("Rust".to_string()),
+                    value: "rust,cargo".to_string(),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    title: Some("Languages".to_string()),
+                    value: "rust,python".to_string(),
+                    ..Default::default()
+                },
+                ItemFilter {
+                    value: "golang".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let storage =
+            Storage::new(Storage::establish_connection(&config.get_db_file()));
+        let fetcher = crate::HNFetcher::new(&config, storage);
+
+        let category =
+            |title| fetcher.match_category(title, "hackernews", false);
+        assert_eq!(category("Rust 2.0").as_deref(), Some("Rust"));
+        assert_eq!(category("Python 4").as_deref(), Some("Languages"));
+        assert_eq!(category("Golang news"), None, "Untitled filter matched");
+        assert_eq!(category("Nothing here"), None);
+        assert_eq!(
+            fetcher.match_category("Rust 2.0", "hackernews", true),
+            None
+        );
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    /// Test filtering items based on the filters; use simple and regex filters
+    pub async fn test_filter_config_from_str() {
+        let pulled_items = [
+            DigestItem {
+                news_title: "So You Want to Build Your Own Data Center"
+                    .to_string(),
+                news_url: "https://example.com".to_string(),
+                created_at: 1_700_000_000,
+                id: 1,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Maze Generation: Recursive Division (2011)"
+                    .to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 2,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Swedish Exports of Ball Bearings".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 3,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Obelisks".to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 4,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Bluesky accounts add 10k followers per day"
+                    .to_string(),
+                news_url: "https://example.org".to_string(),
+                created_at: 1_700_000_000,
+                id: 5,
+                ..Default::default()
+            },
+        ];
+        let config = AppConfig::from_str(
+            r#"{
+                "purge_after_days": 720,
+                "db_file": ":memory:",
+                "blacklisted_domains": [
                     "www.businessinsider.com",
                     "www.nytimes.com",
                     "en.wikipedia.org",
@@ -711,7 +2452,11 @@ mod test {
         assert_eq!(
             pulled_items
                 .iter()
-                .filter(|i| fetcher.keep_item(&i.news_title, false))
+                .filter(|i| fetcher.keep_item(
+                    &i.news_title,
+                    "hackernews",
+                    false
+                ))
                 .count(),
             0,
             "Filtering items agains mutiple simple filters failed",
@@ -727,24 +2472,28 @@ mod test {
                 news_url: "https://example.com".to_string(),
                 created_at: 1_700_000_000,
                 id: 1,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Item #2".to_string(),
                 news_url: "https://example.org".to_string(),
                 created_at: 1_700_000_000,
                 id: 2,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Some other name for item #1".to_string(),
                 news_url: "https://example.com".to_string(),
                 created_at: 1_700_000_000,
                 id: 3,
+                ..Default::default()
             },
             DigestItem {
                 news_title: "Item #2 duplicate".to_string(),
                 news_url: "https://example.org".to_string(),
                 created_at: 1_700_000_000,
                 id: 4,
+                ..Default::default()
             },
         ];
 
@@ -762,4 +2511,178 @@ mod test {
             "Deduplication failed"
         );
     }
+
+    /// Test the deduplication of the items which URLs differ only in tracking
+    /// parameters, the trailing slash or the host's case
+    #[test]
+    pub async fn test_deduplication_normalized_urls() {
+        let pulled_items = vec![
+            DigestItem {
+                news_title: "Item #1".to_string(),
+                news_url: "https://example.com/a?utm_source=hn".to_string(),
+                created_at: 1_700_000_000,
+                id: 1,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Item #1 again".to_string(),
+                news_url: "https://Example.com/a/".to_string(),
+                created_at: 1_700_000_000,
+                id: 2,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Item #1 once more".to_string(),
+                news_url: "https://example.com/a?fbclid=abc&utm_medium=rss"
+                    .to_string(),
+                created_at: 1_700_000_000,
+                id: 3,
+                ..Default::default()
+            },
+            DigestItem {
+                news_title: "Item #4".to_string(),
+                news_url: "https://example.com/a?page=2&utm_source=hn"
+                    .to_string(),
+                created_at: 1_700_000_000,
+                id: 4,
+                ..Default::default()
+            },
+        ];
+
+        let deduplicated = deduplicate(&pulled_items);
+        assert_eq!(deduplicated.len(), 2, "Deduplication failed");
+        assert_eq!(deduplicated[0].id, 1, "Deduplication failed");
+        assert_eq!(deduplicated[1].id, 4, "Deduplication failed");
+        let duplicates: Vec<(i64, i64)> = find_duplicates(&pulled_items)
+            .iter()
+            .map(|(kept, dropped)| (kept.id, dropped.id))
+            .collect();
+        assert_eq!(
+            duplicates,
+            vec![(1, 2), (1, 3)],
+            "Each dropped item must come with the kept one",
+        );
+        assert_eq!(
+            deduplicated[0].news_url, "https://example.com/a?utm_source=hn",
+            "The original URL must be kept",
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a?page=2&utm_source=hn"),
+            "https://example.com/a?page=2",
+        );
+        assert_eq!(normalize_url("not a url"), "not a url");
+    }
+
+    /// Test collapsing the items with near-duplicate titles
+    #[test]
+    pub async fn test_deduplication_titles() {
+        let make_item = |id: i64, title: &str| DigestItem {
+            news_title: title.to_string(),
+            news_url: format!("https://example.com/{id}"),
+            id,
+            ..Default::default()
+        };
+        let pulled_items = vec![
+            make_item(1, "Rust 2.0 released"),
+            make_item(2, "Show HN: My new editor"),
+            make_item(3, "Rust 2.0 Released!"),
+            make_item(4, "Rust 2.0 is released"),
+            make_item(5, "Python 4.0 released"),
+        ];
+
+        assert!(
+            (title_similarity("Rust 2.0", "rust, 2.0!") - 1.0).abs() < 1e-9
+        );
+        assert!(title_similarity("", "") < 1e-9);
+
+        let mut seen_titles = vec!["My new editor".to_string()];
+        let deduplicated =
+            deduplicate_titles(&pulled_items, &mut seen_titles, 0.6);
+        let ids: Vec<i64> = deduplicated.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![1, 5], "Near-duplicate titles must be collapsed");
+        assert_eq!(seen_titles.len(), 3, "Kept titles must be remembered");
+
+        let deduplicated =
+            deduplicate_titles(&pulled_items, &mut Vec::new(), 1.0);
+        assert_eq!(
+            deduplicated.len(),
+            4,
+            "Only the same titles must be collapsed at 1.0",
+        );
+    }
+
+    /// Test sorting the digest by the source and ID
+    #[test]
+    pub async fn test_sort_stable() {
+        let make_item = |id: i64, source: Option<&str>| DigestItem {
+            id,
+            source: source.map(ToString::to_string),
+            ..Default::default()
+        };
+        let mut digest = vec![
+            make_item(3, Some("Lobsters")),
+            make_item(2, None),
+            make_item(1, Some("Lobsters")),
+            make_item(5, Some("Habr")),
+            make_item(4, None),
+        ];
+
+        sort_stable(&mut digest);
+        let keys: Vec<(Option<&str>, i64)> = digest
+            .iter()
+            .map(|item| (item.source.as_deref(), item.id))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (None, 2),
+                (None, 4),
+                (Some("Habr"), 5),
+                (Some("Lobsters"), 1),
+                (Some("Lobsters"), 3),
+            ]
+        );
+    }
+
+    /// Test marking and dropping the items which links answer with an error
+    #[test]
+    pub async fn test_check_links() {
+        use httpmock::prelude::*;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(Method::HEAD).path("/alive");
+            then.status(200);
+        });
+        server.mock(|when, then| {
+            when.method(Method::HEAD).path("/gone");
+            then.status(404);
+        });
+        server.mock(|when, then| {
+            when.method(Method::HEAD).path("/no-head");
+            then.status(405);
+        });
+        let make_item = |id: i64, url: String| DigestItem {
+            id,
+            news_url: url,
+            ..Default::default()
+        };
+        let digest = vec![
+            make_item(1, server.url("/alive")),
+            make_item(2, server.url("/gone")),
+            make_item(3, server.url("/no-head")),
+            // never requested, so it would be a dead link otherwise
+            make_item(4, "https://news.ycombinator.com/item?id=4".to_string()),
+        ];
+        let client = reqwest::Client::new();
+
+        let checked = check_links(&client, digest.clone(), 2, false).await;
+        let dead: Vec<bool> =
+            checked.iter().map(|item| item.dead_link).collect();
+        assert_eq!(dead, vec![false, true, false, false]);
+
+        let checked = check_links(&client, digest, 2, true).await;
+        let ids: Vec<i64> = checked.iter().map(|item| item.id).collect();
+        assert_eq!(ids, vec![1, 3, 4], "Dead links must be dropped");
+    }
 }
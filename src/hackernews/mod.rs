@@ -7,6 +7,6 @@ pub mod prelude {
     pub use super::data_types::*;
     pub use super::fetcher::*;
     pub use regex::{Regex, RegexBuilder};
-    pub use serde::Deserialize;
+    pub use serde::{Deserialize, Serialize};
     pub use url::Url;
 }
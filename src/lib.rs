@@ -0,0 +1,39 @@
+#![warn(clippy::all, clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+//! Hacker News and RSS digest: fetch the new items, filter them and send
+//! the digest by email, to Telegram or to the console.
+//!
+//! The `hackernews-rust` binary is a thin wrapper around this library; the
+//! same pieces can be used to embed the digest into other tools:
+//!
+//! ```no_run
+//! use hackernews_rust::{AppConfig, Fetch, HNFetcher, RunOptions};
+//!
+//! # async fn digest() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = AppConfig::from_file("config.json")?;
+//! let stats = HNFetcher::new(&config, config.get_storage())
+//!     .run(&RunOptions::default())
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+mod common;
+pub mod config;
+mod feeds;
+mod hackernews;
+mod schemas;
+pub mod sender;
+
+use crate::hackernews::prelude::*;
+
+pub use common::{
+    prelude::{
+        AgeHistogram, DigestItem, Fetch, Filters, ItemFilter, ItemRollup,
+        RunOptions, RunStats, SkipReason, SourceFailure, Storage,
+    },
+    FetcherType,
+};
+pub use config::AppConfig;
+pub use feeds::prelude::RssFetcher;
+pub use hackernews::prelude::HNFetcher;
+pub use sender::{Delivery, DigestSender, Sender};
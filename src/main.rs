@@ -1,34 +1,377 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 mod arg_parse;
-mod common;
-mod config;
-mod feeds;
-mod hackernews;
-mod schemas;
-mod sender;
-
-use crate::hackernews::prelude::*;
+
 use arg_parse::CmdArgs;
-use common::FetcherType;
-use config::AppConfig;
-use feeds::prelude::RssFetcher;
+use futures::future::join_all;
+use hackernews_rust::{
+    AgeHistogram, AppConfig, Delivery, DigestItem, Fetch, FetcherType, Filters,
+    HNFetcher, RssFetcher, RunOptions, RunStats, Storage,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = &CmdArgs::parse(std::env::args().collect())?;
-    let config = AppConfig::from_file(&args.config.clone())?;
+    let config = load_config(args)?;
+
+    // Remove the RSS source from the config file if requested
+    if let Some(source_name) = &args.remove_source {
+        if args.config == "-" {
+            return Err("--remove-source requires a config file".into());
+        }
+        if !AppConfig::remove_source(&args.config, source_name)? {
+            eprintln!("No RSS source named '{source_name}' in the config");
+            std::process::exit(1);
+        }
+        println!("Removed RSS source '{source_name}'");
+        return Ok(());
+    }
+
+    // Remove the RSS sources that keep failing if requested
+    if args.prune_sources {
+        return prune_sources(args, &config);
+    }
+
+    // Print the config as it was loaded and merged if requested
+    if args.print_config {
+        println!("{}", config.to_redacted_json());
+        return Ok(());
+    }
+
+    // Print the filters as they are compiled if requested
+    if args.list_filters {
+        return list_filters(&config);
+    }
+
+    // Check the title against the filters if requested
+    if let Some(title) = &args.test_filter {
+        test_filter(
+            &config,
+            title,
+            args.source.as_deref().unwrap_or("hackernews"),
+        );
+        return Ok(());
+    }
+
+    // Send a test message to check the sender's settings if requested
+    if args.test_sender {
+        return test_sender(&config).await;
+    }
+
+    // Resend the last digests, e.g. after a delivery failure, if requested
+    if args.resend_last {
+        return resend_last(&config).await;
+    }
+
+    // Retry the digests that failed to be delivered if requested
+    if args.flush_queue {
+        return flush_queue(&config).await;
+    }
+
+    // Send the roll-up of the week's top items if requested
+    if args.weekly {
+        return weekly(&config).await;
+    }
 
     // Run the vacuum operation separately if requested
     if args.vacuum {
-        let num_deleted =
-            Storage::new(Storage::establish_connection(&config.get_db_file()))
-                .vacuum(config.purge_after_days)?;
-        println!("Vacuumed {num_deleted} items");
-        return Ok(());
+        return vacuum(args, &config);
     }
 
+    if args.reprocess && config.raw_cache_dir.is_none() {
+        return Err("--reprocess requires the raw_cache_dir config".into());
+    }
+    let json_output = args.json_output()?;
     // Create a list of fetchers to run
+    let mut fetchers = create_fetchers(args, &config);
+
+    // Start the raw items dump afresh; the fetchers append to it
+    if let Some(file_name) = &args.dump_raw {
+        std::fs::File::create(file_name)?;
+    }
+
+    // Run the fetchers concurrently. Each fetcher owns its own storage
+    // connection, and the storage calls are synchronous, so the fetchers
+    // never write to the database at the same time.
+    let options = &args.run_options();
+    send_deferred(&config, options).await?;
+    tokio::spawn(stop_on_signal(options.clone()));
+    // The console output gets the run's timing around the digests, unless
+    // it is HTML to be saved to a file or the results are JSON
+    let print_timing = config.is_console_output()
+        && !options.count_only
+        && !config.console_html
+        && !json_output;
+    let started_at = chrono::Local::now();
+    let timer = std::time::Instant::now();
+    if print_timing {
+        println!("Run started: {}", started_at.to_rfc2822());
+    }
+    let results = join_all(fetchers.iter_mut().map(|fetcher| async move {
+        match fetcher {
+            FetcherType::HNFetcher(f) => f.run(options).await,
+            FetcherType::RssFetcher(f) => f.run(options).await,
+        }
+    }))
+    .await;
+
+    // A failed source must not hide the stats of the others, but it still
+    // fails the run once they are printed
+    let (stats, errors) = collect_results(results);
+    if print_timing {
+        let total: usize = stats.iter().map(|s| s.fetched).sum();
+        println!(
+            "Run finished: {}, {total} items in {:.2?}",
+            chrono::Local::now().to_rfc2822(),
+            timer.elapsed()
+        );
+    }
+    if json_output {
+        println!("{}", RunStats::to_json(&stats, timer.elapsed(), &errors));
+    } else if options.count_only {
+        println!("{}", RunStats::counts(&stats));
+    } else {
+        println!("{}", RunStats::summary(&stats));
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("; ").into());
+    }
+
+    Ok(())
+}
+
+/// Split the fetchers' results into the stats of the sources and the errors,
+/// both of the failed sources and of the failed fetchers
+fn collect_results(
+    results: Vec<Result<Vec<RunStats>, Box<dyn std::error::Error>>>,
+) -> (Vec<RunStats>, Vec<String>) {
+    let mut stats = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(source_stats) => {
+                errors.extend(source_stats.iter().filter_map(|s| {
+                    s.error.as_ref().map(|e| format!("{}: {e}", s.source))
+                }));
+                stats.extend(source_stats);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    (stats, errors)
+}
+
+/// Load the config from the file or stdin, and apply the command line
+/// overrides to it
+fn load_config(
+    args: &CmdArgs,
+) -> Result<AppConfig, Box<dyn std::error::Error>> {
+    // `-c -` reads the config from stdin, e.g. piped in by a script
+    let mut config = if args.config == "-" {
+        AppConfig::from_reader(std::io::stdin().lock())?
+    } else {
+        AppConfig::from_file(&args.config)?
+    };
+    if args.preview_email {
+        if config.smtp.is_none() {
+            return Err("--preview-email requires the smtp config".into());
+        }
+        config.preview_email = true;
+    }
+    match args.preview_format.as_deref() {
+        None | Some("text") => {}
+        Some("html") => config.console_html = true,
+        Some(format) => {
+            return Err(format!(
+                "--preview-format must be text or html, got {format}"
+            )
+            .into());
+        }
+    }
+    if args.from.is_some() || args.to.is_some() {
+        if config.smtp.is_none() && config.maildir.is_none() {
+            return Err("--from/--to require the smtp or maildir config".into());
+        }
+        config.set_email_addresses(args.from.as_deref(), args.to.as_deref())?;
+    }
+
+    Ok(config)
+}
+
+/// Send a test message with the configured sender; exit with an error status
+/// if it fails
+async fn test_sender(
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let test_item = DigestItem {
+        news_title: "Test message from hackernews_digest".to_string(),
+        news_url: "https://news.ycombinator.com/".to_string(),
+        ..Default::default()
+    };
+    // A lenient sender would report the failure as a success, and the test
+    // item must never reach the failed queue to be flushed to the user
+    if let Err(e) = config
+        .get_sender()
+        .lenient(false)
+        .quiet_hours(None)
+        .failed_queue(None)
+        .send_digest("Test", &[test_item], None)
+        .await
+    {
+        eprintln!("Sender test failed: {e}");
+        std::process::exit(1);
+    }
+    println!("Sender test succeeded");
+
+    Ok(())
+}
+
+/// Remove the RSS sources which failed to fetch `prune_after_failures` runs
+/// in a row from the config file, and forget their failures
+fn prune_sources(
+    args: &CmdArgs,
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.config == "-" {
+        return Err("--prune-sources requires a config file".into());
+    }
+
+    let mut storage = config.get_storage();
+    let mut num_removed = 0;
+    for failure in
+        storage.get_failing_sources(config.get_prune_after_failures())?
+    {
+        // The sources of `rss_sources_dir` are not in the config file
+        if !AppConfig::remove_source(&args.config, &failure.source)? {
+            eprintln!(
+                "RSS source '{}' failed {} runs in a row, but is not in the config file",
+                failure.source, failure.failures
+            );
+            continue;
+        }
+        storage.reset_source_failures(&failure.source)?;
+        println!(
+            "Removed RSS source '{}' after {} failed runs: {}",
+            failure.source, failure.failures, failure.last_error
+        );
+        num_removed += 1;
+    }
+    println!("Pruned {num_removed} sources");
+
+    Ok(())
+}
+
+/// Send the last digest built for each source again, as it was stored by
+/// the last run that digested any items of the source
+async fn resend_last(
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let digests = config.get_storage().get_last_digests()?;
+    if digests.is_empty() {
+        println!("No digest to resend");
+        return Ok(());
+    }
+
+    // Resending is asked for explicitly, so the quiet hours don't apply; a
+    // failed digest stays the last one to resend, so it isn't queued
+    let sender = config.get_sender().quiet_hours(None).failed_queue(None);
+    for (name, digest) in &digests {
+        sender
+            .send_digest(name, digest, config.max_digest_items)
+            .await?;
+    }
+    println!("Resent {} digests", digests.len());
+
+    Ok(())
+}
+
+/// Send the digests kept in the failed queue again; the sent ones leave the
+/// queue, and a failure keeps the rest for the next try
+async fn flush_queue(
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(failed_queue) = config.get_failed_queue() else {
+        return Err("--flush-queue requires the failed_queue config".into());
+    };
+
+    // A failure must fail the flush, rather than be queued once again
+    let sender = config
+        .get_sender()
+        .lenient(false)
+        .quiet_hours(None)
+        .failed_queue(None);
+    let num_sent = failed_queue.flush(&sender, config.max_digest_items).await?;
+    println!("Sent {num_sent} queued digests");
+
+    Ok(())
+}
+
+/// Days of the stored items the weekly roll-up covers
+const WEEKLY_DAYS: i64 = 7;
+
+/// Send the top scored items stored in the last week as a digest of their
+/// own, from the database only
+async fn weekly(config: &AppConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let since = chrono::Utc::now().timestamp() - WEEKLY_DAYS * 86_400;
+    let limit = i64::try_from(config.get_weekly_top_n())?;
+    let items = config.get_storage().get_top_items(since, limit)?;
+    if items.is_empty() {
+        println!("No items stored in the last {WEEKLY_DAYS} days");
+        return Ok(());
+    }
+
+    // The roll-up is asked for explicitly, so the quiet hours don't apply
+    // and a failure fails the command; it can be sent again from the
+    // database, so it isn't queued
+    let delivery = config
+        .get_sender()
+        .lenient(false)
+        .quiet_hours(None)
+        .failed_queue(None)
+        .send_digest("Weekly top HackerNews", &items, None)
+        .await?;
+    if delivery == Delivery::Sent {
+        println!("Sent the top {} items of the week", items.len());
+    } else {
+        println!("The top {} items of the week not sent", items.len());
+    }
+
+    Ok(())
+}
+
+/// Send the digests deferred by the quiet hours once they are over. Each
+/// digest is forgotten as soon as it is sent, so a failure leaves the rest
+/// for the next run
+async fn send_deferred(
+    config: &AppConfig,
+    options: &RunOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(quiet_hours) = &config.quiet_hours else {
+        return Ok(());
+    };
+    if options.count_only
+        || options.no_store
+        || quiet_hours.contains(chrono::Utc::now())
+    {
+        return Ok(());
+    }
+
+    // A failed digest stays deferred for the next run, so it isn't queued
+    let mut storage = config.get_storage();
+    let sender = config.get_sender().failed_queue(None);
+    for (name, digest) in storage.get_deferred_digests()? {
+        sender
+            .send_digest(&name, &digest, config.max_digest_items)
+            .await?;
+        storage.clear_deferred_digest(&name)?;
+    }
+
+    Ok(())
+}
+
+/// Create the list of fetchers to run
+fn create_fetchers(args: &CmdArgs, config: &AppConfig) -> Vec<FetcherType> {
     let mut fetchers = vec![];
     // HNFetcher is used only if feeds_only is not set to true
     {
@@ -38,36 +381,195 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
 
         if !skip_hackernews {
-            let storage = Storage::new(Storage::establish_connection(
-                &config.get_db_file(),
-            ));
+            let storage = config.get_storage();
             fetchers
-                .push(FetcherType::HNFetcher(HNFetcher::new(&config, storage)));
+                .push(FetcherType::HNFetcher(HNFetcher::new(config, storage)));
         }
     }
-    // RssFetcher is optional, if the config has rss_sources then add it to the fetchers
+    // RssFetcher is optional, if the config has rss_sources then add it to the fetchers;
+    // only the Hacker News items are kept to reprocess
     if let Some(sources) = &config.rss_sources {
-        if !sources.is_empty() {
-            let storage = Storage::new(Storage::establish_connection(
-                &config.get_db_file(),
-            ));
+        if !sources.is_empty() && !args.reprocess {
+            let storage = config.get_storage();
             fetchers.push(FetcherType::RssFetcher(RssFetcher::new(
-                &config, storage,
+                config, storage,
             )));
         }
     }
 
-    // Run the fetchers if there are any
-    for fetcher in &mut fetchers {
-        let (fetched_items, fetch_type) = match fetcher {
-            FetcherType::HNFetcher(f) => {
-                (f.run(args.reverse).await?, "HackerNews")
-            }
-            FetcherType::RssFetcher(f) => (f.run(args.reverse).await?, "RSS"),
+    fetchers
+}
+
+/// Print the filter the title matches and whether the item would be kept,
+/// in the normal and the reverse mode; the filters scoped to other sources
+/// than the given one are left out
+fn test_filter(config: &AppConfig, title: &str, source: &str) {
+    // The fetcher is only used for its filters; its in-memory storage keeps
+    // the database untouched
+    let fetcher = HNFetcher::new(
+        config,
+        Storage::new(Storage::establish_connection(":memory:")),
+    );
+    let yes_no = |keep| if keep { "yes" } else { "no" };
+
+    println!("Title: {title}");
+    println!(
+        "Matched filter: {}",
+        fetcher
+            .matched_filter(title, None, source)
+            .as_deref()
+            .unwrap_or("none")
+    );
+    match fetcher.match_category(title, source, false) {
+        Some(category) => println!("Category: {category}"),
+        None => println!("Category: none"),
+    }
+    println!("Kept: {}", yes_no(fetcher.keep_item(title, source, false)));
+    println!(
+        "Kept with --reverse: {}",
+        yes_no(fetcher.keep_item(title, source, true))
+    );
+}
+
+fn list_filters(config: &AppConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let (compiled, errors) = Filters::try_compile(&config.filters);
+    for filter in &compiled {
+        let sources = if filter.sources.is_empty() {
+            String::new()
+        } else {
+            format!(" (only {})", filter.sources.join(", "))
         };
+        println!(
+            "[{}]{}{sources} {}",
+            filter.category.as_deref().unwrap_or("-"),
+            if filter.exclude { " (exclude)" } else { "" },
+            filter.regex.as_str(),
+        );
+    }
+    for e in &errors {
+        eprintln!(
+            "[{}] invalid pattern '{}': {}",
+            e.category.as_deref().unwrap_or("-"),
+            e.pattern,
+            e.error,
+        );
+    }
+    if !errors.is_empty() {
+        return Err(
+            format!("{} filters failed to compile", errors.len()).into()
+        );
+    }
+
+    Ok(())
+}
+
+/// Roll up the old items if configured, then remove the items older than
+/// the retention period, and those over the per-source limit, from the
+/// database
+fn vacuum(
+    args: &CmdArgs,
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // --max-age overrides the configured retention for this run only
+    let max_age = args.max_age.unwrap_or(config.purge_after_days);
+    if max_age < 0 {
+        return Err("--max-age must be 0 (never purge) or more".into());
+    }
+    let mut storage = config.get_storage();
+    if args.dry_run || args.histogram {
+        return vacuum_dry_run(args, config, &mut storage, max_age);
+    }
+    // The old items are counted before the age purge can delete them
+    if let Some(rollup_after_days) = config.rollup_after_days {
+        let num_rolled_up = storage.rollup(rollup_after_days)?;
+        println!("Rolled up {num_rolled_up} items");
+    }
+    // Either limit deletes an item: the age first, then the count
+    let mut num_deleted = storage.vacuum(max_age)?;
+    if let Some(max_records) = config.get_max_records_per_source() {
+        num_deleted += storage.trim_sources(max_records)?;
+    }
+    println!("Vacuumed {num_deleted} items");
+
+    Ok(())
+}
+
+/// Print how many items the vacuum would roll up and remove, and with
+/// `--histogram` the item counts per source and age, as text or JSON. The
+/// database is only read.
+fn vacuum_dry_run(
+    args: &CmdArgs,
+    config: &AppConfig,
+    storage: &mut Storage,
+    max_age: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let num_rolled_up = match config.rollup_after_days {
+        Some(days) => storage.count_older_than(days)?,
+        None => 0,
+    };
+    let num_deleted = storage.count_vacuum(
+        max_age,
+        config.get_max_records_per_source(),
+        config.rollup_after_days,
+    )?;
+    let histogram = if args.histogram {
+        Some(storage.age_histogram()?)
+    } else {
+        None
+    };
 
-        println!("Fetched new {fetch_type} items: {fetched_items}");
+    if args.json_output()? {
+        println!(
+            "{}",
+            serde_json::json!({
+                "rolled_up": num_rolled_up,
+                "vacuumed": num_deleted,
+                "histogram": histogram,
+            })
+        );
+        return Ok(());
+    }
+    if config.rollup_after_days.is_some() {
+        println!("Would roll up {num_rolled_up} items");
+    }
+    println!("Would vacuum {num_deleted} items");
+    if let Some(histogram) = histogram {
+        println!("{}", AgeHistogram::table(&histogram));
     }
 
     Ok(())
 }
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM and ask the fetchers to stop, so the
+/// items pulled so far are stored and sent. A second signal exits right away.
+async fn stop_on_signal(options: RunOptions) {
+    for _ in 0..2 {
+        if wait_for_signal().await.is_err() {
+            // No signal handling on this platform
+            return;
+        }
+        if options.is_stopping() {
+            std::process::exit(130);
+        }
+        eprintln!(
+            "Stopping after the current item; press Ctrl-C again to abort"
+        );
+        options.stop();
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = sigterm.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
@@ -1,5 +1,8 @@
 mod schema;
 
 pub mod prelude {
-    pub use super::schema::rss_items;
+    pub use super::schema::{
+        deferred_digests, item_rollups, last_digests, pending_items, rss_items,
+        run_markers, sent_urls, source_failures,
+    };
 }
@@ -1,9 +1,92 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    deferred_digests (digest, position) {
+        digest -> VarChar,
+        position -> Integer,
+        id -> BigInt,
+        news_title -> VarChar,
+        news_url -> VarChar,
+        created_at -> BigInt,
+        snippet -> Nullable<VarChar>,
+        label -> Nullable<VarChar>,
+        category -> Nullable<VarChar>,
+    }
+}
+
+diesel::table! {
+    item_rollups (source, month) {
+        source -> VarChar,
+        month -> VarChar,
+        count -> BigInt,
+    }
+}
+
+diesel::table! {
+    last_digests (digest, position) {
+        digest -> VarChar,
+        position -> Integer,
+        id -> BigInt,
+        news_title -> VarChar,
+        news_url -> VarChar,
+        created_at -> BigInt,
+        snippet -> Nullable<VarChar>,
+        label -> Nullable<VarChar>,
+        category -> Nullable<VarChar>,
+    }
+}
+
+diesel::table! {
+    pending_items (id, source) {
+        id -> BigInt,
+        source -> VarChar,
+    }
+}
+
+diesel::table! {
+    run_markers (source) {
+        source -> VarChar,
+        last_run_at -> BigInt,
+    }
+}
+
 diesel::table! {
     rss_items (id, source) {
         id -> BigInt,
         source -> VarChar,
         created_at -> BigInt,
+        domain -> Nullable<VarChar>,
+        run_id -> VarChar,
+        title -> Nullable<VarChar>,
+        url -> Nullable<VarChar>,
+        score -> Nullable<BigInt>,
     }
 }
+
+diesel::table! {
+    sent_urls (url) {
+        url -> VarChar,
+        source -> VarChar,
+        sent_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    source_failures (source) {
+        source -> VarChar,
+        failures -> BigInt,
+        last_error -> VarChar,
+        failed_at -> BigInt,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    deferred_digests,
+    item_rollups,
+    last_digests,
+    pending_items,
+    rss_items,
+    run_markers,
+    sent_urls,
+    source_failures,
+);
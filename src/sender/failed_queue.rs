@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use super::Sender;
+use crate::DigestItem;
+
+/// A digest none of the senders could deliver, kept to retry it later
+#[derive(Clone, Deserialize, Serialize)]
+pub struct QueuedDigest {
+    /// What the digest was sent with, the source name
+    pub subject: String,
+    pub items: Vec<DigestItem>,
+}
+
+/// A JSON file of the undelivered digests, oldest first. It holds at most
+/// `max_len` digests: the oldest ones are dropped to make room for the new.
+#[derive(Clone)]
+pub struct FailedQueue {
+    path: String,
+    max_len: usize,
+}
+
+impl FailedQueue {
+    #[must_use]
+    pub fn new(path: &str, max_len: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            max_len,
+        }
+    }
+
+    /// Read the queued digests; a missing file is an empty queue
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read or is not a valid queue
+    pub fn load(
+        &self,
+    ) -> Result<Vec<QueuedDigest>, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(contents) => {
+                Ok(serde_json::from_str(&contents).map_err(|e| {
+                    format!("Invalid failed queue {}: {e}", self.path)
+                })?)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Vec::new())
+            }
+            Err(e) => {
+                Err(format!("Cannot read failed queue {}: {e}", self.path)
+                    .into())
+            }
+        }
+    }
+
+    /// Write the digests to the file, or remove it if there are none
+    fn save(
+        &self,
+        digests: &[QueuedDigest],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if digests.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(e.into())
+                }
+                _ => Ok(()),
+            };
+        }
+        std::fs::write(&self.path, serde_json::to_string(digests)?)?;
+
+        Ok(())
+    }
+
+    /// Add the digest to the end of the queue, dropping the oldest ones over
+    /// the max length
+    ///
+    /// # Errors
+    ///
+    /// Fails if the queue can't be read or written
+    pub fn push(
+        &self,
+        subject: &str,
+        items: &[DigestItem],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut digests = self.load()?;
+        digests.push(QueuedDigest {
+            subject: subject.to_string(),
+            items: items.to_vec(),
+        });
+        let num_dropped = digests.len().saturating_sub(self.max_len);
+        if num_dropped > 0 {
+            eprintln!("Failed queue is full: dropping {num_dropped} digests");
+        }
+        self.save(&digests[num_dropped..])
+    }
+
+    /// Send the queued digests again, oldest first, keeping at most
+    /// `max_items` items in each. The sent ones are removed from the queue;
+    /// the first failure stops the retries and keeps the rest. Return the
+    /// number of digests sent.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the queue can't be read or written, or a digest can't be
+    /// delivered
+    pub async fn flush(
+        &self,
+        sender: &Sender,
+        max_items: Option<usize>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let digests = self.load()?;
+        for (n, digest) in digests.iter().enumerate() {
+            if let Err(e) = sender
+                .send_digest(&digest.subject, &digest.items, max_items)
+                .await
+            {
+                self.save(&digests[n..])?;
+                return Err(e);
+            }
+        }
+        self.save(&[])?;
+
+        Ok(digests.len())
+    }
+}
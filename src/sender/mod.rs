@@ -1,39 +1,213 @@
-use crate::config::{SmtpConfig, TelegramConfig};
+use crate::config::{
+    EmailConfig, MaildirConfig, QuietHours, SmtpConfig, TelegramConfig,
+};
 use crate::DigestItem;
+use futures::future::LocalBoxFuture;
 use lettre::message::{MultiPart, SinglePart};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{SmtpTransport, Transport};
+use std::io::{BufRead, IsTerminal, Write};
 use teloxide::utils::markdown;
 
-pub enum Sender {
-    Dummy(DummySender),
-    Smtp(SmtpSender),
-    Telegram(TelegramSender),
+mod failed_queue;
+
+pub use failed_queue::{FailedQueue, QueuedDigest};
+
+/// The senders to deliver the digest with; `AppConfig::get_sender` registers
+/// the configured ones
+#[derive(Default)]
+pub struct Sender {
+    senders: Vec<Box<dyn DigestSender>>,
+    /// Only log the delivery failures instead of returning them
+    lenient: bool,
+    /// Hold the digests back in this daily window
+    quiet_hours: Option<QuietHours>,
+    /// Keep the digests none of the senders delivered here
+    failed_queue: Option<FailedQueue>,
+    /// Ask before sending a digest of more items than this
+    confirm_threshold: Option<usize>,
+    /// Skip the digests over the threshold when there is no one to ask,
+    /// instead of sending them
+    skip_unconfirmed: bool,
+}
+
+/// What became of a digest handed to the sender
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    /// Handed to the registered senders
+    Sent,
+    /// Dropped for the quiet hours
+    Skipped,
+    /// Held back for the quiet hours, to be sent by a later run
+    Deferred,
 }
 
 impl Sender {
+    /// Only log the delivery failures, so they don't fail the run
+    #[must_use]
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Hold the digests back in the given daily window; `None` sends them
+    /// at any time
+    #[must_use]
+    pub fn quiet_hours(mut self, quiet_hours: Option<QuietHours>) -> Self {
+        self.quiet_hours = quiet_hours;
+        self
+    }
+
+    /// Keep the digests none of the senders delivered in the queue, to send
+    /// them with `--flush-queue`; `None` drops them
+    #[must_use]
+    pub fn failed_queue(mut self, failed_queue: Option<FailedQueue>) -> Self {
+        self.failed_queue = failed_queue;
+        self
+    }
+
+    /// Ask on the terminal before sending a digest of more than `threshold`
+    /// items; `None` or 0 never asks. Without a terminal, the digest is sent
+    /// or, with `skip_unconfirmed`, skipped
+    #[must_use]
+    pub fn confirm(
+        mut self,
+        threshold: Option<usize>,
+        skip_unconfirmed: bool,
+    ) -> Self {
+        self.confirm_threshold = threshold.filter(|&threshold| threshold > 0);
+        self.skip_unconfirmed = skip_unconfirmed;
+        self
+    }
+
+    /// Check if the digest of `count` items may be sent: it's within the
+    /// confirmation threshold, or the answer read from the `terminal` is
+    /// yes. Without a terminal, it's sent unless `skip_unconfirmed` is set
+    fn is_confirmed(
+        &self,
+        subj: &str,
+        count: usize,
+        terminal: Option<&mut dyn BufRead>,
+    ) -> bool {
+        if self
+            .confirm_threshold
+            .is_none_or(|threshold| count <= threshold)
+        {
+            return true;
+        }
+        let Some(terminal) = terminal else {
+            return !self.skip_unconfirmed;
+        };
+
+        eprint!("Send {count} items of the {subj} digest? [y/N] ");
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        terminal.read_line(&mut answer).is_ok()
+            && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    }
+
+    /// Add a sender to deliver the digest with
+    #[must_use]
+    pub fn register(mut self, sender: Box<dyn DigestSender>) -> Self {
+        self.senders.push(sender);
+        self
+    }
+
+    /// Send the digest with each registered sender, keeping at most
+    /// `max_items` items in it; the number of items left out is passed on
+    /// to the senders as an overflow summary. In the quiet hours nothing is
+    /// sent, and the caller is told whether to keep the digest for later.
+    /// A digest over the confirmation threshold is skipped unless confirmed.
+    /// A digest none of the senders delivered goes to the failed queue.
+    ///
+    /// # Errors
+    ///
+    /// The first delivery failure is returned, unless the sender is lenient
     pub async fn send_digest(
         &self,
         subj: &str,
         digest: &[DigestItem],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        match self {
-            Sender::Dummy(sender) => sender.send_digest(subj, digest).await,
-            Sender::Smtp(sender) => sender.send_digest(subj, digest).await,
-            Sender::Telegram(sender) => sender.send_digest(subj, digest).await,
+        max_items: Option<usize>,
+    ) -> Result<Delivery, Box<dyn std::error::Error>> {
+        if let Some(quiet_hours) = &self.quiet_hours {
+            if quiet_hours.contains(chrono::Utc::now()) {
+                if quiet_hours.defers() {
+                    eprintln!("Quiet hours: deferring the {subj} digest");
+                    return Ok(Delivery::Deferred);
+                }
+                eprintln!("Quiet hours: skipping the {subj} digest");
+                return Ok(Delivery::Skipped);
+            }
+        }
+
+        let full_digest = digest;
+        let (digest, overflow) = truncate_digest(digest, max_items);
+        let stdin = std::io::stdin();
+        let mut terminal = stdin.is_terminal().then(|| stdin.lock());
+        if !self.is_confirmed(
+            subj,
+            digest.len(),
+            terminal.as_mut().map(|lock| lock as &mut dyn BufRead),
+        ) {
+            eprintln!("Not confirmed: skipping the {subj} digest");
+            return Ok(Delivery::Skipped);
+        }
+        drop(terminal);
+
+        // Every sender is tried, so one failed channel doesn't block the others
+        let mut delivered = false;
+        let mut error = None;
+        for sender in &self.senders {
+            match sender.send_digest(subj, digest, overflow).await {
+                Ok(()) => delivered = true,
+                Err(e) => {
+                    eprintln!("Could not deliver the {subj} digest: {e}");
+                    error = error.or(Some(e));
+                }
+            }
+        }
+
+        let Some(error) = error else {
+            return Ok(Delivery::Sent);
+        };
+        if let Some(failed_queue) = self.failed_queue.as_ref() {
+            if !delivered {
+                if let Err(e) = failed_queue.push(subj, full_digest) {
+                    eprintln!("Could not queue the {subj} digest: {e}");
+                }
+            }
         }
+        if self.lenient {
+            return Ok(Delivery::Sent);
+        }
+
+        Err(error)
     }
 }
 
 pub trait DigestSender {
-    async fn send_digest(
-        &self,
-        subj: &str,
-        digest: &[DigestItem],
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    fn send_digest<'a>(
+        &'a self,
+        subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>>;
 }
 
-pub struct DummySender {}
+/// Line width of the plain-text body derived from the HTML one
+const TEXT_WIDTH: usize = 80;
+
+/// Prints the digest to the console
+pub struct DummySender {
+    /// Print the HTML digest instead of the plain-text one
+    pub html: bool,
+}
+
+/// Prints the email that the SMTP sender would send, without connecting
+/// to the server
+pub struct PreviewSender {
+    config: SmtpConfig,
+}
 
 pub struct SmtpSender {
     config: SmtpConfig,
@@ -43,7 +217,22 @@ pub struct TelegramSender {
     config: TelegramConfig,
 }
 
+/// Stores the email that the SMTP sender would send into a local Maildir
+pub struct MaildirSender {
+    config: MaildirConfig,
+}
+
+impl PreviewSender {
+    #[must_use]
+    pub fn new(config: &SmtpConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+}
+
 impl SmtpSender {
+    #[must_use]
     pub fn new(config: &SmtpConfig) -> Self {
         Self {
             config: config.clone(),
@@ -51,6 +240,15 @@ impl SmtpSender {
     }
 }
 
+impl MaildirSender {
+    #[must_use]
+    pub fn new(config: &MaildirConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+}
+
 impl TelegramSender {
     #[must_use]
     pub fn new(config: &TelegramConfig) -> Self {
@@ -61,152 +259,513 @@ impl TelegramSender {
 }
 
 impl DigestSender for SmtpSender {
-    async fn send_digest(
-        &self,
-        subj: &str,
-        digest: &[DigestItem],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let text_body = digest_to_text(digest);
-        let html_body = digest_to_html(digest);
-        let email = lettre::Message::builder()
-            .from(self.config.from.parse()?)
-            .to(self.config.to.parse()?)
-            .subject(format!("{subj} {}", self.config.subject))
-            .multipart(
-                MultiPart::mixed().multipart(
-                    MultiPart::alternative()
-                        .singlepart(SinglePart::plain(text_body))
-                        .multipart(
-                            MultiPart::related()
-                                .singlepart(SinglePart::html(html_body)),
-                        ),
-                ),
-            )?;
+    fn send_digest<'a>(
+        &'a self,
+        subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let email =
+                build_email(&self.config.email, subj, digest, overflow)?;
 
-        let creds = Credentials::new(
-            self.config.username.clone(),
-            self.config.password.clone(),
-        );
-        let mailer = SmtpTransport::relay(&self.config.host)?
-            .credentials(creds)
-            .build();
+            // An unauthenticated relay may reject an AUTH with empty
+            // credentials, so there is none without a username
+            let mut transport = SmtpTransport::relay(&self.config.host)?;
+            if let Some(username) =
+                self.config.username.as_deref().filter(|u| !u.is_empty())
+            {
+                transport = transport.credentials(Credentials::new(
+                    username.to_string(),
+                    self.config.password.clone().unwrap_or_default(),
+                ));
+            }
+            let mailer = transport.build();
 
-        match mailer.send(&email) {
-            Ok(_) => return Ok(()),
-            Err(e) => eprintln!("Could not send email: {e:?}"),
-        }
+            if let Err(e) = mailer.send(&email) {
+                eprintln!("Could not send email: {e:?}");
+                return Err(e.into());
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 }
 
-impl DigestSender for DummySender {
-    async fn send_digest(
-        &self,
-        _subj: &str,
-        digest: &[DigestItem],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut body = String::from("Hi!\n\n");
-        let format_item = |item: &DigestItem| {
-            format!(
-                "* {title} - {url}\n",
-                url = item.news_url,
-                title = item.news_title
-            )
-        };
+impl DigestSender for PreviewSender {
+    fn send_digest<'a>(
+        &'a self,
+        subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let email =
+                build_email(&self.config.email, subj, digest, overflow)?;
+            println!("{}", String::from_utf8_lossy(&email.formatted()));
 
-        for item in digest {
-            body.push_str(format_item(item).as_str());
-        }
+            Ok(())
+        })
+    }
+}
+
+impl DigestSender for MaildirSender {
+    fn send_digest<'a>(
+        &'a self,
+        subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let email =
+                build_email(&self.config.email, subj, digest, overflow)?;
+            store_in_maildir(&self.config.path, &email.formatted())?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Deliver the message into the Maildir: write it into `tmp` first and move
+/// it into `new` then, so mail readers never see a partially written file
+fn store_in_maildir(
+    path: &str,
+    message: &[u8],
+) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static DELIVERIES: AtomicUsize = AtomicUsize::new(0);
+
+    let maildir = std::path::Path::new(path);
+    for dir in ["tmp", "new", "cur"] {
+        std::fs::create_dir_all(maildir.join(dir))?;
+    }
+
+    // The unique name is `time.MusecPpidQn.host`, as described at
+    // https://cr.yp.to/proto/maildir.html
+    let now =
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    let host = std::env::var("HOSTNAME")
+        .unwrap_or_else(|_| "localhost".to_string())
+        .replace('/', "\\057")
+        .replace(':', "\\072");
+    let file_name = format!(
+        "{}.M{}P{}Q{}.{host}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        DELIVERIES.fetch_add(1, Ordering::Relaxed),
+    );
+
+    let tmp_file = maildir.join("tmp").join(&file_name);
+    let new_file = maildir.join("new").join(&file_name);
+    std::fs::write(&tmp_file, message)?;
+    std::fs::rename(&tmp_file, &new_file)?;
 
-        body.push_str(format!("\nGenerated: {}", formatted_now()).as_str());
+    Ok(new_file)
+}
 
-        println!("{body}");
+impl DigestSender for DummySender {
+    fn send_digest<'a>(
+        &'a self,
+        _subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            if self.html {
+                println!("{}", digest_to_html(digest, overflow));
+            } else {
+                println!("{}", digest_to_text(digest, overflow));
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 }
 
 impl DigestSender for TelegramSender {
-    async fn send_digest(
-        &self,
-        _subj: &str,
-        digest: &[DigestItem],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        use teloxide::prelude::*;
+    fn send_digest<'a>(
+        &'a self,
+        _subj: &'a str,
+        digest: &'a [DigestItem],
+        overflow: usize,
+    ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            use teloxide::prelude::*;
 
-        let bot = Bot::new(&self.config.token);
-        let format_item = |item: &DigestItem| {
-            format!(
-                "*[{}]({})*",
-                markdown::escape(&item.news_title),
-                markdown::escape_link_url(&item.news_url),
-            )
-        };
+            let bot = Bot::new(&self.config.token);
+            let format =
+                TelegramFormat::from_config(self.config.parse_mode.as_deref());
+            let mut messages: Vec<String> =
+                digest.iter().map(|item| format.item(item)).collect();
+            if overflow > 0 {
+                messages.push(format.text(&overflow_summary(overflow)));
+            }
 
-        for news_item in digest {
-            match bot
-                .send_message(
-                    self.config.chat_id.clone(),
-                    format_item(news_item),
-                )
-                .parse_mode(teloxide::types::ParseMode::MarkdownV2)
-                .send()
-                .await
-            {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Could not send message: {e:?}");
-                    return Err(Box::new(e));
+            // A failure in one chat must not keep the others from getting the digest
+            let mut errors = Vec::new();
+            let send_interval = self.config.get_send_interval();
+            let mut is_first = true;
+            for chat_id in self.config.chat_id.to_vec() {
+                for message in &messages {
+                    // Space out the messages to stay within the Telegram limits
+                    if !is_first {
+                        tokio::time::sleep(send_interval).await;
+                    }
+                    is_first = false;
+
+                    let mut request =
+                        bot.send_message(chat_id.clone(), message);
+                    if let Some(parse_mode) = format.parse_mode() {
+                        request = request.parse_mode(parse_mode);
+                    }
+                    if let Err(e) = request.send().await {
+                        eprintln!("Could not send message to {chat_id}: {e:?}");
+                        errors.push(format!("{chat_id}: {e}"));
+                        break;
+                    }
                 }
             }
+
+            if !errors.is_empty() {
+                return Err(format!(
+                    "Could not send the digest to some chats: {}",
+                    errors.join("; ")
+                )
+                .into());
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Build the digest email: an HTML one with a plain-text alternative part,
+/// or a plain-text one if the config says so
+///
+/// # Errors
+///
+/// Fails if the from/to addresses are invalid
+pub fn build_email(
+    config: &EmailConfig,
+    subj: &str,
+    digest: &[DigestItem],
+    overflow: usize,
+) -> Result<lettre::Message, Box<dyn std::error::Error>> {
+    let html_body = digest_to_html(digest, overflow);
+    let text_body = if config.derive_text_from_html.unwrap_or(false) {
+        html2text::from_read(html_body.as_bytes(), TEXT_WIDTH)?
+    } else {
+        digest_to_text(digest, overflow)
+    };
+    let builder = lettre::Message::builder()
+        .from(config.from.parse()?)
+        .to(config.to.parse()?)
+        .subject(email_subject(config, subj, digest.len() + overflow));
+    let email = if config.plaintext_only.unwrap_or(false) {
+        builder.singlepart(SinglePart::plain(text_body))?
+    } else {
+        builder.multipart(
+            MultiPart::mixed().multipart(
+                MultiPart::alternative()
+                    .singlepart(SinglePart::plain(text_body))
+                    .multipart(
+                        MultiPart::related()
+                            .singlepart(SinglePart::html(html_body)),
+                    ),
+            ),
+        )?
+    };
+
+    Ok(email)
+}
+
+/// Build the email subject: the source name and the configured subject, or
+/// the source's own one, between the prefix and the suffix with their
+/// placeholders filled in
+fn email_subject(config: &EmailConfig, subj: &str, count: usize) -> String {
+    let fill = |text: Option<&str>| {
+        text.unwrap_or_default()
+            .replace("{source}", subj)
+            .replace("{count}", &count.to_string())
+    };
+    let subject = config
+        .source_subjects
+        .get(subj)
+        .map_or_else(|| config.subject.clone(), |subject| fill(Some(subject)));
+    format!(
+        "{}{subj} {subject}{}",
+        fill(config.subject_prefix.as_deref()),
+        fill(config.subject_suffix.as_deref())
+    )
+}
+
+/// Keep at most `max_items` items of the digest. Return the kept items and
+/// the number of items left out
+#[must_use]
+pub fn truncate_digest(
+    digest: &[DigestItem],
+    max_items: Option<usize>,
+) -> (&[DigestItem], usize) {
+    match max_items {
+        Some(max) if digest.len() > max => {
+            (digest.get(..max).unwrap_or(digest), digest.len() - max)
+        }
+        _ => (digest, 0),
+    }
+}
+
+/// Summary line for the items left out of a truncated digest
+fn overflow_summary(overflow: usize) -> String {
+    format!("...and {overflow} more (see storage)")
+}
+
+/// How the Telegram messages are formatted, by the `parse_mode` config
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TelegramFormat {
+    MarkdownV2,
+    Html,
+    /// Plain text, with the URL after the title
+    Plain,
+}
+
+impl TelegramFormat {
+    /// Get the format by the `parse_mode` config value; `AppConfig` rejects
+    /// the unknown ones, so they fall back to the default `MarkdownV2`
+    fn from_config(parse_mode: Option<&str>) -> Self {
+        match parse_mode {
+            Some("HTML") => Self::Html,
+            Some("none") => Self::Plain,
+            _ => Self::MarkdownV2,
+        }
+    }
+
+    /// Get the Telegram parse mode to send the messages with, if any
+    fn parse_mode(self) -> Option<teloxide::types::ParseMode> {
+        match self {
+            Self::MarkdownV2 => Some(teloxide::types::ParseMode::MarkdownV2),
+            Self::Html => Some(teloxide::types::ParseMode::Html),
+            Self::Plain => None,
+        }
+    }
+
+    /// Format the digest item as a message: a bold link, or the title and
+    /// the URL in plain text
+    fn item(self, item: &DigestItem) -> String {
+        let label = self.text(&item_labels(item));
+        match self {
+            Self::MarkdownV2 => format!(
+                "{label}*[{}]({})*",
+                markdown::escape(&item.news_title),
+                markdown::escape_link_url(&item.news_url),
+            ),
+            Self::Html => format!(
+                r#"{label}<b><a href="{}">{}</a></b>"#,
+                html_escape::encode_double_quoted_attribute(&item.news_url),
+                html_escape::encode_text(&item.news_title),
+            ),
+            Self::Plain => {
+                format!("{label}{}\n{}", item.news_title, item.news_url)
+            }
         }
+    }
 
-        Ok(())
+    /// Escape the text for the parse mode
+    fn text(self, text: &str) -> String {
+        match self {
+            Self::MarkdownV2 => markdown::escape(text),
+            Self::Html => html_escape::encode_text(text).to_string(),
+            Self::Plain => text.to_string(),
+        }
     }
 }
 
+/// Labels to put before the item's title: the source, the filter category
+/// that kept the item and the dead link mark, e.g. `[Habr] [Rust] `; empty
+/// if there are none
+fn item_labels(item: &DigestItem) -> String {
+    let mut labels = String::new();
+    for label in [&item.source, &item.category].into_iter().flatten() {
+        labels.push_str(format!("[{label}] ").as_str());
+    }
+    if item.dead_link {
+        labels.push_str("[dead link] ");
+    }
+    labels
+}
+
+/// Get the estimated reading time to put after the item's title, if any
+fn read_time_note(item: &DigestItem) -> String {
+    item.read_time
+        .map(|minutes| format!(" (~{minutes} min read)"))
+        .unwrap_or_default()
+}
+
+/// Section of the digest items without a category
+const NO_CATEGORY: &str = "Other";
+
+/// Group the digest items by their category, in the order the categories
+/// first appear; the items without a category go last
+fn group_by_category(digest: &[DigestItem]) -> Vec<(String, Vec<&DigestItem>)> {
+    let mut groups: Vec<(String, Vec<&DigestItem>)> = Vec::new();
+    let mut uncategorized = Vec::new();
+    for item in digest {
+        let Some(category) = &item.category else {
+            uncategorized.push(item);
+            continue;
+        };
+        match groups.iter_mut().find(|(name, _)| name == category) {
+            Some((_, items)) => items.push(item),
+            None => groups.push((category.clone(), vec![item])),
+        }
+    }
+    if !uncategorized.is_empty() {
+        groups.push((NO_CATEGORY.to_string(), uncategorized));
+    }
+
+    groups
+}
+
+/// Get the HTML anchor of the category section, e.g. `cat-c--` for `C++`
+fn category_anchor(category: &str) -> String {
+    let slug: String = category
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("cat-{slug}")
+}
+
 /// Convert a digest to an HTML string
-pub fn digest_to_html(digest: &[DigestItem]) -> String {
+#[must_use]
+pub fn digest_to_html(digest: &[DigestItem], overflow: usize) -> String {
     let mut body = String::from(
-        "<html><head>HackerNews Digest</head><body><p>Hi!</p><div><ul>",
+        "<html><head>HackerNews Digest</head><body><p>Hi!</p><div>",
     );
     let format_item = |item: &DigestItem| {
+        let snippet = match &item.snippet {
+            Some(snippet) => format!(
+                "<br/><small>{}</small>",
+                html_escape::encode_text(snippet)
+            ),
+            None => String::new(),
+        };
+        let comments = if item.comments.is_empty() {
+            String::new()
+        } else {
+            let comments: Vec<String> = item
+                .comments
+                .iter()
+                .map(|comment| {
+                    format!(
+                        "<li><small>{}</small></li>",
+                        html_escape::encode_text(comment)
+                    )
+                })
+                .collect();
+            format!("<ul>{}</ul>", comments.concat())
+        };
+        let label = html_escape::encode_text(&item_labels(item)).to_string();
         format!(
-            "<li><a href=\"{url}\">{title}</a></li>",
-            url = item.news_url,
-            title = item.news_title
+            "<li>{label}<a href=\"{url}\">{title}</a>{read_time}{snippet}{comments}</li>",
+            url = html_escape::encode_double_quoted_attribute(&item.news_url),
+            title = html_escape::encode_text(&item.news_title),
+            read_time = read_time_note(item),
         )
     };
 
-    digest
-        .iter()
-        .for_each(|item| body.push_str(format_item(item).as_str()));
+    let groups = group_by_category(digest);
+    if groups.len() > 1 {
+        // A table of contents linking to the category sections
+        body.push_str("<ul>");
+        for (category, items) in &groups {
+            body.push_str(
+                format!(
+                    "<li><a href=\"#{}\">{} ({})</a></li>",
+                    category_anchor(category),
+                    html_escape::encode_text(category),
+                    items.len()
+                )
+                .as_str(),
+            );
+        }
+        body.push_str("</ul>");
+    }
+    for (category, items) in &groups {
+        if groups.len() > 1 {
+            body.push_str(
+                format!(
+                    "<h3 id=\"{}\">{}</h3>",
+                    category_anchor(category),
+                    html_escape::encode_text(category)
+                )
+                .as_str(),
+            );
+        }
+        body.push_str("<ul>");
+        for item in items {
+            body.push_str(format_item(item).as_str());
+        }
+        body.push_str("</ul>");
+    }
+
+    if overflow > 0 {
+        body.push_str(
+            format!("<p>{}</p>", overflow_summary(overflow)).as_str(),
+        );
+    }
 
     body.push_str(
-        format!(
-            "</ul></div><p>Generated: {}</p></body></html>",
-            formatted_now(),
-        )
-        .as_str(),
+        format!("</div><p>Generated: {}</p></body></html>", formatted_now())
+            .as_str(),
     );
     body
 }
 
 /// Convert a digest to a plain text string
-pub fn digest_to_text(digest: &[DigestItem]) -> String {
+#[must_use]
+pub fn digest_to_text(digest: &[DigestItem], overflow: usize) -> String {
     let mut body = String::from("Hi!\n\n");
     let format_item = |item: &DigestItem| {
+        let snippet = match &item.snippet {
+            Some(snippet) => format!("    {snippet}\n"),
+            None => String::new(),
+        };
+        let comments: Vec<String> = item
+            .comments
+            .iter()
+            .map(|comment| format!("    > {comment}\n"))
+            .collect();
+        let label = item_labels(item);
         format!(
-            "* {title} - {url}\n",
+            "* {label}{title}{read_time} - {url}\n{snippet}{}",
+            comments.concat(),
             url = item.news_url,
-            title = item.news_title
+            title = item.news_title,
+            read_time = read_time_note(item),
         )
     };
 
-    digest
-        .iter()
-        .for_each(|item| body.push_str(format_item(item).as_str()));
+    let groups = group_by_category(digest);
+    if groups.len() > 1 {
+        let mut contents = Vec::new();
+        for (category, items) in &groups {
+            contents.push(format!("{category} ({})", items.len()));
+        }
+        body.push_str(format!("{}\n", contents.join(", ")).as_str());
+    }
+    for (category, items) in &groups {
+        if groups.len() > 1 {
+            body.push_str(format!("\n{category}\n").as_str());
+        }
+        for item in items {
+            body.push_str(format_item(item).as_str());
+        }
+    }
+
+    if overflow > 0 {
+        body.push_str(format!("{}\n", overflow_summary(overflow)).as_str());
+    }
 
     body.push_str(format!("\nGenerated: {}", formatted_now()).as_str());
     body
@@ -215,3 +774,547 @@ pub fn digest_to_text(digest: &[DigestItem]) -> String {
 fn formatted_now() -> String {
     chrono::Local::now().to_rfc2822()
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        build_email, digest_to_html, digest_to_text, email_subject,
+        truncate_digest, Delivery, DigestSender, FailedQueue, MaildirSender,
+        Sender, TelegramFormat,
+    };
+    use crate::{
+        config::{EmailConfig, MaildirConfig, QuietHours},
+        DigestItem,
+    };
+    use futures::future::LocalBoxFuture;
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    /// Records the number of items and the overflow of each digest sent
+    struct RecordingSender {
+        sent: Rc<RefCell<Vec<(usize, usize)>>>,
+    }
+
+    impl DigestSender for RecordingSender {
+        fn send_digest<'a>(
+            &'a self,
+            _subj: &'a str,
+            digest: &'a [DigestItem],
+            overflow: usize,
+        ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>>
+        {
+            Box::pin(async move {
+                self.sent.borrow_mut().push((digest.len(), overflow));
+                Ok(())
+            })
+        }
+    }
+
+    fn make_email_config() -> EmailConfig {
+        EmailConfig {
+            from: "Digest <digest@example.com>".to_string(),
+            to: "to@example.com".to_string(),
+            subject: "Digest".to_string(),
+            source_subjects: HashMap::new(),
+            subject_prefix: None,
+            subject_suffix: None,
+            plaintext_only: None,
+            derive_text_from_html: None,
+        }
+    }
+
+    fn make_digest(len: i64) -> Vec<DigestItem> {
+        (1..=len)
+            .map(|id| DigestItem {
+                id,
+                news_title: format!("Item #{id}"),
+                news_url: format!("https://example.com/{id}"),
+                created_at: 1_700_000_000,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_registered_senders() {
+        let first = Rc::new(RefCell::new(Vec::new()));
+        let second = Rc::new(RefCell::new(Vec::new()));
+        let sender = Sender::default()
+            .register(Box::new(RecordingSender {
+                sent: first.clone(),
+            }))
+            .register(Box::new(RecordingSender {
+                sent: second.clone(),
+            }));
+
+        sender
+            .send_digest("Test", &make_digest(5), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(*first.borrow(), vec![(2, 3)], "First sender not used");
+        assert_eq!(*second.borrow(), vec![(2, 3)], "Second sender not used");
+    }
+
+    /// Fails every delivery
+    struct FailingSender {}
+
+    impl DigestSender for FailingSender {
+        fn send_digest<'a>(
+            &'a self,
+            _subj: &'a str,
+            _digest: &'a [DigestItem],
+            _overflow: usize,
+        ) -> LocalBoxFuture<'a, Result<(), Box<dyn std::error::Error>>>
+        {
+            Box::pin(async { Err("delivery failed".into()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery_failures() {
+        let digest = make_digest(1);
+        let sent = Rc::new(RefCell::new(Vec::new()));
+
+        let sender = Sender::default()
+            .register(Box::new(FailingSender {}))
+            .register(Box::new(RecordingSender { sent: sent.clone() }));
+        assert!(
+            sender.send_digest("Test", &digest, None).await.is_err(),
+            "Delivery failures must be returned",
+        );
+        assert_eq!(sent.borrow().len(), 1, "Other senders must still run");
+
+        let sender = sender.lenient(true);
+        assert!(
+            sender.send_digest("Test", &digest, None).await.is_ok(),
+            "Lenient sender must only log the failures",
+        );
+        assert_eq!(sent.borrow().len(), 2, "Other senders must still run");
+    }
+
+    #[tokio::test]
+    async fn test_failed_queue() {
+        let path = std::env::temp_dir()
+            .join(format!("hnd-failed-queue-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let failed_queue = FailedQueue::new(&path, 2);
+        let failing = Sender::default()
+            .register(Box::new(FailingSender {}))
+            .failed_queue(Some(failed_queue.clone()));
+
+        for (subject, len) in [("First", 1), ("Second", 2), ("Third", 3)] {
+            let digest = make_digest(len);
+            let result = failing.send_digest(subject, &digest, None).await;
+            assert!(result.is_err(), "The failure must be returned");
+        }
+        let subjects: Vec<String> = failed_queue
+            .load()
+            .unwrap()
+            .into_iter()
+            .map(|digest| digest.subject)
+            .collect();
+        assert_eq!(subjects, ["Second", "Third"], "The oldest must be dropped");
+
+        // A failed flush keeps the queue as it is
+        assert!(failed_queue
+            .flush(&failing.failed_queue(None), None)
+            .await
+            .is_err());
+        assert_eq!(failed_queue.load().unwrap().len(), 2);
+
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sender = Sender::default()
+            .register(Box::new(RecordingSender { sent: sent.clone() }));
+        assert_eq!(failed_queue.flush(&sender, None).await.unwrap(), 2);
+        assert_eq!(*sent.borrow(), vec![(2, 0), (3, 0)]);
+        assert!(
+            !std::path::Path::new(&path).exists(),
+            "The flushed queue must be removed",
+        );
+    }
+
+    #[test]
+    /// A queued digest comes back as it was, to render the same on a flush
+    fn test_failed_queue_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("hnd-queue-round-trip-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let failed_queue = FailedQueue::new(&path, 1);
+        let digest = vec![DigestItem {
+            id: 1,
+            news_title: "Rust news".to_string(),
+            news_url: "https://example.com/1".to_string(),
+            created_at: 1_700_000_000,
+            snippet: Some("All about Rust".to_string()),
+            source: Some("Example".to_string()),
+            category: Some("Rust".to_string()),
+            dead_link: true,
+            comments: vec!["Great read".to_string()],
+            read_time: Some(3),
+            score: Some(42),
+            body: Some("All about Rust, in detail".to_string()),
+        }];
+
+        failed_queue.push("Example", &digest).unwrap();
+        let queued = failed_queue.load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let items = &queued[0].items;
+        assert_eq!(items[0].source.as_deref(), Some("Example"));
+        assert!(items[0].dead_link);
+        assert_eq!(items[0].comments, ["Great read"]);
+        assert_eq!(items[0].read_time, Some(3));
+        assert_eq!(items[0].score, Some(42));
+        assert_eq!(items[0].body, digest[0].body);
+        assert_eq!(
+            digest_to_html(items, 0),
+            digest_to_html(&digest, 0),
+            "The queued digest must render the same",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quiet_hours() {
+        let now = chrono::Utc::now();
+        let quiet_hours = |from_hours: i64, mode: &str| QuietHours {
+            start: (now + chrono::Duration::hours(from_hours))
+                .format("%H:%M")
+                .to_string(),
+            end: (now + chrono::Duration::hours(from_hours + 2))
+                .format("%H:%M")
+                .to_string(),
+            tz: Some("UTC".to_string()),
+            mode: Some(mode.to_string()),
+        };
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let sender = |quiet_hours| {
+            Sender::default()
+                .register(Box::new(RecordingSender { sent: sent.clone() }))
+                .quiet_hours(Some(quiet_hours))
+        };
+        let digest = make_digest(1);
+
+        let delivery = sender(quiet_hours(-1, "skip"))
+            .send_digest("Test", &digest, None)
+            .await
+            .unwrap();
+        assert_eq!(delivery, Delivery::Skipped);
+        let delivery = sender(quiet_hours(-1, "defer"))
+            .send_digest("Test", &digest, None)
+            .await
+            .unwrap();
+        assert_eq!(delivery, Delivery::Deferred);
+        assert!(sent.borrow().is_empty(), "Nothing must be sent when quiet");
+
+        let delivery = sender(quiet_hours(1, "defer"))
+            .send_digest("Test", &digest, None)
+            .await
+            .unwrap();
+        assert_eq!(delivery, Delivery::Sent);
+        assert_eq!(sent.borrow().len(), 1, "Must send outside the window");
+    }
+
+    #[test]
+    fn test_comments_rendering() {
+        let digest = vec![DigestItem {
+            comments: vec!["First <b>".to_string(), "Second".to_string()],
+            ..make_digest(1).remove(0)
+        }];
+
+        assert!(
+            digest_to_html(&digest, 0).contains(
+                "<ul><li><small>First &lt;b&gt;</small></li>\
+                <li><small>Second</small></li></ul></li>"
+            ),
+            "Comments must be nested under the HTML item",
+        );
+        assert!(
+            digest_to_text(&digest, 0).contains(
+                "* Item #1 - https://example.com/1\n    > First <b>\n    > Second\n"
+            ),
+            "Comments must be indented under the text item",
+        );
+    }
+
+    #[test]
+    fn test_read_time_rendering() {
+        let mut digest = make_digest(2);
+        digest[0].read_time = Some(3);
+
+        let html = digest_to_html(&digest, 0);
+        assert!(
+            html.contains("Item #1</a> (~3 min read)"),
+            "Read time must follow the HTML title",
+        );
+        assert!(!html.contains("Item #2</a> (~"), "No read time, no note");
+        let text = digest_to_text(&digest, 0);
+        assert!(
+            text.contains("* Item #1 (~3 min read) - https://example.com/1\n"),
+            "Read time must follow the text title",
+        );
+        assert!(text.contains("* Item #2 - https://example.com/2\n"));
+    }
+
+    #[test]
+    fn test_truncate_digest() {
+        let digest = make_digest(5);
+
+        let (kept, overflow) = truncate_digest(&digest, Some(2));
+        assert_eq!(kept.len(), 2, "Truncated digest size is wrong");
+        assert_eq!(overflow, 3, "Overflow count is wrong");
+
+        let (kept, overflow) = truncate_digest(&digest, Some(5));
+        assert_eq!(kept.len(), 5, "Digest at the limit must be kept");
+        assert_eq!(overflow, 0, "Digest at the limit has no overflow");
+
+        let (kept, overflow) = truncate_digest(&digest, None);
+        assert_eq!(kept.len(), 5, "Unlimited digest must be kept");
+        assert_eq!(overflow, 0, "Unlimited digest has no overflow");
+    }
+
+    #[test]
+    fn test_overflow_summary_rendering() {
+        let digest = make_digest(5);
+        let (kept, overflow) = truncate_digest(&digest, Some(2));
+
+        let text = digest_to_text(kept, overflow);
+        assert!(text.contains("...and 3 more (see storage)"));
+        assert!(!text.contains("Item #3"));
+
+        let html = digest_to_html(kept, overflow);
+        assert!(html.contains("<p>...and 3 more (see storage)</p>"));
+        assert!(!html.contains("Item #3"));
+
+        let text = digest_to_text(&digest, 0);
+        assert!(!text.contains("more (see storage)"));
+    }
+
+    #[test]
+    fn test_html_escaping() {
+        let digest = [DigestItem {
+            id: 1,
+            news_title: r#"Rust & "unsafe" <T>"#.to_string(),
+            news_url: r#"https://example.com/?a=1&b="2""#.to_string(),
+            created_at: 1_700_000_000,
+            ..Default::default()
+        }];
+
+        let html = digest_to_html(&digest, 0);
+        assert!(
+            html.contains(
+                r#"<a href="https://example.com/?a=1&amp;b=&quot;2&quot;">"#
+            ),
+            "URL must be attribute-escaped: {html}",
+        );
+        assert!(
+            html.contains(r#">Rust &amp; "unsafe" &lt;T&gt;</a>"#),
+            "Title must be HTML-escaped: {html}",
+        );
+        assert!(!html.contains("<T>"), "Title tags must not leak: {html}");
+    }
+
+    #[test]
+    fn test_source_labels() {
+        let mut digest = make_digest(2);
+        digest[0].source = Some("Habr <En>".to_string());
+
+        let text = digest_to_text(&digest, 0);
+        assert!(text.contains("* [Habr <En>] Item #1 - "), "{text}");
+        assert!(text.contains("* Item #2 - "), "{text}");
+
+        let html = digest_to_html(&digest, 0);
+        assert!(
+            html.contains("<li>[Habr &lt;En&gt;] <a href="),
+            "Label must be rendered and escaped: {html}",
+        );
+        assert!(html.contains("<li><a href="), "{html}");
+    }
+
+    #[test]
+    fn test_derive_text_from_html() {
+        let mut digest = make_digest(1);
+        digest[0].news_title = "Rust &amp; friends".to_string();
+        let mut config = make_email_config();
+        config.plaintext_only = Some(true);
+        config.derive_text_from_html = Some(true);
+
+        let email = build_email(&config, "HackerNews", &digest, 0).unwrap();
+        let raw = String::from_utf8_lossy(&email.formatted()).to_string();
+        assert!(raw.contains("Rust &amp; friends"), "{raw}");
+        assert!(raw.contains("https://example.com/1"), "{raw}");
+        assert!(!raw.contains("<li>"), "HTML tags must be converted: {raw}");
+    }
+
+    #[test]
+    fn test_category_labels() {
+        let mut digest = make_digest(2);
+        digest[0].source = Some("Habr".to_string());
+        digest[0].category = Some("Rust".to_string());
+        digest[1].category = Some("Go".to_string());
+
+        let text = digest_to_text(&digest, 0);
+        assert!(text.contains("* [Habr] [Rust] Item #1 - "), "{text}");
+        assert!(text.contains("* [Go] Item #2 - "), "{text}");
+
+        let html = digest_to_html(&digest, 0);
+        assert!(html.contains("<li>[Habr] [Rust] <a href="), "{html}");
+        assert!(html.contains("<li>[Go] <a href="), "{html}");
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let mut digest = make_digest(4);
+        digest[0].category = Some("C++".to_string());
+        digest[1].category = Some("Rust".to_string());
+        digest[2].category = Some("C++".to_string());
+
+        let html = digest_to_html(&digest, 0);
+        assert!(
+            html.contains(r##"<li><a href="#cat-c--">C++ (2)</a></li>"##),
+            "{html}",
+        );
+        assert!(html.contains(r##"<a href="#cat-other">Other (1)</a>"##));
+        assert!(html.contains(r#"<h3 id="cat-rust">Rust</h3>"#), "{html}");
+        let (first, third) =
+            (html.find("Item #1").unwrap(), html.find("Item #3").unwrap());
+        assert!(
+            first < third && third < html.find("Item #2").unwrap(),
+            "Items must be grouped by category: {html}",
+        );
+
+        let text = digest_to_text(&digest, 0);
+        assert!(text.contains("C++ (2), Rust (1), Other (1)\n"), "{text}");
+
+        for item in &mut digest {
+            item.category = Some("Rust".to_string());
+        }
+        let html = digest_to_html(&digest, 0);
+        assert!(!html.contains("<h3"), "One category needs no TOC: {html}");
+    }
+
+    #[test]
+    fn test_build_email() {
+        let digest = make_digest(2);
+        let mut config = make_email_config();
+
+        let email = build_email(&config, "HackerNews", &digest, 0).unwrap();
+        let raw = String::from_utf8_lossy(&email.formatted()).to_string();
+        assert!(raw.contains("Subject: HackerNews Digest"), "{raw}");
+        assert!(raw.contains("To: to@example.com"), "{raw}");
+        assert!(raw.contains("Content-Type: text/plain"), "{raw}");
+        assert!(raw.contains("Content-Type: text/html"), "{raw}");
+
+        config.plaintext_only = Some(true);
+        let email = build_email(&config, "HackerNews", &digest, 0).unwrap();
+        let raw = String::from_utf8_lossy(&email.formatted()).to_string();
+        assert!(raw.contains("Content-Type: text/plain"), "{raw}");
+        assert!(!raw.contains("Content-Type: text/html"), "{raw}");
+        assert!(!raw.contains("multipart"), "{raw}");
+    }
+
+    #[test]
+    fn test_subject_prefix_suffix() {
+        let digest = make_digest(2);
+        let mut config = make_email_config();
+        config.subject_prefix = Some("📰 ".to_string());
+        config.subject_suffix = Some(" — {count} items".to_string());
+
+        assert_eq!(
+            email_subject(&config, "HN", digest.len() + 10),
+            "📰 HN Digest — 12 items",
+        );
+        let email = build_email(&config, "HN", &digest, 10).unwrap();
+        let raw = String::from_utf8_lossy(&email.formatted()).to_string();
+        let subject = raw
+            .lines()
+            .find(|line| line.starts_with("Subject: "))
+            .unwrap();
+        assert!(subject.is_ascii(), "Subject must be MIME-encoded: {raw}");
+        assert!(subject.contains("=?utf-8?b?"), "{subject}");
+
+        config
+            .source_subjects
+            .insert("Habr".to_string(), "Articles ({count})".to_string());
+        assert_eq!(
+            email_subject(&config, "Habr", 3),
+            "📰 Habr Articles (3) — 3 items",
+        );
+        assert_eq!(email_subject(&config, "HN", 3), "📰 HN Digest — 3 items",);
+    }
+
+    #[tokio::test]
+    async fn test_maildir_sender() {
+        let path = std::env::temp_dir()
+            .join(format!("hnd-maildir-{}", std::process::id()));
+        let sender = MaildirSender::new(&MaildirConfig {
+            path: path.to_string_lossy().to_string(),
+            email: make_email_config(),
+        });
+        let digest = make_digest(2);
+
+        sender.send_digest("HackerNews", &digest, 0).await.unwrap();
+        sender.send_digest("Habr", &digest, 0).await.unwrap();
+
+        let mut files: Vec<String> = std::fs::read_dir(path.join("new"))
+            .unwrap()
+            .map(|entry| {
+                std::fs::read_to_string(entry.unwrap().path()).unwrap()
+            })
+            .collect();
+        files.sort();
+        let tmp_files = std::fs::read_dir(path.join("tmp")).unwrap().count();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(files.len(), 2, "Each digest must get its own file");
+        assert_eq!(tmp_files, 0, "The files must be moved out of tmp");
+        assert!(files[0].contains("Subject: Habr Digest"), "{}", files[0]);
+        assert!(files[1].contains("Subject: HackerNews Digest"));
+        assert!(files[1].contains("https://example.com/2"), "{}", files[1]);
+    }
+
+    #[test]
+    fn test_telegram_formats() {
+        let item = DigestItem {
+            news_title: "Rust <2024> & C++".to_string(),
+            news_url: "https://example.com/?a=1&b=2".to_string(),
+            category: Some("Rust".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            TelegramFormat::from_config(None).item(&item),
+            r"\[Rust\] *[Rust <2024\> & C\+\+](https://example.com/?a=1&b=2)*",
+        );
+        assert_eq!(
+            TelegramFormat::from_config(Some("HTML")).item(&item),
+            r#"[Rust] <b><a href="https://example.com/?a=1&amp;b=2">Rust &lt;2024&gt; &amp; C++</a></b>"#,
+        );
+        assert_eq!(
+            TelegramFormat::from_config(Some("none")).item(&item),
+            "[Rust] Rust <2024> & C++\nhttps://example.com/?a=1&b=2",
+        );
+        assert_eq!(
+            TelegramFormat::from_config(Some("none")).parse_mode(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_confirm_threshold() {
+        let sender = Sender::default().confirm(Some(2), false);
+        assert!(sender.is_confirmed("HN", 2, None));
+        assert!(sender.is_confirmed("HN", 3, None));
+        assert!(sender.is_confirmed("HN", 3, Some(&mut "y\n".as_bytes())));
+        assert!(sender.is_confirmed("HN", 3, Some(&mut "Yes\n".as_bytes())));
+        assert!(!sender.is_confirmed("HN", 3, Some(&mut "n\n".as_bytes())));
+        assert!(!sender.is_confirmed("HN", 3, Some(&mut "".as_bytes())));
+
+        let sender = Sender::default().confirm(Some(2), true);
+        assert!(sender.is_confirmed("HN", 2, None));
+        assert!(!sender.is_confirmed("HN", 3, None));
+
+        let sender = Sender::default().confirm(Some(0), true);
+        assert!(sender.is_confirmed("HN", 100, None));
+    }
+}